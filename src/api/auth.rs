@@ -0,0 +1,107 @@
+use ring::constant_time::verify_slices_are_equal;
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+use super::error::{ApiError, ErrorCode};
+
+/// What a token lets its holder do. Admin can do anything read can.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    Read,   // the GET endpoints
+    Admin,  // anything that changes the node
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Token {
+    #[serde(default)]
+    pub name: String,   // who holds the token, for the access log
+    pub token: String,
+    pub scope: Scope,
+}
+
+/// Who may use the API. With no tokens, anyone can use every endpoint, as before.
+/// With tokens, a request needs an `Authorization: Bearer <token>` header with a
+/// token of the endpoint's scope, except that read endpoints stay open if `public_read` is set.
+///
+/// A tokens file looks like
+/// `{"public_read": true, "tokens": [{"name": "ops", "token": "...", "scope": "admin"}]}`
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Auth {
+    pub public_read: bool,
+    pub tokens: Vec<Token>,
+}
+
+impl Auth {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let json = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let auth: Self = serde_json::from_str(&json).map_err(|e| e.to_string())?;
+        if let Some(token) = auth.tokens.iter().find(|token| token.token.is_empty()) {
+            return Err(format!("token {:?} is empty", token.name));
+        }
+        Ok(auth)
+    }
+
+    pub fn add_token(&mut self, name: &str, token: &str, scope: Scope) {
+        self.tokens.push(Token { name: name.to_string(), token: token.to_string(), scope });
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.tokens.is_empty()
+    }
+
+    /// Check the `Authorization` header of a request against the scope its endpoint needs,
+    /// and return the name of the token holder, if any
+    pub fn check(&self, authorization: Option<&str>, needed: Scope) -> Result<Option<String>, ApiError> {
+        if !self.enabled() || (needed == Scope::Read && self.public_read && authorization.is_none()) {
+            return Ok(None);
+        }
+        let presented = authorization
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim)
+            .ok_or_else(|| ApiError::new(ErrorCode::Unauthorized, "missing bearer token"))?;
+        // compare with every token, so the time taken doesn't tell which one was close
+        let mut found = None;
+        for token in &self.tokens {
+            if verify_slices_are_equal(token.token.as_bytes(), presented.as_bytes()).is_ok() {
+                found = Some(token);
+            }
+        }
+        let token = found.ok_or_else(|| ApiError::new(ErrorCode::Unauthorized, "invalid token"))?;
+        if token.scope < needed {
+            return Err(ApiError::new(ErrorCode::Forbidden, "this endpoint needs an admin token"));
+        }
+        Ok(Some(token.name.clone()))
+    }
+}
+
+// DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. BEFORE TEST
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_scopes() {
+        let mut auth = Auth::default();
+        assert_eq!(auth.check(None, Scope::Admin).unwrap(), None);
+
+        auth.add_token("ops", "admin-secret", Scope::Admin);
+        auth.add_token("dashboard", "read-secret", Scope::Read);
+        let code = |result: Result<Option<String>, ApiError>| result.unwrap_err().code;
+        assert_eq!(code(auth.check(None, Scope::Read)), ErrorCode::Unauthorized);
+        assert_eq!(code(auth.check(Some("Bearer nope"), Scope::Read)), ErrorCode::Unauthorized);
+        assert_eq!(code(auth.check(Some("read-secret"), Scope::Read)), ErrorCode::Unauthorized);
+        assert_eq!(code(auth.check(Some("Bearer read-secret"), Scope::Admin)), ErrorCode::Forbidden);
+        assert_eq!(auth.check(Some("Bearer read-secret"), Scope::Read).unwrap().as_deref(), Some("dashboard"));
+        assert_eq!(auth.check(Some("Bearer admin-secret"), Scope::Read).unwrap().as_deref(), Some("ops"));
+        assert_eq!(auth.check(Some("Bearer admin-secret"), Scope::Admin).unwrap().as_deref(), Some("ops"));
+
+        auth.public_read = true;
+        assert_eq!(auth.check(None, Scope::Read).unwrap(), None);
+        assert_eq!(code(auth.check(None, Scope::Admin)), ErrorCode::Unauthorized);
+    }
+}
+
+// DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. AFTER TEST
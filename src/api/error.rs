@@ -0,0 +1,116 @@
+use serde::Serialize;
+use std::fmt::Display;
+use crate::types::mempool::Rejection;
+use crate::wallet::WalletError;
+
+/// An error returned by an endpoint, sent as an `ErrorResponse` with a matching HTTP status
+#[derive(Debug, Clone)]
+pub struct ApiError {
+    pub code: ErrorCode,
+    pub message: String,
+    pub details: Option<serde_json::Value>,
+}
+
+/// What kind of error happened, which decides the HTTP status
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    BadRequest,         // 400: a malformed parameter or body
+    Unauthorized,       // 401: no token, or an unknown one, when the API needs one
+    Forbidden,          // 403: a read token used for an admin endpoint
+    NotFound,           // 404: no such endpoint, block, transaction or key
+    MethodNotAllowed,   // 405: the endpoint exists, but not for this method
+    PayloadTooLarge,    // 413: a request body over the API's limit
+    UnsupportedMediaType,   // 415: a POST without a JSON content type or an admin token
+    Rejected,           // 422: a well-formed transaction that can't be accepted
+    Internal,           // 500: the node failed, e.g. to write the keystore
+    BadGateway,         // 502: the node could not reach a peer
+    Unavailable,        // 503: the feature is not enabled on this node
+}
+
+/// The body of every error response
+#[derive(Serialize, Debug, Clone)]
+pub struct ErrorResponse {
+    pub success: bool,  // always false
+    pub error: ErrorBody,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ErrorBody {
+    pub code: ErrorCode,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<serde_json::Value>,
+}
+
+impl ErrorCode {
+    pub fn status(&self) -> u16 {
+        match self {
+            ErrorCode::BadRequest => 400,
+            ErrorCode::Unauthorized => 401,
+            ErrorCode::Forbidden => 403,
+            ErrorCode::NotFound => 404,
+            ErrorCode::MethodNotAllowed => 405,
+            ErrorCode::PayloadTooLarge => 413,
+            ErrorCode::UnsupportedMediaType => 415,
+            ErrorCode::Rejected => 422,
+            ErrorCode::Internal => 500,
+            ErrorCode::BadGateway => 502,
+            ErrorCode::Unavailable => 503,
+        }
+    }
+}
+
+impl ApiError {
+    pub fn new(code: ErrorCode, message: impl Display) -> Self {
+        Self { code, message: message.to_string(), details: None }
+    }
+
+    pub fn bad_request(message: impl Display) -> Self {
+        Self::new(ErrorCode::BadRequest, message)
+    }
+
+    pub fn not_found(message: impl Display) -> Self {
+        Self::new(ErrorCode::NotFound, message)
+    }
+
+    pub fn status(&self) -> u16 {
+        self.code.status()
+    }
+
+    pub fn to_response(&self) -> ErrorResponse {
+        ErrorResponse {
+            success: false,
+            error: ErrorBody {
+                code: self.code,
+                message: self.message.clone(),
+                details: self.details.clone(),
+            },
+        }
+    }
+}
+
+impl From<Rejection> for ApiError {
+    fn from(rejection: Rejection) -> Self {
+        let code = match rejection {
+            Rejection::Full { .. } => ErrorCode::Unavailable,
+            _ => ErrorCode::Rejected,
+        };
+        Self {
+            code,
+            message: rejection.to_string(),
+            details: serde_json::to_value(&rejection).ok(),
+        }
+    }
+}
+
+impl From<WalletError> for ApiError {
+    fn from(e: WalletError) -> Self {
+        let code = match e {
+            WalletError::UnknownAddress(_) => ErrorCode::NotFound,
+            WalletError::UnknownAccount(_) | WalletError::InsufficientBalance { .. } => ErrorCode::Rejected,
+            _ => ErrorCode::Internal,
+        };
+        Self::new(code, e)
+    }
+}
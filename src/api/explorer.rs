@@ -0,0 +1,564 @@
+use std::collections::{HashMap, HashSet};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::types::{address::Address, hash::H256};
+use super::error::ApiError;
+use super::handlers::{ApiRequest, ApiResult, Context};
+use super::view::{TransactionStatus, TransactionView};
+
+// A block explorer for people with a browser, as HTML pages under /explorer that link to
+// each other. The pages are made here from the same views as the JSON endpoints, with
+// the styles inline and no scripts, so they work without anything but the node.
+// When the node has tokens, browsers can only see them with `api.public_read` set.
+
+// how many blocks the latest blocks page lists
+const LATEST_BLOCKS: u64 = 25;
+
+// how many heights the fork tree shows, by default and at most
+const FORK_TREE_DEPTH: u64 = 30;
+const MAX_FORK_TREE_DEPTH: u64 = 200;
+
+// the spacing of the fork tree, in pixels
+const TREE_STEP_X: u64 = 36;
+const TREE_STEP_Y: u64 = 28;
+const TREE_MARGIN: u64 = 30;
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 0; color: #222; }
+nav { background: #243447; padding: 0.6em 1.2em; display: flex; gap: 1.2em; align-items: center; }
+nav a { color: #fff; text-decoration: none; }
+nav form { margin-left: auto; }
+nav input { width: 28em; padding: 0.3em; }
+main { padding: 0.5em 1.2em 2em; }
+table { border-collapse: collapse; margin: 0.5em 0 1.5em; }
+th, td { border-bottom: 1px solid #ddd; padding: 0.3em 0.8em; text-align: left; }
+th { background: #f3f5f7; }
+.hash { font-family: monospace; }
+.muted { color: #888; }
+.stale { color: #b35c00; }
+svg a circle { fill: #2f6fb3; }
+svg a.stale circle { fill: #e0a050; }
+svg a.tip circle { fill: #2a9d4a; }
+svg line { stroke: #999; stroke-width: 2; }
+svg text { font-size: 10px; fill: #666; }
+";
+
+/// Replace the characters that mean something in HTML
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&#39;")
+}
+
+/// A whole page, with the navigation bar and search box above `body`
+pub fn page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{} - Explorer</title><style>{}</style></head>\
+<body><nav><a href=\"/explorer\">Latest blocks</a><a href=\"/explorer/mempool\">Mempool</a>\
+<a href=\"/explorer/peers\">Peers</a><a href=\"/explorer/forks\">Fork tree</a>\
+<form action=\"/explorer/search\"><input name=\"q\" placeholder=\"Block hash or height, transaction hash or address\"></form></nav>\
+<main><h1>{}</h1>{}</main></body></html>\n",
+        escape(title),
+        STYLE,
+        escape(title),
+        body
+    )
+}
+
+/// A page telling what went wrong with a request for a page
+pub fn error_page(e: &ApiError) -> String {
+    page("Error", &format!("<p>{}</p>", escape(&e.message)))
+}
+
+fn table(headers: &[&str], rows: Vec<Vec<String>>) -> String {
+    let mut html = String::from("<table><tr>");
+    for header in headers {
+        html.push_str(&format!("<th>{}</th>", header));
+    }
+    html.push_str("</tr>");
+    for row in rows {
+        html.push_str("<tr>");
+        for cell in row {
+            html.push_str(&format!("<td>{}</td>", cell));
+        }
+        html.push_str("</tr>");
+    }
+    html.push_str("</table>");
+    html
+}
+
+/// A table of the fields of one thing, a field per row
+fn fields(rows: Vec<(&str, String)>) -> String {
+    let mut html = String::from("<table>");
+    for (name, value) in rows {
+        html.push_str(&format!("<tr><th>{}</th><td>{}</td></tr>", name, value));
+    }
+    html.push_str("</table>");
+    html
+}
+
+fn short(hash: &str) -> String {
+    format!("{}…", &hash[..std::cmp::min(hash.len(), 16)])
+}
+
+fn block_link(hash: &str) -> String {
+    format!("<a class=\"hash\" href=\"/explorer/block/{}\">{}</a>", hash, short(hash))
+}
+
+fn transaction_link(hash: &str) -> String {
+    format!("<a class=\"hash\" href=\"/explorer/tx/{}\">{}</a>", hash, short(hash))
+}
+
+fn account_link(address: &str) -> String {
+    format!("<a class=\"hash\" href=\"/explorer/account/{}\">{}</a>", address, address)
+}
+
+/// How long ago a block timestamp was, roughly
+fn age(timestamp: u128) -> String {
+    if timestamp == 0 {
+        return "-".to_string();
+    }
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis();
+    let seconds = now.saturating_sub(timestamp) / 1000;
+    match seconds {
+        0..=59 => format!("{}s ago", seconds),
+        60..=3599 => format!("{}m ago", seconds / 60),
+        3600..=86399 => format!("{}h ago", seconds / 3600),
+        _ => format!("{}d ago", seconds / 86400),
+    }
+}
+
+fn transaction_rows(transactions: &[TransactionView]) -> Vec<Vec<String>> {
+    transactions
+        .iter()
+        .map(|txn| {
+            vec![
+                transaction_link(&txn.hash),
+                account_link(&txn.sender),
+                account_link(&txn.receiver),
+                txn.value.to_string(),
+                txn.account_nonce.to_string(),
+            ]
+        })
+        .collect()
+}
+
+const TRANSACTION_HEADERS: &[&str] = &["Hash", "Sender", "Receiver", "Value", "Nonce"];
+
+impl Context {
+    /// The chain at a glance, and the latest blocks of the longest chain, or those
+    /// below the height `from`
+    pub fn explorer_home(&self, req: &ApiRequest) -> ApiResult<String> {
+        let blockchain = self.blockchain.lock().unwrap();
+        let tip_height = blockchain.tip_height();
+        let from = std::cmp::min(req.optional_param::<u64>("from", tip_height)?, tip_height);
+        let mut body = String::new();
+        if from == tip_height {
+            let mempool = self.mempool.lock().unwrap();
+            body.push_str(&fields(vec![
+                ("Chain", escape(&blockchain.spec().name)),
+                ("Height", tip_height.to_string()),
+                ("Tip", block_link(&blockchain.tip().to_string())),
+                ("Blocks on every branch", blockchain.num_blocks().to_string()),
+                ("Mempool", format!("<a href=\"/explorer/mempool\">{} transactions</a>", mempool.len())),
+            ]));
+        }
+        let rows = (0..=from)
+            .rev()
+            .take(LATEST_BLOCKS as usize)
+            .map(|height| {
+                let hash = blockchain.block_at_height(height).unwrap();
+                let block = blockchain.get_block(&hash).unwrap();
+                vec![
+                    height.to_string(),
+                    block_link(&hash.to_string()),
+                    block.content.transactions.len().to_string(),
+                    age(block.header.timestamp),
+                ]
+            })
+            .collect();
+        body.push_str(&table(&["Height", "Hash", "Transactions", "Mined"], rows));
+        if from >= LATEST_BLOCKS {
+            body.push_str(&format!("<p><a href=\"/explorer?from={}\">Older blocks</a></p>", from - LATEST_BLOCKS));
+        }
+        Ok(page("Latest blocks", &body))
+    }
+
+    pub fn explorer_block(&self, req: &ApiRequest) -> ApiResult<String> {
+        let block = self.block(req)?;
+        let header = &block.header;
+        let next = if header.in_longest_chain {
+            let blockchain = self.blockchain.lock().unwrap();
+            blockchain.block_at_height(header.height + 1)
+        } else {
+            None
+        };
+        let branch = if header.in_longest_chain {
+            "longest chain".to_string()
+        } else {
+            "<span class=\"stale\">stale branch</span>".to_string()
+        };
+        let mut body = fields(vec![
+            ("Hash", format!("<span class=\"hash\">{}</span>", header.hash)),
+            ("Height", header.height.to_string()),
+            ("Branch", branch),
+            ("Confirmations", header.confirmations.to_string()),
+            ("Parent", if header.height == 0 { "-".to_string() } else { block_link(&header.parent) }),
+            ("Next", next.map_or("-".to_string(), |hash| block_link(&hash.to_string()))),
+            ("Mined", format!("{} <span class=\"muted\">({} ms since the epoch)</span>", age(header.timestamp), header.timestamp)),
+            ("Difficulty", format!("<span class=\"hash\">{}</span>", header.difficulty)),
+            ("Nonce", header.nonce.to_string()),
+            ("Merkle root", format!("<span class=\"hash\">{}</span>", header.merkle_root)),
+        ]);
+        body.push_str(&format!("<h2>{} transactions</h2>", block.transactions.len()));
+        body.push_str(&table(TRANSACTION_HEADERS, transaction_rows(&block.transactions)));
+        Ok(page(&format!("Block {}", header.height), &body))
+    }
+
+    pub fn explorer_transaction(&self, req: &ApiRequest) -> ApiResult<String> {
+        let lookup = self.transaction(req)?;
+        let txn = &lookup.transaction;
+        let status = match lookup.status {
+            TransactionStatus::Confirmed => "confirmed",
+            TransactionStatus::Forked => "<span class=\"stale\">only in a stale branch</span>",
+            TransactionStatus::Pending => "waiting in the mempool",
+        };
+        let block = lookup
+            .block
+            .as_ref()
+            .map_or("-".to_string(), |block| format!("{} at height {}", block_link(&block.hash), block.height));
+        let body = fields(vec![
+            ("Hash", format!("<span class=\"hash\">{}</span>", txn.hash)),
+            ("Status", status.to_string()),
+            ("Block", block),
+            ("Confirmations", lookup.confirmations.to_string()),
+            ("Sender", account_link(&txn.sender)),
+            ("Receiver", account_link(&txn.receiver)),
+            ("Value", txn.value.to_string()),
+            ("Account nonce", txn.account_nonce.to_string()),
+            ("Public key", format!("<span class=\"hash\">{}</span>", txn.public_key)),
+            ("Signature", format!("<span class=\"hash\">{}</span>", txn.signature)),
+        ]);
+        Ok(page("Transaction", &body))
+    }
+
+    /// An account, and a page of its history, from `offset`
+    pub fn explorer_account(&self, req: &ApiRequest) -> ApiResult<String> {
+        let account = self.account(req)?;
+        let history = self.account_history(req)?;
+        let mut body = fields(vec![
+            ("Address", format!("<span class=\"hash\">{}</span>", account.address)),
+            ("Balance", account.balance.to_string()),
+            ("Nonce", account.nonce.to_string()),
+            ("Pending transactions", account.pending_count.to_string()),
+            ("Balance after pending transactions", account.pending_balance.to_string()),
+        ]);
+        if !account.exists {
+            body.push_str("<p class=\"muted\">The account is not in the state at the tip.</p>");
+        }
+        body.push_str(&format!("<h2>History ({} transactions)</h2>", history.total));
+        let rows = history
+            .transactions
+            .iter()
+            .map(|entry| {
+                let txn = &entry.transaction;
+                let (direction, other) = if txn.sender == history.address {
+                    ("to", &txn.receiver)
+                } else {
+                    ("from", &txn.sender)
+                };
+                vec![
+                    transaction_link(&txn.hash),
+                    format!("{} {}", direction, account_link(other)),
+                    txn.value.to_string(),
+                    format!("{} at height {}", block_link(&entry.block.hash), entry.block.height),
+                    entry.confirmations.to_string(),
+                ]
+            })
+            .collect();
+        body.push_str(&table(&["Hash", "Counterparty", "Value", "Block", "Confirmations"], rows));
+        let next = history.offset + history.transactions.len();
+        if next < history.total {
+            body.push_str(&format!(
+                "<p><a href=\"/explorer/account/{}?offset={}&amp;limit={}\">Older transactions</a></p>",
+                history.address, next, history.limit
+            ));
+        }
+        Ok(page("Account", &body))
+    }
+
+    pub fn explorer_mempool(&self, req: &ApiRequest) -> ApiResult<String> {
+        let mempool = self.mempool(req)?;
+        let mut body = format!("<p>{} transactions are waiting to be mined.</p>", mempool.count);
+        body.push_str(&table(TRANSACTION_HEADERS, transaction_rows(&mempool.transactions)));
+        Ok(page("Mempool", &body))
+    }
+
+    /// The connected peers, with the simulated conditions of their links
+    pub fn explorer_peers(&self, req: &ApiRequest) -> ApiResult<String> {
+        let peers = self.network_peers(req)?;
+        let rows = peers
+            .iter()
+            .map(|peer| {
+                let link = match (&self.network_conditions, peer.parse()) {
+                    (Some(conditions), Ok(addr)) => conditions.link(&addr),
+                    _ => Default::default(),
+                };
+                let bandwidth = link.bandwidth.map_or("unlimited".to_string(), |bandwidth| format!("{} B/s", bandwidth));
+                vec![
+                    escape(peer),
+                    format!("{} ms ± {} ms", link.latency_ms, link.jitter_ms),
+                    bandwidth,
+                    format!("{}%", link.drop_rate * 100.0),
+                    if link.partitioned { "yes".to_string() } else { "no".to_string() },
+                ]
+            })
+            .collect();
+        let mut body = format!("<p>{} peers are connected.</p>", peers.len());
+        body.push_str(&table(&["Address", "Latency", "Bandwidth", "Loss", "Partitioned"], rows));
+        Ok(page("Peers", &body))
+    }
+
+    /// Every block of the last `depth` heights, on every branch, as a tree drawn from left
+    /// to right. The longest chain runs along the top, and each fork gets its own row.
+    pub fn explorer_forks(&self, req: &ApiRequest) -> ApiResult<String> {
+        let depth = req.optional_param::<u64>("depth", FORK_TREE_DEPTH)?.clamp(1, MAX_FORK_TREE_DEPTH);
+        let blockchain = self.blockchain.lock().unwrap();
+        let tip = blockchain.tip();
+        let lowest = blockchain.tip_height().saturating_sub(depth - 1);
+
+        // each block continues the row of its parent, unless it is on the longest chain,
+        // which has row 0, or another child of the parent has taken that row already
+        let mut rows: HashMap<H256, u64> = HashMap::new();
+        let mut continued: HashSet<H256> = HashSet::new();
+        let mut next_row = 1;
+        let mut circles = String::new();
+        let mut lines = String::new();
+        let mut stale = 0;
+        let position = |height: u64, row: u64| (TREE_MARGIN + (height - lowest) * TREE_STEP_X, TREE_MARGIN + row * TREE_STEP_Y);
+        for hash in blockchain.all_blocks() {
+            let height = blockchain.get_height(&hash).unwrap();
+            if height < lowest {
+                continue;
+            }
+            let parent = blockchain.get_block(&hash).unwrap().get_parent();
+            let in_longest_chain = blockchain.in_longest_chain(&hash);
+            let row = match rows.get(&parent) {
+                _ if in_longest_chain => 0,
+                Some(&row) if row != 0 && continued.insert(parent) => row,
+                _ => {
+                    next_row += 1;
+                    next_row - 1
+                }
+            };
+            rows.insert(hash, row);
+            let (x, y) = position(height, row);
+            if let Some(&parent_row) = rows.get(&parent) {
+                let (parent_x, parent_y) = position(height - 1, parent_row);
+                lines.push_str(&format!("<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\"/>", parent_x, parent_y, x, y));
+            }
+            let class = if hash == tip {
+                "tip"
+            } else if in_longest_chain {
+                "main"
+            } else {
+                stale += 1;
+                "stale"
+            };
+            circles.push_str(&format!(
+                "<a class=\"{}\" href=\"/explorer/block/{}\"><circle cx=\"{}\" cy=\"{}\" r=\"8\"><title>{} at height {}</title></circle></a>",
+                class,
+                hash,
+                x,
+                y,
+                hash,
+                height
+            ));
+        }
+        let mut labels = String::new();
+        for height in (lowest..=blockchain.tip_height()).filter(|height| height % 5 == 0) {
+            let (x, _) = position(height, 0);
+            labels.push_str(&format!("<text x=\"{}\" y=\"12\" text-anchor=\"middle\">{}</text>", x, height));
+        }
+        let width = 2 * TREE_MARGIN + (blockchain.tip_height() - lowest) * TREE_STEP_X;
+        let height = 2 * TREE_MARGIN + (next_row - 1) * TREE_STEP_Y;
+        let body = format!(
+            "<p>{} blocks of the last {} heights are off the longest chain. \
+Show <a href=\"/explorer/forks?depth={}\">more</a> or <a href=\"/explorer/forks?depth={}\">fewer</a> heights.</p>\
+<div style=\"overflow-x: auto\"><svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\">{}{}{}</svg></div>",
+            stale,
+            depth,
+            std::cmp::min(depth * 2, MAX_FORK_TREE_DEPTH),
+            std::cmp::max(depth / 2, 1),
+            width,
+            height,
+            labels,
+            lines,
+            circles
+        );
+        Ok(page("Fork tree", &body))
+    }
+
+    /// Show the block, transaction or account that `q` names: a block height, a block or
+    /// transaction hash, or an address
+    pub fn explorer_search(&self, req: &ApiRequest) -> ApiResult<String> {
+        let query = req.param::<String>("q")?;
+        let query = query.trim();
+        let by = |name: &str| {
+            let path = HashMap::from([(name.to_string(), query.to_string())]);
+            ApiRequest::new(path, HashMap::new(), String::new())
+        };
+        if let Ok(height) = query.parse::<u64>() {
+            let hash = self.blockchain.lock().unwrap().block_at_height(height);
+            let hash = hash.ok_or_else(|| ApiError::not_found(format!("there is no block at height {}", height)))?;
+            let path = HashMap::from([("hash".to_string(), hash.to_string())]);
+            return self.explorer_block(&ApiRequest::new(path, HashMap::new(), String::new()));
+        }
+        if query.parse::<Address>().is_ok() {
+            return self.explorer_account(&by("address"));
+        }
+        if let Ok(hash) = query.parse::<H256>() {
+            if self.blockchain.lock().unwrap().get_block(&hash).is_ok() {
+                return self.explorer_block(&by("hash"));
+            }
+            return self.explorer_transaction(&by("hash")).map_err(|_| ApiError::not_found(format!("no block or transaction has hash {}", hash)));
+        }
+        Err(ApiError::bad_request(format!("{} is not a block height, a hash or an address", query)))
+    }
+}
+
+// DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. BEFORE TEST
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::blockchain::{spec::ChainSpec, Blockchain};
+    use crate::events::EventBus;
+    use crate::generator::{self, scenario::Scenario};
+    use crate::metrics::Metrics;
+    use crate::miner;
+    use crate::network;
+    use crate::shutdown::Shutdown;
+    use crate::simulation::Simulation;
+    use crate::types::{
+        block::generate_random_block,
+        hash::Hashable,
+        mempool::Mempool,
+        transaction::{self, SignedTransaction, Transaction},
+    };
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+    use std::sync::{Arc, Mutex};
+
+    // an API context over `blockchain`, with nothing running behind its handles
+    fn context(blockchain: Blockchain) -> Context {
+        let blockchain = Arc::new(Mutex::new(blockchain));
+        let mempool = Arc::new(Mutex::new(Mempool::new()));
+        let simulation = Simulation::default();
+        let (_, miner, _) = miner::new(&blockchain, &mempool, 1, &simulation);
+        let (_, txn_generator, _) = generator::new(&blockchain, &mempool, &simulation, Scenario::default());
+        let (msg_tx, _) = smol::channel::bounded(1);
+        let (_, network) = network::server::new(([127, 0, 0, 1], 0).into(), msg_tx).unwrap();
+        Context {
+            miner,
+            txn_generator,
+            network,
+            network_conditions: None,
+            blockchain,
+            mempool,
+            wallet: None,
+            events: EventBus::default(),
+            metrics: Metrics::default(),
+            shutdown: Shutdown::new(),
+        }
+    }
+
+    fn request(name: &str, value: &str) -> ApiRequest {
+        ApiRequest::new(HashMap::from([(name.to_string(), value.to_string())]), HashMap::new(), String::new())
+    }
+
+    fn search(query: &str) -> ApiRequest {
+        ApiRequest::new(HashMap::new(), HashMap::from([("q".to_string(), query.to_string())]), String::new())
+    }
+
+    #[test]
+    fn render_pages() {
+        assert_eq!(escape("<a href=\"x\">&</a>"), "&lt;a href=&quot;x&quot;&gt;&amp;&lt;/a&gt;");
+        let html = error_page(&ApiError::not_found("no block <script>"));
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("no block &lt;script&gt;"));
+        assert!(!html.contains("<script"));
+        assert_eq!(age(0), "-");
+        let hash = H256::from([7; 32]).to_string();
+        assert!(block_link(&hash).contains(&format!("/explorer/block/{}", hash)));
+        assert!(table(&["Height"], vec![vec!["1".to_string()]]).contains("<td>1</td>"));
+    }
+
+    #[test]
+    fn serve_pages_of_a_forked_chain() {
+        let spec = ChainSpec { name: "<b>net</b>".to_string(), ..ChainSpec::default() };
+        let mut blockchain = Blockchain::from_spec(&spec);
+        let genesis = blockchain.tip();
+
+        // the longest chain has a transaction, and a stale block forks off the genesis block
+        let key = Ed25519KeyPair::from_seed_unchecked(&[0; 32]).unwrap();
+        let sender = Address::from_public_key_bytes(key.public_key().as_ref());
+        let receiver = Address::from_public_key_bytes(Ed25519KeyPair::from_seed_unchecked(&[1; 32]).unwrap().public_key().as_ref());
+        let transaction = Transaction { account_nonce: 1, receiver, value: 25 };
+        let txn = SignedTransaction {
+            signature: transaction::sign(&transaction, &key).as_ref().to_vec(),
+            public_key: key.public_key().as_ref().to_vec(),
+            transaction,
+        };
+        let mut first = generate_random_block(&genesis);
+        first.content.transactions = vec![txn.clone()];
+        let second = generate_random_block(&first.hash());
+        let stale = generate_random_block(&genesis);
+        for block in [&first, &second, &stale] {
+            blockchain.insert(block).unwrap();
+        }
+        let ctx = context(blockchain);
+
+        // the longest chain runs along the top row, and the fork gets the next one
+        let html = ctx.explorer_forks(&request("depth", "30")).unwrap();
+        assert!(html.contains("<p>1 blocks of the last 30 heights are off the longest chain."));
+        assert!(html.contains("width=\"132\" height=\"88\""));
+        let circle = |class: &str, hash: H256, x: u64, y: u64| {
+            format!("<a class=\"{}\" href=\"/explorer/block/{}\"><circle cx=\"{}\" cy=\"{}\" r=\"8\">", class, hash, x, y)
+        };
+        assert!(html.contains(&circle("main", genesis, 30, 30)));
+        assert!(html.contains(&circle("main", first.hash(), 66, 30)));
+        assert!(html.contains(&circle("tip", second.hash(), 102, 30)));
+        assert!(html.contains(&circle("stale", stale.hash(), 66, 58)));
+        assert!(html.contains("<line x1=\"30\" y1=\"30\" x2=\"66\" y2=\"58\"/>"));
+        assert!(html.contains("<line x1=\"66\" y1=\"30\" x2=\"102\" y2=\"30\"/>"));
+
+        // a search shows the page of what it finds
+        let block_page = |hash: H256| ctx.explorer_block(&request("hash", &hash.to_string())).unwrap();
+        assert_eq!(ctx.explorer_search(&search("2")).unwrap(), block_page(second.hash()));
+        assert_eq!(ctx.explorer_search(&search(&format!(" {} ", stale.hash()))).unwrap(), block_page(stale.hash()));
+        let txn_page = ctx.explorer_transaction(&request("hash", &txn.hash().to_string())).unwrap();
+        assert_eq!(ctx.explorer_search(&search(&txn.hash().to_string())).unwrap(), txn_page);
+        let account_page = ctx.explorer_account(&request("address", &sender.to_string())).unwrap();
+        assert_eq!(ctx.explorer_search(&search(&sender.to_string())).unwrap(), account_page);
+
+        // unknown hashes and heights are not found, and other queries are bad requests
+        let unknown = H256::from([7; 32]).to_string();
+        assert_eq!(ctx.explorer_search(&search(&unknown)).unwrap_err().status(), 404);
+        assert_eq!(ctx.explorer_block(&request("hash", &unknown)).unwrap_err().status(), 404);
+        assert_eq!(ctx.explorer_transaction(&request("hash", &unknown)).unwrap_err().status(), 404);
+        assert_eq!(ctx.explorer_search(&search("3")).unwrap_err().status(), 404);
+        let e = ctx.explorer_search(&search("<script>")).unwrap_err();
+        assert_eq!(e.status(), 400);
+        assert!(error_page(&e).contains("&lt;script&gt; is not a block height"));
+
+        // the pages show every field, and escape the text that is not the node's own
+        let html = ctx.explorer_home(&request("from", "2")).unwrap();
+        assert!(html.contains("&lt;b&gt;net&lt;/b&gt;") && !html.contains("<b>net"));
+        assert!(block_page(first.hash()).contains(&format!("<span class=\"hash\">{}</span>", first.header.merkle_root)));
+        assert!(block_page(stale.hash()).contains("<span class=\"stale\">stale branch</span>"));
+        assert!(txn_page.contains(&format!("href=\"/explorer/account/{}\">{}</a>", receiver, receiver)));
+        assert!(txn_page.contains(&format!("<span class=\"hash\">{}</span>", hex::encode(&txn.signature))));
+        for html in [&txn_page, &account_page] {
+            assert!(!html.contains("<script") && !html.contains("<b>"));
+        }
+    }
+}
+
+// DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. AFTER TEST
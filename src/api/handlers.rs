@@ -0,0 +1,505 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crossbeam::channel::RecvTimeoutError;
+use crate::blockchain::{archive::{Archive, Scope}, Blockchain};
+use crate::events::{Event, EventBus};
+use crate::metrics::Metrics;
+use crate::generator::{scenario::Scenario, Handle as GeneratorHandle};
+use crate::miner::{stats::MinerStatus, Handle as MinerHandle};
+use crate::network::{
+    conditions::{Conditions, LinkConditions},
+    message::Message,
+    server::Handle as NetworkServerHandle,
+};
+use crate::shutdown::Shutdown;
+use crate::types::{
+    address::Address,
+    hash::{H256, Hashable},
+    mempool::{Mempool, Rejection},
+    transaction::SignedTransaction,
+};
+use crate::wallet::{AccountInfo, Wallet};
+use super::error::{ApiError, ErrorCode};
+use super::view::{
+    AccountHistoryView, AccountView, BlockView, ChainSpecView, HeaderView, MempoolView, NetworkConditionsView,
+    NewAddressView, OkView, StateView, SubmittedView, TransactionLookupView,
+};
+
+// how many transactions an account history page holds, by default and at most
+const HISTORY_PAGE_SIZE: usize = 20;
+const MAX_HISTORY_PAGE_SIZE: usize = 100;
+
+// how long an event stream may be idle before we send a comment, to notice closed connections
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+pub type ApiResult<T> = Result<T, ApiError>;
+
+/// Writes a stream of server-sent events to a connection until it closes
+pub type Stream = Box<dyn FnOnce(&mut dyn Write) -> io::Result<()> + Send>;
+
+/// What an endpoint gets from a request
+pub struct ApiRequest {
+    pub path: HashMap<String, String>,      // the `{name}` segments of the route
+    pub query: HashMap<String, String>,
+    pub body: String,
+    fields: HashMap<String, String>,        // the top-level fields of a JSON object body
+}
+
+// Everything the endpoints need from the node
+#[derive(Clone)]
+pub struct Context {
+    pub miner: MinerHandle,
+    pub txn_generator: GeneratorHandle,
+    pub network: NetworkServerHandle,
+    pub network_conditions: Option<Conditions>,    // if the node simulates its links
+    pub blockchain: Arc<Mutex<Blockchain>>,
+    pub mempool: Arc<Mutex<Mempool>>,
+    pub wallet: Option<Arc<Mutex<Wallet>>>,
+    pub events: EventBus,
+    pub metrics: Metrics,
+    pub shutdown: Shutdown,
+}
+
+impl ApiRequest {
+    pub fn new(path: HashMap<String, String>, query: HashMap<String, String>, body: String) -> Self {
+        let fields = match serde_json::from_str::<serde_json::Value>(&body) {
+            Ok(serde_json::Value::Object(map)) => map
+                .into_iter()
+                .map(|(name, value)| match value {
+                    serde_json::Value::String(s) => (name, s),
+                    value => (name, value.to_string()),
+                })
+                .collect(),
+            _ => HashMap::new(),
+        };
+        Self { path, query, body, fields }
+    }
+
+    /// Look a parameter up in the path, then in the query string, then in a JSON body
+    fn get(&self, name: &str) -> Option<&String> {
+        self.path.get(name).or_else(|| self.query.get(name)).or_else(|| self.fields.get(name))
+    }
+
+    /// Get a parameter and parse it, or describe what is wrong with it
+    pub fn param<T>(&self, name: &str) -> ApiResult<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let value = self.get(name).ok_or_else(|| ApiError::bad_request(format!("missing {}", name)))?;
+        parse(value, name)
+    }
+
+    /// Get an optional parameter and parse it, falling back to a default
+    pub fn optional_param<T>(&self, name: &str, default: T) -> ApiResult<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        match self.get(name) {
+            Some(value) => parse(value, name),
+            None => Ok(default),
+        }
+    }
+}
+
+/// Parse a parameter, or describe what is wrong with it
+fn parse<T>(value: &str, name: &str) -> ApiResult<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    value.parse::<T>().map_err(|e| ApiError::bad_request(format!("error parsing {}: {}", name, e)))
+}
+
+/// Parse a signed transaction given as JSON or as hex-encoded bincode
+fn parse_transaction(body: &str) -> ApiResult<SignedTransaction> {
+    let body = body.trim();
+    if body.starts_with('{') {
+        serde_json::from_str(body).map_err(|e| ApiError::bad_request(format!("error parsing transaction json: {}", e)))
+    } else {
+        let bytes = hex::decode(body).map_err(|e| ApiError::bad_request(format!("error parsing transaction hex: {}", e)))?;
+        bincode::deserialize(&bytes).map_err(|e| ApiError::bad_request(format!("error decoding transaction: {}", e)))
+    }
+}
+
+impl Context {
+    // MINER
+
+    pub fn miner_start(&self, req: &ApiRequest) -> ApiResult<OkView> {
+        let lambda = req.param::<u64>("lambda")?;
+        if req.get("threads").is_some() {
+            self.miner.set_threads(req.param::<usize>("threads")?);
+        }
+        self.miner.start(lambda);
+        Ok(OkView::ok())
+    }
+
+    pub fn miner_pause(&self, _req: &ApiRequest) -> ApiResult<OkView> {
+        self.miner.pause();
+        Ok(OkView::ok())
+    }
+
+    pub fn miner_resume(&self, _req: &ApiRequest) -> ApiResult<OkView> {
+        self.miner.resume();
+        Ok(OkView::ok())
+    }
+
+    pub fn miner_set_lambda(&self, req: &ApiRequest) -> ApiResult<OkView> {
+        self.miner.set_lambda(req.param::<u64>("lambda")?);
+        Ok(OkView::ok())
+    }
+
+    pub fn miner_set_block_size(&self, req: &ApiRequest) -> ApiResult<OkView> {
+        let limit = req.param::<usize>("limit")?;
+        let max = self.blockchain.lock().unwrap().spec().consensus.max_block_transactions;
+        if let Some(max) = max.filter(|max| limit > *max) {
+            return Err(ApiError::bad_request(format!("the chain allows at most {} transactions per block", max)));
+        }
+        self.miner.set_block_size_limit(limit);
+        Ok(OkView::ok())
+    }
+
+    pub fn miner_set_payout_address(&self, req: &ApiRequest) -> ApiResult<OkView> {
+        self.miner.set_payout_address(req.param::<Address>("address")?);
+        Ok(OkView::ok())
+    }
+
+    pub fn miner_status(&self, _req: &ApiRequest) -> ApiResult<MinerStatus> {
+        Ok(self.miner.status())
+    }
+
+    // TRANSACTION GENERATOR
+
+    pub fn generator_start(&self, req: &ApiRequest) -> ApiResult<OkView> {
+        self.txn_generator.start(req.param::<u64>("theta")?);
+        Ok(OkView::ok())
+    }
+
+    /// The scenario is sent as JSON in the request body
+    pub fn generator_set_scenario(&self, req: &ApiRequest) -> ApiResult<OkView> {
+        let scenario: Scenario = serde_json::from_str(&req.body)
+            .map_err(|e| ApiError::bad_request(format!("error parsing scenario: {}", e)))?;
+        self.txn_generator.set_scenario(scenario).map_err(ApiError::bad_request)?;
+        Ok(OkView::ok())
+    }
+
+    // WALLET
+
+    fn wallet(&self) -> ApiResult<&Arc<Mutex<Wallet>>> {
+        self.wallet.as_ref().ok_or_else(|| ApiError::new(ErrorCode::Unavailable, "no wallet loaded"))
+    }
+
+    pub fn wallet_accounts(&self, _req: &ApiRequest) -> ApiResult<Vec<AccountInfo>> {
+        let wallet = self.wallet()?.lock().unwrap();
+        let blockchain = self.blockchain.lock().unwrap();
+        let mempool = self.mempool.lock().unwrap();
+        let state = blockchain.get_state(&blockchain.tip()).unwrap();
+        Ok(wallet.accounts(state, &mempool))
+    }
+
+    pub fn wallet_new_address(&self, req: &ApiRequest) -> ApiResult<NewAddressView> {
+        let mut wallet = self.wallet()?.lock().unwrap();
+        let name = req.get("name").cloned().unwrap_or_default();
+        let address = wallet.new_key(&name)?;
+        Ok(NewAddressView { name, address: address.to_hex_string() })
+    }
+
+    pub fn wallet_send(&self, req: &ApiRequest) -> ApiResult<SubmittedView> {
+        let wallet = self.wallet()?.lock().unwrap();
+        let from = req.param::<Address>("from")?;
+        let to = req.param::<Address>("to")?;
+        let value = req.param::<u128>("value")?;
+
+        // build the transaction and queue it while holding the locks,
+        // so the next payment sees this one in the mempool
+        let blockchain = self.blockchain.lock().unwrap();
+        let mut mempool = self.mempool.lock().unwrap();
+        let txn = wallet.build_transaction(&from, to, value, &blockchain, &mempool)?;
+        let hash = self.submit_transaction(txn, &blockchain, &mut mempool)?;
+        Ok(SubmittedView { success: true, hash: hash.to_string() })
+    }
+
+    // TRANSACTIONS
+
+    pub fn transaction_submit(&self, req: &ApiRequest) -> ApiResult<SubmittedView> {
+        let txn = parse_transaction(&req.body)?;
+        let blockchain = self.blockchain.lock().unwrap();
+        let mut mempool = self.mempool.lock().unwrap();
+        let hash = self.submit_transaction(txn, &blockchain, &mut mempool)?;
+        Ok(SubmittedView { success: true, hash: hash.to_string() })
+    }
+
+    /// Admit a transaction to the mempool the same way the network worker does,
+    /// then tell the miner and our peers about it
+    fn submit_transaction(&self, txn: SignedTransaction, blockchain: &Blockchain, mempool: &mut Mempool) -> Result<H256, Rejection> {
+        let state = blockchain.get_state(&blockchain.tip()).unwrap();
+        let hash = mempool.admit(txn, state)?;
+        self.miner.update();
+        self.network.broadcast(Message::NewTransactionHashes(vec![hash]));
+        Ok(hash)
+    }
+
+    pub fn transaction(&self, req: &ApiRequest) -> ApiResult<TransactionLookupView> {
+        let hash = req.param::<H256>("hash")?;
+        // look in the blockchain first, then in the mempool
+        let blockchain = self.blockchain.lock().unwrap();
+        if let Some((block_hash, txn)) = blockchain.get_transaction(&hash) {
+            return Ok(TransactionLookupView::in_block(txn, &block_hash, &blockchain));
+        }
+        let mempool = self.mempool.lock().unwrap();
+        match mempool.get(&hash) {
+            Some(txn) => Ok(TransactionLookupView::pending(txn)),
+            None => Err(ApiError::not_found("transaction not found")),
+        }
+    }
+
+    pub fn mempool(&self, _req: &ApiRequest) -> ApiResult<MempoolView> {
+        let mempool = self.mempool.lock().unwrap();
+        Ok(MempoolView::new(&mempool))
+    }
+
+    // NETWORK
+
+    pub fn network_ping(&self, _req: &ApiRequest) -> ApiResult<OkView> {
+        self.network.broadcast(Message::Ping(String::from("Test ping")));
+        Ok(OkView::ok())
+    }
+
+    pub fn network_connect(&self, req: &ApiRequest) -> ApiResult<OkView> {
+        let addr = req.param::<SocketAddr>("addr")?;
+        self.network
+            .connect(addr)
+            .map_err(|e| ApiError::new(ErrorCode::BadGateway, format!("error connecting to {}: {}", addr, e)))?;
+        Ok(OkView::new(format!("connected to {}", addr)))
+    }
+
+    pub fn network_peers(&self, _req: &ApiRequest) -> ApiResult<Vec<String>> {
+        let mut peers = self.network.peers();
+        peers.sort();
+        Ok(peers.into_iter().map(|peer| peer.to_string()).collect())
+    }
+
+    fn conditions(&self) -> ApiResult<&Conditions> {
+        self.network_conditions
+            .as_ref()
+            .ok_or_else(|| ApiError::new(ErrorCode::Unavailable, "links are not simulated; set network.simulate_links"))
+    }
+
+    pub fn network_conditions(&self, _req: &ApiRequest) -> ApiResult<NetworkConditionsView> {
+        Ok(NetworkConditionsView::new(self.conditions()?))
+    }
+
+    /// The conditions are sent as JSON in the request body, and apply to the link to
+    /// the `peer` of the query string, or to every link without its own if it is not given
+    pub fn network_set_conditions(&self, req: &ApiRequest) -> ApiResult<OkView> {
+        let link: LinkConditions = serde_json::from_str(&req.body)
+            .map_err(|e| ApiError::bad_request(format!("error parsing link conditions: {}", e)))?;
+        link.validate().map_err(ApiError::bad_request)?;
+        let conditions = self.conditions()?;
+        if req.get("peer").is_some() {
+            conditions.set_link(req.param("peer")?, link);
+        } else {
+            conditions.set_default_link(link);
+        }
+        Ok(OkView::ok())
+    }
+
+    /// Return the link to `peer` to the default conditions, or, without a peer, every link to ideal ones
+    pub fn network_reset_conditions(&self, req: &ApiRequest) -> ApiResult<OkView> {
+        let conditions = self.conditions()?;
+        if req.get("peer").is_some() {
+            conditions.reset_link(&req.param("peer")?);
+        } else {
+            conditions.reset();
+        }
+        Ok(OkView::ok())
+    }
+
+    // NODE
+
+    /// Ask the node to stop its services and exit, after answering this request
+    pub fn node_shutdown(&self, _req: &ApiRequest) -> ApiResult<OkView> {
+        self.shutdown.request("api");
+        Ok(OkView::new("shutting down"))
+    }
+
+    // BLOCKCHAIN
+
+    pub fn longest_chain(&self, _req: &ApiRequest) -> ApiResult<Vec<String>> {
+        let blockchain = self.blockchain.lock().unwrap();
+        let v = blockchain.all_blocks_in_longest_chain();
+        Ok(v.into_iter().map(|h| h.to_string()).collect())
+    }
+
+    /// The transaction hashes of each block of the longest chain
+    pub fn longest_chain_tx(&self, _req: &ApiRequest) -> ApiResult<Vec<Vec<String>>> {
+        let blockchain = self.blockchain.lock().unwrap();
+        let txn_chain = blockchain
+            .all_blocks_in_longest_chain()
+            .iter()
+            .map(|block_hash| {
+                let block = blockchain.get_block(block_hash).unwrap();
+                block.content.transactions.iter().map(|txn| txn.hash().to_string()).collect()
+            })
+            .collect();
+        Ok(txn_chain)
+    }
+
+    pub fn longest_chain_tx_count(&self, _req: &ApiRequest) -> ApiResult<usize> {
+        let blockchain = self.blockchain.lock().unwrap();
+        let count = blockchain
+            .all_blocks_in_longest_chain()
+            .iter()
+            .map(|block_hash| blockchain.get_block(block_hash).unwrap().content.transactions.len())
+            .sum();
+        Ok(count)
+    }
+
+    /// The chain spec this node follows, with the genesis hash other nodes must share
+    pub fn chain_spec(&self, _req: &ApiRequest) -> ApiResult<ChainSpecView> {
+        let blockchain = self.blockchain.lock().unwrap();
+        Ok(ChainSpecView::new(&blockchain))
+    }
+
+    /// The blocks of the longest chain, or with `scope=all` of every branch, as a chain archive
+    pub fn export(&self, req: &ApiRequest) -> ApiResult<Archive> {
+        let scope = req.optional_param("scope", Scope::LongestChain)?;
+        let blockchain = self.blockchain.lock().unwrap();
+        Ok(Archive::export(&blockchain, scope))
+    }
+
+    pub fn num_blocks(&self, _req: &ApiRequest) -> ApiResult<usize> {
+        let blockchain = self.blockchain.lock().unwrap();
+        Ok(blockchain.all_blocks_in_longest_chain().len())
+    }
+
+    /// The state after a block of the longest chain, as "(address, nonce, balance)" strings.
+    /// The autograder reads this shape, so it stays; `accounts` returns the same as objects.
+    pub fn state(&self, req: &ApiRequest) -> ApiResult<Vec<String>> {
+        let state = self.accounts(req)?;
+        Ok(state
+            .accounts
+            .iter()
+            .map(|account| format!("({}, {}, {})", account.address, account.nonce, account.balance))
+            .collect())
+    }
+
+    /// Every account after a block of the longest chain
+    pub fn accounts(&self, req: &ApiRequest) -> ApiResult<StateView> {
+        let height = req.param::<u64>("block")?;
+        let blockchain = self.blockchain.lock().unwrap();
+        let block_hash = blockchain
+            .block_at_height(height)
+            .ok_or_else(|| ApiError::not_found("given block number is out of bounds"))?;
+        let state = blockchain.get_state(&block_hash).unwrap();
+        Ok(StateView::new(&block_hash, state, &blockchain))
+    }
+
+    pub fn block(&self, req: &ApiRequest) -> ApiResult<BlockView> {
+        let hash = req.param::<H256>("hash")?;
+        let blockchain = self.blockchain.lock().unwrap();
+        let block = blockchain.get_block(&hash).map_err(|_| ApiError::not_found("block not found"))?;
+        Ok(BlockView::new(block, &blockchain))
+    }
+
+    pub fn block_at_height(&self, req: &ApiRequest) -> ApiResult<BlockView> {
+        let height = req.param::<u64>("height")?;
+        let blockchain = self.blockchain.lock().unwrap();
+        let hash = blockchain.block_at_height(height).ok_or_else(|| ApiError::not_found("block not found"))?;
+        Ok(BlockView::new(blockchain.get_block(&hash).unwrap(), &blockchain))
+    }
+
+    pub fn header(&self, req: &ApiRequest) -> ApiResult<HeaderView> {
+        let hash = req.param::<H256>("hash")?;
+        let blockchain = self.blockchain.lock().unwrap();
+        let block = blockchain.get_block(&hash).map_err(|_| ApiError::not_found("block not found"))?;
+        Ok(HeaderView::new(&hash, &block.header, &blockchain))
+    }
+
+    // METRICS
+
+    /// The node's metrics in the Prometheus text format, with the gauges that are
+    /// read from the blockchain, mempool and miner brought up to date
+    pub fn metrics(&self, _req: &ApiRequest) -> ApiResult<String> {
+        let metrics = &self.metrics;
+        {
+            let blockchain = self.blockchain.lock().unwrap();
+            let tip = blockchain.get_block(&blockchain.tip()).unwrap();
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis();
+            let tip_age = now.saturating_sub(tip.header.timestamp) as f64 / 1000.0;
+            let height = blockchain.tip_height();
+            metrics.set("bitcoin_chain_height", &[], height as f64);
+            metrics.set("bitcoin_tip_age_seconds", &[], tip_age);
+            metrics.set("bitcoin_known_blocks", &[], blockchain.num_blocks() as f64);
+            metrics.set("bitcoin_stale_blocks", &[], (blockchain.num_blocks() as u64 - height - 1) as f64);
+        }
+        metrics.set("bitcoin_mempool_transactions", &[], self.mempool.lock().unwrap().len() as f64);
+        let miner = self.miner.status();
+        metrics.set("bitcoin_blocks_mined_total", &[], miner.blocks_found as f64);
+        metrics.set("bitcoin_stale_blocks_mined_total", &[], miner.stale_blocks as f64);
+        metrics.set("bitcoin_hash_rate", &[], miner.hash_rate);
+        Ok(metrics.render())
+    }
+
+    // EVENTS
+
+    /// Stream events as they happen, optionally only those whose type is in the
+    /// comma-separated `types` parameter
+    pub fn events(&self, req: &ApiRequest) -> ApiResult<Stream> {
+        let kinds: Option<HashSet<String>> = match req.get("types") {
+            Some(types) => {
+                let kinds: HashSet<String> = types.split(',').map(|kind| kind.trim().to_string()).collect();
+                if let Some(unknown) = kinds.iter().find(|kind| !Event::KINDS.contains(&kind.as_str())) {
+                    return Err(ApiError::bad_request(format!("unknown event type {}", unknown)));
+                }
+                Some(kinds)
+            }
+            None => None,
+        };
+        let events = self.events.subscribe();
+        Ok(Box::new(move |out: &mut dyn Write| {
+            // tell the client how long to wait before reconnecting
+            out.write_all(b"retry: 1000\n\n")?;
+            out.flush()?;
+            loop {
+                match events.recv_timeout(KEEPALIVE_INTERVAL) {
+                    Ok((id, event)) => {
+                        if kinds.as_ref().is_some_and(|kinds| !kinds.contains(event.kind())) {
+                            continue;
+                        }
+                        let data = serde_json::to_string(&event).unwrap();
+                        write!(out, "id: {}\nevent: {}\ndata: {}\n\n", id, event.kind(), data)?;
+                    }
+                    Err(RecvTimeoutError::Timeout) => out.write_all(b": keepalive\n\n")?,
+                    // we fell behind and were dropped; the client will reconnect
+                    Err(RecvTimeoutError::Disconnected) => return Ok(()),
+                }
+                out.flush()?;
+            }
+        }))
+    }
+
+    // ACCOUNTS
+
+    pub fn account(&self, req: &ApiRequest) -> ApiResult<AccountView> {
+        let address = req.param::<Address>("address")?;
+        let blockchain = self.blockchain.lock().unwrap();
+        let mempool = self.mempool.lock().unwrap();
+        Ok(AccountView::new(&address, &blockchain, &mempool))
+    }
+
+    pub fn account_history(&self, req: &ApiRequest) -> ApiResult<AccountHistoryView> {
+        let address = req.param::<Address>("address")?;
+        let offset = req.optional_param::<usize>("offset", 0)?;
+        let limit = std::cmp::min(req.optional_param::<usize>("limit", HISTORY_PAGE_SIZE)?, MAX_HISTORY_PAGE_SIZE);
+        let blockchain = self.blockchain.lock().unwrap();
+        Ok(AccountHistoryView::new(&address, offset, limit, &blockchain))
+    }
+}
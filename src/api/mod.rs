@@ -1,273 +1,344 @@
+pub mod auth;
+pub mod error;
+pub mod explorer;
+pub mod handlers;
+pub mod router;
+pub mod schema;
+pub mod view;
+
 use serde::Serialize;
-use crate::blockchain::Blockchain;
-use crate::miner::Handle as MinerHandle;
-use crate::generator::Handle as GeneratorHandle;
-use crate::network::server::Handle as NetworkServerHandle;
-use crate::network::message::Message;
-use crate::types::{
-    mempool::Mempool,
-    hash::{H256, Hashable},
-    block::Content,
-    transaction::SignedTransaction,
-};
+use auth::{Auth, Scope};
+use error::{ApiError, ErrorCode};
+use handlers::{ApiRequest, ApiResult, Context};
+use router::{Handler, Match, Router};
 
-use log::info;
+use crossbeam::channel::{self, TrySendError};
+use log::{debug, info, warn};
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::io::{self, Read, Write};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
 use std::thread;
+use std::time::Instant;
 use tiny_http::Header;
+use tiny_http::Method;
+use tiny_http::Request;
 use tiny_http::Response;
 use tiny_http::Server as HTTPServer;
 use url::Url;
 
+// the threads answering requests, and how many requests may wait for one
+const WORKERS: usize = 16;
+const QUEUE_SIZE: usize = 64;
+// event streams hold a worker for as long as the client stays, so leave some workers
+// free for other requests
+const MAX_STREAMS: usize = WORKERS / 2;
+// the largest request body read, in bytes
+const MAX_BODY: u64 = 1 << 20;
+
 pub struct Server {
-    handle: HTTPServer,
-    miner: MinerHandle,
-    txn_generator: GeneratorHandle,
-    network: NetworkServerHandle,
-    blockchain: Arc<Mutex<Blockchain>>,
-    mempool: Arc<Mutex<Mempool>>
+    handle: Arc<HTTPServer>,
+    ctx: Context,
+    router: Router,
+    auth: Auth,
+    streams: AtomicUsize,  // the event streams open
 }
 
-#[derive(Serialize)]
-struct ApiResponse {
-    success: bool,
-    message: String,
+/// Every endpoint of the API. Reading endpoints are GETs, and anything that changes
+/// the node is a POST, taking its parameters from a JSON body or the query string.
+///
+/// The course autograder starts nodes with GETs to /miner/start, /tx-generator/start
+/// and /network/ping, so those stay available as GETs, still needing an admin token,
+/// when `legacy_get` is set.
+pub fn routes(legacy_get: bool) -> Router {
+    let router = Router::new()
+        .post("/miner/start", Context::miner_start)
+        .post("/miner/pause", Context::miner_pause)
+        .post("/miner/resume", Context::miner_resume)
+        .post("/miner/set-lambda", Context::miner_set_lambda)
+        .post("/miner/set-block-size", Context::miner_set_block_size)
+        .post("/miner/set-payout-address", Context::miner_set_payout_address)
+        .get("/miner/status", Context::miner_status)
+        .post("/tx-generator/start", Context::generator_start)
+        .post("/tx-generator/set-scenario", Context::generator_set_scenario)
+        .get("/wallet/accounts", Context::wallet_accounts)
+        .post("/wallet/new-address", Context::wallet_new_address)
+        .post("/wallet/send", Context::wallet_send)
+        .post("/transaction", Context::transaction_submit)
+        .get("/tx/{hash}", Context::transaction)
+        .get("/mempool", Context::mempool)
+        .post("/network/ping", Context::network_ping)
+        .post("/network/connect", Context::network_connect)
+        .get("/network/peers", Context::network_peers)
+        .get("/network/conditions", Context::network_conditions)
+        .post("/network/conditions", Context::network_set_conditions)
+        .post("/network/conditions/reset", Context::network_reset_conditions)
+        .post("/node/shutdown", Context::node_shutdown)
+        .get("/blockchain/longest-chain", Context::longest_chain)
+        .get("/blockchain/longest-chain-tx", Context::longest_chain_tx)
+        .get("/blockchain/longest-chain-tx-count", Context::longest_chain_tx_count)
+        .get("/blockchain/num-blocks", Context::num_blocks)
+        .get("/blockchain/spec", Context::chain_spec)
+        .get("/blockchain/export", Context::export)
+        .get("/blockchain/state", Context::state)
+        .get("/blockchain/accounts", Context::accounts)
+        .get("/block/{hash}", Context::block)
+        .get("/block/height/{height}", Context::block_at_height)
+        .get("/header/{hash}", Context::header)
+        .get("/account/{address}", Context::account)
+        .get("/account/{address}/history", Context::account_history)
+        .stream("/events", Context::events)
+        .text("/metrics", Context::metrics)
+        .html("/explorer", Context::explorer_home)
+        .html("/explorer/block/{hash}", Context::explorer_block)
+        .html("/explorer/tx/{hash}", Context::explorer_transaction)
+        .html("/explorer/account/{address}", Context::explorer_account)
+        .html("/explorer/mempool", Context::explorer_mempool)
+        .html("/explorer/peers", Context::explorer_peers)
+        .html("/explorer/forks", Context::explorer_forks)
+        .html("/explorer/search", Context::explorer_search)
+        .get("/openapi.json", |_, _| Ok(schema::openapi()));
+    if !legacy_get {
+        return router;
+    }
+    router
+        .route(Method::Get, Scope::Admin, "/miner/start", Context::miner_start)
+        .route(Method::Get, Scope::Admin, "/tx-generator/start", Context::generator_start)
+        .route(Method::Get, Scope::Admin, "/network/ping", Context::network_ping)
 }
 
-macro_rules! respond_result {
-    ( $req:expr, $success:expr, $message:expr ) => {{
-        let content_type = "Content-Type: application/json".parse::<Header>().unwrap();
-        let payload = ApiResponse {
-            success: $success,
-            message: $message.to_string(),
-        };
-        let resp = Response::from_string(serde_json::to_string_pretty(&payload).unwrap())
-            .with_header(content_type);
-        $req.respond(resp).unwrap();
-    }};
+/// Send the result of an endpoint: its body with status 200, or an error object with the error's status
+fn respond<T: Serialize>(req: Request, result: ApiResult<T>) -> u16 {
+    respond_with_headers(req, result, Vec::new())
 }
-macro_rules! respond_json {
-    ( $req:expr, $message:expr ) => {{
-        let content_type = "Content-Type: application/json".parse::<Header>().unwrap();
-        let resp = Response::from_string(serde_json::to_string(&$message).unwrap())
-            .with_header(content_type);
-        $req.respond(resp).unwrap();
-    }};
+
+fn respond_with_headers<T: Serialize>(req: Request, result: ApiResult<T>, headers: Vec<Header>) -> u16 {
+    let content_type = "Content-Type: application/json".parse::<Header>().unwrap();
+    let (status, body) = match result {
+        Ok(value) => (200, serde_json::to_string(&value)),
+        Err(e) => (e.status(), serde_json::to_string(&e.to_response())),
+    };
+    let mut resp = Response::from_string(body.unwrap())
+        .with_header(content_type)
+        .with_status_code(status);
+    for header in headers {
+        resp.add_header(header);
+    }
+    if let Err(e) = req.respond(resp) {
+        warn!("Error sending API response: {}", e);
+    }
+    status
 }
 
-impl Server {
-    pub fn start(
-        addr: std::net::SocketAddr,
-        miner: &MinerHandle,
-        txn_generator: &GeneratorHandle,
-        network: &NetworkServerHandle,
-        blockchain: &Arc<Mutex<Blockchain>>,
-        mempool: &Arc<Mutex<Mempool>>
-    ) {
-        let handle = HTTPServer::http(&addr).unwrap();
-        let server = Self {
-            handle,
-            miner: miner.clone(),
-            txn_generator: txn_generator.clone(),
-            network: network.clone(),
-            blockchain: Arc::clone(blockchain),
-            mempool: Arc::clone(mempool),
-        };
-        thread::spawn(move || {
-            for req in server.handle.incoming_requests() {
-                let miner = server.miner.clone();
-                let txn_generator = server.txn_generator.clone();
-                let network = server.network.clone();
-                let blockchain = Arc::clone(&server.blockchain);
-                let mempool = Arc::clone(&server.mempool);
-                thread::spawn(move || {
-                    // a valid url requires a base
-                    let base_url = Url::parse(&format!("http://{}/", &addr)).unwrap();
-                    let url = match base_url.join(req.url()) {
-                        Ok(u) => u,
-                        Err(e) => {
-                            respond_result!(req, false, format!("error parsing url: {}", e));
-                            return;
-                        }
-                    };
-                    match url.path() {
-                        "/miner/start" => {
-                            let params = url.query_pairs();
-                            let params: HashMap<_, _> = params.into_owned().collect();
-                            let lambda = match params.get("lambda") {
-                                Some(v) => v,
-                                None => {
-                                    respond_result!(req, false, "missing lambda");
-                                    return;
-                                }
-                            };
-                            let lambda = match lambda.parse::<u64>() {
-                                Ok(v) => v,
-                                Err(e) => {
-                                    respond_result!(
-                                        req,
-                                        false,
-                                        format!("error parsing lambda: {}", e)
-                                    );
-                                    return;
-                                }
-                            };
-                            miner.start(lambda);
-                            respond_result!(req, true, "ok");
-                        }
-                        "/tx-generator/start" => {
-                            let params = url.query_pairs();
-                            let params: HashMap<_, _> = params.into_owned().collect();
-                            let theta = match params.get("theta") {
-                                Some(v) => v,
-                                None => {
-                                    respond_result!(req, false, "missing theta");
-                                    return;
-                                }
-                            };
-                            let theta = match theta.parse::<u64>() {
-                                Ok(v) => v,
-                                Err(e) => {
-                                    respond_result!(
-                                        req,
-                                        false,
-                                        format!("error parsing theta: {}", e)
-                                    );
-                                    return;
-                                }
-                            };
-                            txn_generator.start(theta);
-                            respond_result!(req, true, "ok");
-                        }
-                        "/network/ping" => {
-                            network.broadcast(Message::Ping(String::from("Test ping")));
-                            respond_result!(req, true, "ok");
-                        }
-                        "/blockchain/longest-chain" => {
-                            let blockchain = blockchain.lock().unwrap();
-                            let v = blockchain.all_blocks_in_longest_chain();
-                            let v_string: Vec<String> = v.into_iter().map(|h|h.to_string()).collect();
-                            respond_json!(req, v_string);
-                        }
-                        "/blockchain/longest-chain-tx" => {
-                            let blockchain = blockchain.lock().unwrap();
-                            let v = blockchain.all_blocks_in_longest_chain();
-                            
-                            let mut txn_chain = Vec::new();     // will store all transactions
-                            for block_hash in v {               // iterate through all blocks
-                                let block = blockchain.get_block(&block_hash).unwrap().clone();
-                                let mut block_txns = Vec::new();
-                                for txn in block.content.transactions.iter() {
-                                    block_txns.push(txn.hash().to_string());
-                                }
-                                txn_chain.push(block_txns);     // add block's txns to txn_chain
-                            }
-                            drop(blockchain);
-                    
-                            respond_json!(req, txn_chain);
-                        }
-                        "/blockchain/longest-chain-tx-count" => {
-                            let blockchain = blockchain.lock().unwrap();
-                            let v = blockchain.all_blocks_in_longest_chain();
-                            
-                            let mut total_tx_count = 0; // will store total # of transactions
-                            for block_hash in v {       // iterate through all blocks
-                                let block = blockchain.get_block(&block_hash).unwrap().clone();
-                                total_tx_count += block.content.transactions.len(); // add block's transactions count
-                            }
-                            drop(blockchain);
-                            
-                            respond_json!(req, total_tx_count);
-                        }
-                        "/blockchain/state" => {
-                            let params = url.query_pairs();
-                            let params: HashMap<_, _> = params.into_owned().collect();
-                            let block_num = match params.get("block") {
-                                Some(v) => v,
-                                None => {
-                                    respond_result!(req, false, "missing block number");
-                                    return;
-                                }
-                            };
-                            let block_num = match block_num.parse::<usize>() {
-                                Ok(v) => v,
-                                Err(e) => {
-                                    respond_result!(
-                                        req,
-                                        false,
-                                        format!("error parsing block number: {}", e)
-                                    );
-                                    return;
-                                }
-                            };
+/// Answer a request, and return the status sent. `user` is set to the name of the
+/// token holder, once the request is authorised.
+fn serve(req: Request, addr: &std::net::SocketAddr, server: &Server, user: &mut Option<String>) -> u16 {
+    let Server { ctx, router, auth, .. } = server;
+    // a valid url requires a base
+    let base_url = Url::parse(&format!("http://{}/", addr)).unwrap();
+    let url = match base_url.join(req.url()) {
+        Ok(u) => u,
+        Err(e) => return respond::<()>(req, Err(ApiError::bad_request(format!("error parsing url: {}", e)))),
+    };
+    let (route, path_params) = match router.find(req.method(), url.path()) {
+        Match::Found(route, params) => (route, params),
+        Match::MethodNotAllowed(allowed) => {
+            let allowed: Vec<String> = allowed.iter().map(|m| m.to_string()).collect();
+            let allow = format!("Allow: {}", allowed.join(", ")).parse::<Header>().unwrap();
+            let e = ApiError::new(
+                ErrorCode::MethodNotAllowed,
+                format!("{} {} is not allowed, use {}", req.method(), url.path(), allowed.join(" or ")),
+            );
+            return respond_with_headers::<()>(req, Err(e), vec![allow]);
+        }
+        Match::NotFound => return respond::<()>(req, Err(ApiError::not_found("endpoint not found"))),
+    };
+
+    let authorization = req
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Authorization"))
+        .map(|header| header.value.as_str().to_string());
+    match auth.check(authorization.as_deref(), route.scope) {
+        Ok(name) => *user = name,
+        Err(e) if e.code == ErrorCode::Unauthorized => {
+            let challenge = "WWW-Authenticate: Bearer".parse::<Header>().unwrap();
+            return respond_with_headers::<()>(req, Err(e), vec![challenge]);
+        }
+        Err(e) => return respond::<()>(req, Err(e)),
+    }
+    // a browser can send a cross-site POST with a form's content type but not with a
+    // JSON one or a token, so one of those shows the request came from an API client
+    if *req.method() == Method::Post && user.is_none() && !is_json(&req) {
+        let e = ApiError::new(ErrorCode::UnsupportedMediaType, "POST requests need Content-Type: application/json");
+        return respond::<()>(req, Err(e));
+    }
+
+    let too_large = || ApiError::new(ErrorCode::PayloadTooLarge, format!("the body is over {} bytes", MAX_BODY));
+    if req.body_length().is_some_and(|len| len as u64 > MAX_BODY) {
+        return respond::<()>(req, Err(too_large()));
+    }
+    let mut req = req;
+    let mut body = String::new();
+    // read one byte past the limit to tell a body at the limit from a longer one
+    if let Err(e) = req.as_reader().take(MAX_BODY + 1).read_to_string(&mut body) {
+        return respond::<()>(req, Err(ApiError::bad_request(format!("error reading body: {}", e))));
+    }
+    if body.len() as u64 > MAX_BODY {
+        return respond::<()>(req, Err(too_large()));
+    }
+    let query: HashMap<_, _> = url.query_pairs().into_owned().collect();
+    let api_req = ApiRequest::new(path_params, query, body);
+    match route.handler() {
+        Handler::Json(handler) => respond(req, handler(ctx, &api_req)),
+        Handler::Text(handler) => match handler(ctx, &api_req) {
+            Ok(text) => {
+                let content_type = "Content-Type: text/plain; version=0.0.4".parse::<Header>().unwrap();
+                if let Err(e) = req.respond(Response::from_string(text).with_header(content_type)) {
+                    warn!("Error sending API response: {}", e);
+                }
+                200
+            }
+            Err(e) => respond::<()>(req, Err(e)),
+        },
+        Handler::Html(handler) => {
+            let (status, html) = match handler(ctx, &api_req) {
+                Ok(html) => (200, html),
+                Err(e) => (e.status(), explorer::error_page(&e)),
+            };
+            let content_type = "Content-Type: text/html; charset=utf-8".parse::<Header>().unwrap();
+            if let Err(e) = req.respond(Response::from_string(html).with_header(content_type).with_status_code(status)) {
+                warn!("Error sending API response: {}", e);
+            }
+            status
+        }
+        Handler::Stream(_) if server.streams.fetch_add(1, Ordering::SeqCst) >= MAX_STREAMS => {
+            server.streams.fetch_sub(1, Ordering::SeqCst);
+            let e = ApiError::new(ErrorCode::Unavailable, "too many event streams are open, try again later");
+            respond::<()>(req, Err(e))
+        }
+        Handler::Stream(handler) => match handler(ctx, &api_req) {
+            Ok(stream) => {
+                // tiny_http buffers response bodies, so take over the connection to send
+                // each event as it comes; the body has no length and ends when the
+                // connection closes, and a write fails once the client goes away
+                let mut connection = req.into_writer();
+                let head = "HTTP/1.1 200 OK\r\n\
+                    Content-Type: text/event-stream\r\n\
+                    Cache-Control: no-cache\r\n\
+                    Connection: close\r\n\r\n";
+                let result = connection
+                    .write_all(head.as_bytes())
+                    .and_then(|_| connection.flush())
+                    .and_then(|_| stream(&mut connection));
+                if let Err(e) = result {
+                    debug!("Event stream closed: {}", e);
+                }
+                server.streams.fetch_sub(1, Ordering::SeqCst);
+                200
+            }
+            Err(e) => {
+                server.streams.fetch_sub(1, Ordering::SeqCst);
+                respond::<()>(req, Err(e))
+            }
+        },
+    }
+}
+
+fn is_json(req: &Request) -> bool {
+    req.headers().iter().any(|header| {
+        header.field.equiv("Content-Type")
+            && header.value.as_str().split(';').next().unwrap().trim().eq_ignore_ascii_case("application/json")
+    })
+}
+
+/// Stops a running API server
+pub struct Handle {
+    http: Arc<HTTPServer>,
+    thread: thread::JoinHandle<()>,
+}
 
-                            let blockchain = blockchain.lock().unwrap();
-                            let v = blockchain.all_blocks_in_longest_chain();
-                            
-                            // Handle block_num values that are out of bounds
-                            if block_num >= v.len() {
-                                respond_result!(req, false, "given block number is out of bounds");
-                                return;
-                            }
+impl Handle {
+    /// The address the server listens at, with the port it was given if it asked for port 0
+    pub fn addr(&self) -> std::net::SocketAddr {
+        self.http.server_addr()
+    }
 
-                            // Get state
-                            let block_hash = v[block_num];
-                            let state = match blockchain.get_state(&block_hash) {
-                                Ok(s) => s.clone(),
-                                Err(_) => panic!("Block missing from blockchain.")
-                            };
-                            drop(blockchain);
-                            
-                            let mut acc_info = Vec::new();
-                            for (address, (acc_nonce, balance)) in &state.map {
-                                let address_str = address.clone().to_hex_string();
-                                let info_str = format!("({}, {}, {})", address_str, acc_nonce, balance);
-                                acc_info.push(info_str);
-                            }
-                            acc_info.sort();
+    /// Stop accepting requests. Requests in flight are still answered.
+    pub fn stop(self) {
+        self.http.unblock();
+        let _ = self.thread.join();
+        info!("API server stopped");
+    }
+}
 
-                            respond_json!(req, acc_info);
-                        }
-                        "/blockchain/num-blocks" => {
-                            let blockchain = blockchain.lock().unwrap();
-                            let length = blockchain.all_blocks_in_longest_chain().len();
-                            respond_json!(req, length);
-                        }
-                        "/mempool" => {
-                            let mempool = mempool.lock().unwrap();
-                            let map = mempool.map.clone();
-                            drop(mempool);
+/// The API server's socket, bound before anything it serves is started
+pub struct Listener(HTTPServer);
+
+impl Listener {
+    pub fn bind(addr: std::net::SocketAddr) -> io::Result<Self> {
+        HTTPServer::http(addr).map(Listener).map_err(io::Error::other)
+    }
+}
 
-                            let mut all_txns = Vec::new();
-                            for txn in map.values() {
-                                let acc_nonce = txn.transaction.account_nonce;
-                                let receiver = txn.transaction.receiver.clone().to_hex_string();
-                                let value = txn.transaction.value;
-                                let info = (acc_nonce, receiver, value);
-                                all_txns.push(info);
-                            }
-                            
-                            respond_json!(req, all_txns);
-                        }
-                        _ => {
-                            let content_type =
-                                "Content-Type: application/json".parse::<Header>().unwrap();
-                            let payload = ApiResponse {
-                                success: false,
-                                message: "endpoint not found".to_string(),
-                            };
-                            let resp = Response::from_string(
-                                serde_json::to_string_pretty(&payload).unwrap(),
-                            )
-                            .with_header(content_type)
-                            .with_status_code(404);
-                            req.respond(resp).unwrap();
-                        }
+impl Server {
+    pub fn start(listener: Listener, ctx: Context, auth: Auth) -> Handle {
+        let http = Arc::new(listener.0);
+        let addr = http.server_addr();
+        let server = Self {
+            handle: Arc::clone(&http),
+            ctx,
+            // only keep the GET actions when nobody else can reach us
+            router: routes(addr.ip().is_loopback()),
+            auth,
+            streams: AtomicUsize::new(0),
+        };
+        if server.auth.enabled() {
+            info!("API requires tokens, reads are {}", if server.auth.public_read { "public" } else { "not public" });
+        }
+        let server = Arc::new(server);
+        let (sender, receiver) = channel::bounded::<Request>(QUEUE_SIZE);
+        for _ in 0..WORKERS {
+            let server = Arc::clone(&server);
+            let receiver = receiver.clone();
+            thread::spawn(move || {
+                // ends once the server stops and the queue is empty
+                for req in receiver.iter() {
+                    let start = Instant::now();
+                    let remote = *req.remote_addr();
+                    let method = req.method().clone();
+                    let url = req.url().to_string();
+                    let mut user = None;
+                    let status = serve(req, &addr, &server, &mut user);
+                    info!(
+                        target: "api::access",
+                        "{} {} {} {} {} {}ms",
+                        remote,
+                        user.as_deref().unwrap_or("-"),
+                        method,
+                        url,
+                        status,
+                        start.elapsed().as_millis()
+                    );
+                }
+            });
+        }
+        let thread = thread::spawn(move || {
+            for req in server.handle.incoming_requests() {
+                match sender.try_send(req) {
+                    Ok(()) => {}
+                    // every worker is busy and the queue is full, so turn the request away
+                    Err(TrySendError::Full(req)) => {
+                        let e = ApiError::new(ErrorCode::Unavailable, "the server is busy, try again later");
+                        respond::<()>(req, Err(e));
                     }
-                });
+                    Err(TrySendError::Disconnected(_)) => break,
+                }
             }
         });
         info!("API server listening at {}", &addr);
+        Handle { http, thread }
     }
 }
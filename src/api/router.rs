@@ -0,0 +1,210 @@
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use tiny_http::Method;
+use super::auth::Scope;
+use super::handlers::{ApiRequest, ApiResult, Context, Stream};
+
+type Endpoint<T> = Box<dyn Fn(&Context, &ApiRequest) -> ApiResult<T> + Send + Sync>;
+
+/// What a route does with a request: answer it with a JSON, plain text or HTML body, or stream events to it
+pub enum Handler {
+    Json(Endpoint<Value>),
+    Text(Endpoint<String>),
+    Html(Endpoint<String>),
+    Stream(Endpoint<Stream>),
+}
+
+/// Routes requests to handlers by method and path. A path pattern is split on '/',
+/// and a `{name}` segment matches any one segment, passed to the handler as a path parameter.
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+}
+
+pub struct Route {
+    pub method: Method,
+    pub path: &'static str,
+    pub scope: Scope,   // what a token needs to use the route
+    segments: Vec<Segment>,
+    handler: Handler,
+}
+
+enum Segment {
+    Literal(&'static str),
+    Param(&'static str),
+}
+
+/// How a request matched the routes
+pub enum Match<'a> {
+    Found(&'a Route, HashMap<String, String>),
+    MethodNotAllowed(Vec<Method>),  // the methods the path allows
+    NotFound,
+}
+
+fn split(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|segment| !segment.is_empty())
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self { routes: Vec::new() }
+    }
+
+    /// A route that reads from the node
+    pub fn get<T, F>(self, path: &'static str, handler: F) -> Self
+    where
+        T: Serialize,
+        F: Fn(&Context, &ApiRequest) -> ApiResult<T> + Send + Sync + 'static,
+    {
+        self.route(Method::Get, Scope::Read, path, handler)
+    }
+
+    /// A route that changes the node
+    pub fn post<T, F>(self, path: &'static str, handler: F) -> Self
+    where
+        T: Serialize,
+        F: Fn(&Context, &ApiRequest) -> ApiResult<T> + Send + Sync + 'static,
+    {
+        self.route(Method::Post, Scope::Admin, path, handler)
+    }
+
+    /// A route that reads from the node in plain text
+    pub fn text<F>(self, path: &'static str, handler: F) -> Self
+    where
+        F: Fn(&Context, &ApiRequest) -> ApiResult<String> + Send + Sync + 'static,
+    {
+        self.add(Method::Get, Scope::Read, path, Handler::Text(Box::new(handler)))
+    }
+
+    /// A page of the block explorer, for browsers
+    pub fn html<F>(self, path: &'static str, handler: F) -> Self
+    where
+        F: Fn(&Context, &ApiRequest) -> ApiResult<String> + Send + Sync + 'static,
+    {
+        self.add(Method::Get, Scope::Read, path, Handler::Html(Box::new(handler)))
+    }
+
+    /// A route that reads from the node as events happen
+    pub fn stream<F>(self, path: &'static str, handler: F) -> Self
+    where
+        F: Fn(&Context, &ApiRequest) -> ApiResult<Stream> + Send + Sync + 'static,
+    {
+        self.add(Method::Get, Scope::Read, path, Handler::Stream(Box::new(handler)))
+    }
+
+    pub fn route<T, F>(self, method: Method, scope: Scope, path: &'static str, handler: F) -> Self
+    where
+        T: Serialize,
+        F: Fn(&Context, &ApiRequest) -> ApiResult<T> + Send + Sync + 'static,
+    {
+        let handler = Handler::Json(Box::new(move |ctx, req| {
+            let value = handler(ctx, req)?;
+            Ok(serde_json::to_value(value).unwrap())
+        }));
+        self.add(method, scope, path, handler)
+    }
+
+    fn add(mut self, method: Method, scope: Scope, path: &'static str, handler: Handler) -> Self {
+        let segments = split(path)
+            .map(|segment| match segment.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+                Some(name) => Segment::Param(name),
+                None => Segment::Literal(segment),
+            })
+            .collect();
+        self.routes.push(Route { method, path, scope, segments, handler });
+        self
+    }
+
+    pub fn routes(&self) -> impl Iterator<Item = &Route> {
+        self.routes.iter()
+    }
+
+    /// Find the route for a request, and its path parameters
+    pub fn find(&self, method: &Method, path: &str) -> Match<'_> {
+        let segments: Vec<&str> = split(path).collect();
+        let mut allowed = Vec::new();
+        for route in &self.routes {
+            let params = match route.matches(&segments) {
+                Some(params) => params,
+                None => continue,
+            };
+            if route.method == *method {
+                return Match::Found(route, params);
+            }
+            allowed.push(route.method.clone());
+        }
+        if allowed.is_empty() {
+            Match::NotFound
+        } else {
+            Match::MethodNotAllowed(allowed)
+        }
+    }
+}
+
+impl Route {
+    fn matches(&self, segments: &[&str]) -> Option<HashMap<String, String>> {
+        if segments.len() != self.segments.len() {
+            return None;
+        }
+        let mut params = HashMap::new();
+        for (pattern, segment) in self.segments.iter().zip(segments) {
+            match pattern {
+                Segment::Literal(literal) if literal == segment => {}
+                Segment::Literal(_) => return None,
+                Segment::Param(name) => {
+                    params.insert(name.to_string(), segment.to_string());
+                }
+            }
+        }
+        Some(params)
+    }
+
+    pub fn handler(&self) -> &Handler {
+        &self.handler
+    }
+}
+
+// DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. BEFORE TEST
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::view::OkView;
+
+    fn ok(_ctx: &Context, _req: &ApiRequest) -> ApiResult<OkView> {
+        Ok(OkView::ok())
+    }
+
+    #[test]
+    fn find_routes() {
+        let router = Router::new()
+            .get("/block/{hash}", ok)
+            .get("/block/height/{height}", ok)
+            .post("/miner/start", ok);
+
+        match router.find(&Method::Get, "/block/height/7") {
+            Match::Found(route, params) => {
+                assert_eq!(route.path, "/block/height/{height}");
+                assert_eq!(params["height"], "7");
+            }
+            _ => panic!("no route for /block/height/7"),
+        }
+        match router.find(&Method::Get, "/block/00ff") {
+            Match::Found(route, params) => {
+                assert_eq!(route.path, "/block/{hash}");
+                assert_eq!(params["hash"], "00ff");
+            }
+            _ => panic!("no route for /block/00ff"),
+        }
+        match router.find(&Method::Get, "/miner/start") {
+            Match::MethodNotAllowed(allowed) => assert_eq!(allowed, vec![Method::Post]),
+            _ => panic!("GET /miner/start should not be allowed"),
+        }
+        assert!(matches!(router.find(&Method::Post, "/miner/start/"), Match::Found(..)));
+        assert!(matches!(router.find(&Method::Get, "/block"), Match::NotFound));
+        assert!(matches!(router.find(&Method::Get, "/miner/stop"), Match::NotFound));
+    }
+}
+
+// DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. AFTER TEST
@@ -0,0 +1,450 @@
+use serde_json::{json, Map, Value};
+use crate::events::Event;
+
+// A description of every endpoint, served as an OpenAPI 3 document at /openapi.json
+// so clients can be generated. Keep it in step with the routes in mod.rs and the
+// response bodies in view.rs.
+
+pub struct Endpoint {
+    pub method: &'static str,
+    pub path: &'static str,
+    pub summary: &'static str,
+    pub params: &'static [Param],
+    pub body: Option<Body>,
+    pub response: &'static str,     // the name of a schema in `component_schemas`
+}
+
+/// The query parameters of a POST can also be sent as the fields of a JSON body
+pub struct Param {
+    pub name: &'static str,
+    pub location: Location,
+    pub kind: &'static str,         // a JSON schema type
+    pub required: bool,
+}
+
+#[derive(Clone, Copy)]
+pub enum Location {
+    Path,
+    Query,
+}
+
+#[derive(Clone, Copy)]
+pub enum Body {
+    Json(&'static str),     // the name of a schema
+    Transaction,            // JSON or hex-encoded bincode
+}
+
+const fn query(name: &'static str, kind: &'static str) -> Param {
+    Param { name, location: Location::Query, kind, required: true }
+}
+
+const fn optional(name: &'static str, kind: &'static str) -> Param {
+    Param { name, location: Location::Query, kind, required: false }
+}
+
+const fn path(name: &'static str, kind: &'static str) -> Param {
+    Param { name, location: Location::Path, kind, required: true }
+}
+
+const fn get(path: &'static str, summary: &'static str, params: &'static [Param], response: &'static str) -> Endpoint {
+    Endpoint { method: "get", path, summary, params, body: None, response }
+}
+
+const fn post(path: &'static str, summary: &'static str, params: &'static [Param], response: &'static str) -> Endpoint {
+    Endpoint { method: "post", path, summary, params, body: None, response }
+}
+
+pub const ENDPOINTS: &[Endpoint] = &[
+    post("/miner/start", "Start mining", &[query("lambda", "integer"), optional("threads", "integer")], "Ok"),
+    post("/miner/pause", "Pause mining", &[], "Ok"),
+    post("/miner/resume", "Resume mining", &[], "Ok"),
+    post("/miner/set-lambda", "Set the delay between mining rounds, in microseconds", &[query("lambda", "integer")], "Ok"),
+    post("/miner/set-block-size", "Set the most transactions per block", &[query("limit", "integer")], "Ok"),
+    post("/miner/set-payout-address", "Set the address paid by mined blocks", &[query("address", "string")], "Ok"),
+    get("/miner/status", "Miner state and hash rate", &[], "MinerStatus"),
+    post("/tx-generator/start", "Start generating transactions", &[query("theta", "integer")], "Ok"),
+    Endpoint {
+        method: "post",
+        path: "/tx-generator/set-scenario",
+        summary: "Replace the transaction generator scenario",
+        params: &[],
+        body: Some(Body::Json("Scenario")),
+        response: "Ok",
+    },
+    get("/wallet/accounts", "Wallet accounts with their confirmed and pending state", &[], "WalletAccounts"),
+    post("/wallet/new-address", "Add a key to the wallet", &[optional("name", "string")], "NewAddress"),
+    post("/wallet/send", "Sign and submit a payment from a wallet account",
+        &[query("from", "string"), query("to", "string"), query("value", "integer")], "Submitted"),
+    Endpoint {
+        method: "post",
+        path: "/transaction",
+        summary: "Submit a signed transaction",
+        params: &[],
+        body: Some(Body::Transaction),
+        response: "Submitted",
+    },
+    get("/tx/{hash}", "A transaction from the blockchain or the mempool", &[path("hash", "string")], "TransactionLookup"),
+    get("/mempool", "The transactions waiting in the mempool", &[], "Mempool"),
+    post("/network/ping", "Ping every peer", &[], "Ok"),
+    post("/network/connect", "Connect to a peer", &[query("addr", "string")], "Ok"),
+    get("/network/peers", "The addresses of the connected peers", &[], "Peers"),
+    get("/network/conditions", "The simulated latency, bandwidth, loss and partitions of links to peers", &[], "NetworkConditions"),
+    Endpoint {
+        method: "post",
+        path: "/network/conditions",
+        summary: "Simulate conditions on the link to a peer, or on every link without its own",
+        params: &[optional("peer", "string")],
+        body: Some(Body::Json("LinkConditions")),
+        response: "Ok",
+    },
+    post("/network/conditions/reset", "Return the link to a peer to the default conditions, or every link to ideal ones",
+        &[optional("peer", "string")], "Ok"),
+    post("/node/shutdown", "Stop the node's services and exit", &[], "Ok"),
+    get("/blockchain/longest-chain", "Block hashes of the longest chain", &[], "HashList"),
+    get("/blockchain/longest-chain-tx", "Transaction hashes of each block of the longest chain", &[], "HashLists"),
+    get("/blockchain/longest-chain-tx-count", "Number of transactions in the longest chain", &[], "Count"),
+    get("/blockchain/num-blocks", "Number of blocks in the longest chain", &[], "Count"),
+    get("/blockchain/spec", "The chain spec the node follows, and its genesis hash", &[], "ChainSpec"),
+    get("/blockchain/export", "The blocks of the longest chain, or of every branch, as a chain archive",
+        &[optional("scope", "string")], "Archive"),
+    get("/blockchain/state", "Every account after a block of the longest chain, as \"(address, nonce, balance)\" strings",
+        &[query("block", "integer")], "StateStrings"),
+    get("/blockchain/accounts", "Every account after a block of the longest chain", &[query("block", "integer")], "State"),
+    get("/block/{hash}", "A block by hash", &[path("hash", "string")], "Block"),
+    get("/block/height/{height}", "A block of the longest chain by height", &[path("height", "integer")], "Block"),
+    get("/header/{hash}", "A block header by hash", &[path("hash", "string")], "Header"),
+    get("/account/{address}", "An account at the tip, and with its pending transactions", &[path("address", "string")], "Account"),
+    get("/account/{address}/history", "Transactions of the longest chain touching an address, newest first",
+        &[path("address", "string"), optional("offset", "integer"), optional("limit", "integer")], "AccountHistory"),
+    get("/events", "Server-sent events as they happen, optionally only of the comma-separated types",
+        &[optional("types", "string")], "Event"),
+    get("/metrics", "Counters and gauges in the Prometheus text format", &[], "Metrics"),
+    get("/explorer", "The block explorer: the latest blocks, or those below a height", &[optional("from", "integer")], "Html"),
+    get("/explorer/block/{hash}", "The block explorer's page of a block", &[path("hash", "string")], "Html"),
+    get("/explorer/tx/{hash}", "The block explorer's page of a transaction", &[path("hash", "string")], "Html"),
+    get("/explorer/account/{address}", "The block explorer's page of an account",
+        &[path("address", "string"), optional("offset", "integer"), optional("limit", "integer")], "Html"),
+    get("/explorer/mempool", "The block explorer's page of the mempool", &[], "Html"),
+    get("/explorer/peers", "The block explorer's page of the peers", &[], "Html"),
+    get("/explorer/forks", "The block explorer's tree of the blocks of recent heights on every branch",
+        &[optional("depth", "integer")], "Html"),
+    get("/explorer/search", "The block explorer's page of a block height or hash, transaction hash or address",
+        &[query("q", "string")], "Html"),
+    get("/openapi.json", "This document", &[], "OpenApi"),
+];
+
+fn schema_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+/// An object schema whose fields are all required
+fn object(fields: &[(&str, Value)]) -> Value {
+    let properties: Map<String, Value> = fields.iter().map(|(name, schema)| (name.to_string(), schema.clone())).collect();
+    let required: Vec<&str> = fields.iter().map(|(name, _)| *name).collect();
+    json!({ "type": "object", "properties": properties, "required": required })
+}
+
+/// Flatten the fields of `base` into an object with some more fields, like `#[serde(flatten)]`
+fn extend(base: &Value, fields: &[(&str, Value)]) -> Value {
+    let mut schema = base.clone();
+    let extra = object(fields);
+    schema["properties"].as_object_mut().unwrap().extend(extra["properties"].as_object().unwrap().clone());
+    schema["required"].as_array_mut().unwrap().extend(extra["required"].as_array().unwrap().clone());
+    schema
+}
+
+fn string() -> Value {
+    json!({ "type": "string" })
+}
+
+fn hex() -> Value {
+    json!({ "type": "string", "pattern": "^[0-9a-f]*$" })
+}
+
+fn integer() -> Value {
+    json!({ "type": "integer", "minimum": 0 })
+}
+
+fn boolean() -> Value {
+    json!({ "type": "boolean" })
+}
+
+fn array(items: Value) -> Value {
+    json!({ "type": "array", "items": items })
+}
+
+fn one_of(values: &[&str]) -> Value {
+    json!({ "type": "string", "enum": values })
+}
+
+/// The schemas of the response bodies in view.rs, and of the other bodies the API uses
+fn component_schemas() -> Map<String, Value> {
+    let transaction = object(&[
+        ("hash", hex()),
+        ("sender", hex()),
+        ("receiver", hex()),
+        ("value", integer()),
+        ("account_nonce", integer()),
+        ("public_key", hex()),
+        ("signature", hex()),
+    ]);
+    let block_ref = object(&[("hash", hex()), ("height", integer())]);
+    let header = object(&[
+        ("hash", hex()),
+        ("parent", hex()),
+        ("nonce", integer()),
+        ("difficulty", hex()),
+        ("timestamp", integer()),
+        ("merkle_root", hex()),
+        ("height", integer()),
+        ("confirmations", integer()),
+        ("in_longest_chain", boolean()),
+    ]);
+
+    let mut schemas = Map::new();
+    let mut add = |name: &str, schema: Value| {
+        schemas.insert(name.to_string(), schema);
+    };
+    add("Ok", object(&[("success", boolean()), ("message", string())]));
+    add("Error", object(&[
+        ("success", boolean()),
+        ("error", {
+            let mut body = object(&[
+                ("code", one_of(&["bad_request", "unauthorized", "forbidden", "not_found", "method_not_allowed", "payload_too_large", "unsupported_media_type", "rejected", "internal", "bad_gateway", "unavailable"])),
+                ("message", string()),
+            ]);
+            body["properties"]["details"] = json!({ "type": "object" });
+            body
+        }),
+    ]));
+    add("Submitted", object(&[("success", boolean()), ("hash", hex())]));
+    add("NewAddress", object(&[("name", string()), ("address", hex())]));
+    add("Count", integer());
+    add("HashList", array(hex()));
+    add("HashLists", array(array(hex())));
+    add("StateStrings", array(string()));
+    add("MinerStatus", json!({ "type": "object" }));
+    add("Scenario", json!({ "type": "object" }));
+    add("OpenApi", json!({ "type": "object" }));
+    add("Metrics", string());
+    add("Html", string());
+    add("Event", {
+        // each event is the `data` of a server-sent event named after its type
+        let mut event = object(&[("type", one_of(Event::KINDS))]);
+        event["properties"]["hash"] = hex();
+        event["properties"]["height"] = integer();
+        event["properties"]["addr"] = string();
+        event
+    });
+    add("WalletAccounts", array(object(&[
+        ("name", string()),
+        ("address", hex()),
+        ("nonce", integer()),
+        ("balance", integer()),
+        ("pending_nonce", integer()),
+        ("pending_balance", integer()),
+    ])));
+    add("Transaction", transaction.clone());
+    add("TransactionLookup", {
+        let mut lookup = extend(&transaction, &[
+            ("status", one_of(&["confirmed", "forked", "pending"])),
+            ("confirmations", integer()),
+        ]);
+        lookup["properties"]["block"] = json!({ "oneOf": [block_ref.clone(), { "type": "null" }] });
+        lookup
+    });
+    add("Mempool", object(&[("count", integer()), ("transactions", array(schema_ref("Transaction")))]));
+    add("State", object(&[
+        ("block", block_ref.clone()),
+        ("accounts", array(object(&[("address", hex()), ("nonce", integer()), ("balance", integer())]))),
+    ]));
+    add("ChainSpec", object(&[
+        ("genesis_hash", hex()),
+        ("name", string()),
+        ("difficulty", hex()),
+        ("timestamp", integer()),
+        ("allocations", array(object(&[("address", hex()), ("balance", integer())]))),
+        ("consensus", json!({ "type": "object", "properties": { "max_block_transactions": { "type": ["integer", "null"] } } })),
+    ]));
+    let link_conditions = json!({
+        "type": "object",
+        "properties": {
+            "latency_ms": integer(),
+            "jitter_ms": integer(),
+            "bandwidth": { "type": ["integer", "null"], "minimum": 1 },
+            "drop_rate": { "type": "number", "minimum": 0, "maximum": 1 },
+            "partitioned": boolean(),
+        },
+    });
+    add("Peers", array(string()));
+    add("LinkConditions", link_conditions.clone());
+    add("NetworkConditions", object(&[
+        ("default", schema_ref("LinkConditions")),
+        ("peers", array({
+            let mut peer = link_conditions;
+            peer["properties"]["peer"] = string();
+            peer["required"] = json!(["peer"]);
+            peer
+        })),
+    ]));
+    // blocks as the P2P messages have them, with hashes as arrays of bytes
+    add("Archive", object(&[
+        ("chain", string()),
+        ("genesis_hash", hex()),
+        ("blocks", array(json!({ "type": "object" }))),
+    ]));
+    add("Header", header.clone());
+    add("Block", extend(&header, &[("transactions", array(schema_ref("Transaction")))]));
+    add("Account", object(&[
+        ("address", hex()),
+        ("exists", boolean()),
+        ("nonce", integer()),
+        ("balance", integer()),
+        ("pending_count", integer()),
+        ("pending_nonce", integer()),
+        ("pending_balance", integer()),
+    ]));
+    add("AccountHistory", object(&[
+        ("address", hex()),
+        ("total", integer()),
+        ("offset", integer()),
+        ("limit", integer()),
+        ("transactions", array(extend(&transaction, &[
+            ("direction", one_of(&["sent", "received", "self"])),
+            ("block", block_ref),
+            ("confirmations", integer()),
+        ]))),
+    ]));
+    schemas
+}
+
+fn operation(endpoint: &Endpoint) -> Value {
+    let parameters: Vec<Value> = endpoint
+        .params
+        .iter()
+        .map(|param| {
+            json!({
+                "name": param.name,
+                "in": match param.location {
+                    Location::Path => "path",
+                    Location::Query => "query",
+                },
+                "required": param.required,
+                "schema": { "type": param.kind },
+            })
+        })
+        .collect();
+    let error = json!({
+        "description": "The request failed",
+        "content": { "application/json": { "schema": schema_ref("Error") } },
+    });
+    let content_type = match endpoint.response {
+        "Event" => "text/event-stream",
+        "Metrics" => "text/plain",
+        "Html" => "text/html",
+        _ => "application/json",
+    };
+    // when the node has tokens, GETs need a read token and POSTs an admin one
+    let scope = if endpoint.method == "post" { "admin" } else { "read" };
+    let mut operation = json!({
+        "summary": endpoint.summary,
+        "security": [{ "bearer": [] }],
+        "x-scope": scope,
+        "parameters": parameters,
+        "responses": {
+            "200": {
+                "description": "Success",
+                "content": { content_type: { "schema": schema_ref(endpoint.response) } },
+            },
+            "default": error,
+        },
+    });
+    let fields: Vec<&Param> = endpoint.params.iter().filter(|param| matches!(param.location, Location::Query)).collect();
+    if endpoint.method == "post" && endpoint.body.is_none() && !fields.is_empty() {
+        let properties: Map<String, Value> = fields
+            .iter()
+            .map(|param| (param.name.to_string(), json!({ "type": param.kind })))
+            .collect();
+        let required: Vec<&str> = fields.iter().filter(|param| param.required).map(|param| param.name).collect();
+        operation["requestBody"] = json!({
+            "content": {
+                "application/json": {
+                    "schema": { "type": "object", "properties": properties, "required": required },
+                },
+            },
+        });
+    }
+    match endpoint.body {
+        Some(Body::Json(name)) => {
+            operation["requestBody"] = json!({
+                "required": true,
+                "content": { "application/json": { "schema": schema_ref(name) } },
+            });
+        }
+        Some(Body::Transaction) => {
+            operation["requestBody"] = json!({
+                "required": true,
+                "content": {
+                    "application/json": { "schema": { "type": "object" } },
+                    "text/plain": { "schema": hex() },
+                },
+            });
+        }
+        None => {}
+    }
+    operation
+}
+
+/// The OpenAPI 3 document describing the API
+pub fn openapi() -> Value {
+    let mut paths = Map::new();
+    for endpoint in ENDPOINTS {
+        let item = paths.entry(endpoint.path).or_insert_with(|| json!({}));
+        item[endpoint.method] = operation(endpoint);
+    }
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Bitcoin node API",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": {
+            "schemas": component_schemas(),
+            "securitySchemes": { "bearer": { "type": "http", "scheme": "bearer" } },
+        },
+    })
+}
+
+// DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. BEFORE TEST
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn every_response_schema_exists() {
+        let document = openapi();
+        let schemas = document["components"]["schemas"].as_object().unwrap();
+        for endpoint in ENDPOINTS {
+            assert!(schemas.contains_key(endpoint.response), "{} has no schema", endpoint.response);
+            if let Some(Body::Json(name)) = endpoint.body {
+                assert!(schemas.contains_key(name), "{} has no schema", name);
+            }
+            assert!(document["paths"][endpoint.path][endpoint.method].is_object());
+        }
+        // the document describes exactly the routes the server has
+        let router = crate::api::routes(false);
+        let mut routes: Vec<(String, &str)> = router.routes().map(|r| (r.method.to_string().to_lowercase(), r.path)).collect();
+        let mut documented: Vec<(String, &str)> = ENDPOINTS.iter().map(|e| (e.method.to_string(), e.path)).collect();
+        routes.sort();
+        documented.sort();
+        assert_eq!(routes, documented);
+
+        let block = &schemas["Block"];
+        assert!(block["properties"]["merkle_root"].is_object());
+        assert!(block["required"].as_array().unwrap().contains(&json!("transactions")));
+    }
+}
+
+// DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. AFTER TEST
@@ -0,0 +1,349 @@
+use serde::Serialize;
+use crate::blockchain::{spec::ChainSpec, Blockchain};
+use crate::network::conditions::{Conditions, LinkConditions};
+use crate::types::{
+    address::Address,
+    block::{Block, Header},
+    hash::{H256, Hashable},
+    mempool::Mempool,
+    state::State,
+    transaction::SignedTransaction,
+};
+
+// The response bodies of the API. Hashes, addresses, keys and signatures are hex strings.
+
+/// The reply to an action that has nothing else to return
+#[derive(Serialize, Debug, Clone)]
+pub struct OkView {
+    pub success: bool,  // always true
+    pub message: String,
+}
+
+/// A transaction accepted into the mempool
+#[derive(Serialize, Debug, Clone)]
+pub struct SubmittedView {
+    pub success: bool,  // always true
+    pub hash: String,
+}
+
+/// A key added to the wallet
+#[derive(Serialize, Debug, Clone)]
+pub struct NewAddressView {
+    pub name: String,
+    pub address: String,
+}
+
+/// The transactions waiting in the mempool, in no particular order
+#[derive(Serialize, Debug, Clone)]
+pub struct MempoolView {
+    pub count: usize,
+    pub transactions: Vec<TransactionView>,
+}
+
+/// Every account in the state after a block, ordered by address
+#[derive(Serialize, Debug, Clone)]
+pub struct StateView {
+    pub block: BlockRef,
+    pub accounts: Vec<AccountStateView>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct AccountStateView {
+    pub address: String,
+    pub nonce: u128,
+    pub balance: u128,
+}
+
+/// The chain spec a node follows, with the hash of the genesis block it makes
+#[derive(Serialize, Debug, Clone)]
+pub struct ChainSpecView {
+    pub genesis_hash: String,
+    #[serde(flatten)]
+    pub spec: ChainSpec,
+}
+
+/// The simulated conditions of the node's links: the default, and the peers that have their own
+#[derive(Serialize, Debug, Clone)]
+pub struct NetworkConditionsView {
+    pub default: LinkConditions,
+    pub peers: Vec<PeerConditionsView>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PeerConditionsView {
+    pub peer: String,
+    #[serde(flatten)]
+    pub conditions: LinkConditions,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct HeaderView {
+    pub hash: String,
+    pub parent: String,
+    pub nonce: u32,
+    pub difficulty: String,
+    pub timestamp: u128,
+    pub merkle_root: String,
+    pub height: u64,
+    pub confirmations: u64,
+    pub in_longest_chain: bool,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct BlockView {
+    #[serde(flatten)]
+    pub header: HeaderView,
+    pub transactions: Vec<TransactionView>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct TransactionView {
+    pub hash: String,
+    pub sender: String,
+    pub receiver: String,
+    pub value: u128,
+    pub account_nonce: u128,
+    pub public_key: String,
+    pub signature: String,
+}
+
+/// Where a transaction is: in a block, or still waiting in the mempool
+#[derive(Serialize, Debug, Clone)]
+pub struct TransactionLookupView {
+    #[serde(flatten)]
+    pub transaction: TransactionView,
+    pub status: TransactionStatus,
+    pub block: Option<BlockRef>,
+    pub confirmations: u64,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionStatus {
+    Confirmed,      // in a block of the longest chain
+    Forked,         // only in blocks off the longest chain
+    Pending,        // in the mempool
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct BlockRef {
+    pub hash: String,
+    pub height: u64,
+}
+
+/// An account at the tip of the chain, and with its transactions waiting in the mempool
+#[derive(Serialize, Debug, Clone)]
+pub struct AccountView {
+    pub address: String,
+    pub exists: bool,   // whether the account is in the tip state
+    pub nonce: u128,
+    pub balance: u128,
+    pub pending_count: usize,   // transactions from the account waiting in the mempool
+    pub pending_nonce: u128,
+    pub pending_balance: u128,
+}
+
+/// A page of the transactions of the longest chain that touched an address, newest first
+#[derive(Serialize, Debug, Clone)]
+pub struct AccountHistoryView {
+    pub address: String,
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+    pub transactions: Vec<HistoryEntryView>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct HistoryEntryView {
+    #[serde(flatten)]
+    pub transaction: TransactionView,
+    pub direction: Direction,
+    pub block: BlockRef,
+    pub confirmations: u64,
+}
+
+/// Which way a transaction moved value, from the point of view of one address
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Sent,
+    Received,
+    #[serde(rename = "self")]
+    ToSelf,
+}
+
+impl OkView {
+    pub fn ok() -> Self {
+        Self::new("ok")
+    }
+
+    pub fn new(message: impl ToString) -> Self {
+        Self { success: true, message: message.to_string() }
+    }
+}
+
+impl MempoolView {
+    pub fn new(mempool: &Mempool) -> Self {
+        let transactions: Vec<TransactionView> = mempool.transactions().map(TransactionView::new).collect();
+        Self { count: transactions.len(), transactions }
+    }
+}
+
+impl ChainSpecView {
+    pub fn new(blockchain: &Blockchain) -> Self {
+        Self { genesis_hash: blockchain.genesis_hash().to_string(), spec: blockchain.spec().clone() }
+    }
+}
+
+impl NetworkConditionsView {
+    pub fn new(conditions: &Conditions) -> Self {
+        let peers = conditions
+            .links()
+            .into_iter()
+            .map(|(peer, conditions)| PeerConditionsView { peer: peer.to_string(), conditions })
+            .collect();
+        Self { default: conditions.default_link(), peers }
+    }
+}
+
+impl StateView {
+    pub fn new(block_hash: &H256, state: &State, blockchain: &Blockchain) -> Self {
+        let mut accounts: Vec<AccountStateView> = state.map
+            .iter()
+            .map(|(address, (nonce, balance))| AccountStateView {
+                address: address.to_hex_string(),
+                nonce: *nonce,
+                balance: *balance,
+            })
+            .collect();
+        accounts.sort_by(|a, b| a.address.cmp(&b.address));
+        Self {
+            block: BlockRef {
+                hash: block_hash.to_string(),
+                height: blockchain.get_height(block_hash).unwrap_or(0),
+            },
+            accounts,
+        }
+    }
+}
+
+impl HeaderView {
+    pub fn new(hash: &H256, header: &Header, blockchain: &Blockchain) -> Self {
+        Self {
+            hash: hash.to_string(),
+            parent: header.parent.to_string(),
+            nonce: header.nonce,
+            difficulty: header.difficulty.to_string(),
+            timestamp: header.timestamp,
+            merkle_root: header.merkle_root.to_string(),
+            height: blockchain.get_height(hash).unwrap_or(0),
+            confirmations: blockchain.confirmations(hash),
+            in_longest_chain: blockchain.in_longest_chain(hash),
+        }
+    }
+}
+
+impl BlockView {
+    pub fn new(block: &Block, blockchain: &Blockchain) -> Self {
+        Self {
+            header: HeaderView::new(&block.hash(), &block.header, blockchain),
+            transactions: block.content.transactions.iter().map(TransactionView::new).collect(),
+        }
+    }
+}
+
+impl TransactionView {
+    pub fn new(txn: &SignedTransaction) -> Self {
+        Self {
+            hash: txn.hash().to_string(),
+            sender: Address::from_public_key_bytes(&txn.public_key).to_hex_string(),
+            receiver: txn.transaction.receiver.to_hex_string(),
+            value: txn.transaction.value,
+            account_nonce: txn.transaction.account_nonce,
+            public_key: hex::encode(&txn.public_key),
+            signature: hex::encode(&txn.signature),
+        }
+    }
+}
+
+impl TransactionLookupView {
+    /// A transaction found in a block
+    pub fn in_block(txn: &SignedTransaction, block_hash: &H256, blockchain: &Blockchain) -> Self {
+        let status = if blockchain.in_longest_chain(block_hash) {
+            TransactionStatus::Confirmed
+        } else {
+            TransactionStatus::Forked
+        };
+        Self {
+            transaction: TransactionView::new(txn),
+            status,
+            block: Some(BlockRef {
+                hash: block_hash.to_string(),
+                height: blockchain.get_height(block_hash).unwrap_or(0),
+            }),
+            confirmations: blockchain.confirmations(block_hash),
+        }
+    }
+
+    /// A transaction waiting in the mempool
+    pub fn pending(txn: &SignedTransaction) -> Self {
+        Self {
+            transaction: TransactionView::new(txn),
+            status: TransactionStatus::Pending,
+            block: None,
+            confirmations: 0,
+        }
+    }
+}
+
+impl AccountView {
+    pub fn new(address: &Address, blockchain: &Blockchain, mempool: &Mempool) -> Self {
+        let state = blockchain.get_state(&blockchain.tip()).unwrap();
+        let confirmed = state.map.get(address).copied();
+        let (nonce, balance) = confirmed.unwrap_or((0, 0));
+        let (pending_nonce, pending_balance) = mempool.pending_account(address, (nonce, balance));
+        let pending_count = mempool
+            .transactions()
+            .filter(|txn| Address::from_public_key_bytes(&txn.public_key) == *address)
+            .count();
+        Self {
+            address: address.to_hex_string(),
+            exists: confirmed.is_some(),
+            nonce,
+            balance,
+            pending_count,
+            pending_nonce,
+            pending_balance,
+        }
+    }
+}
+
+impl AccountHistoryView {
+    pub fn new(address: &Address, offset: usize, limit: usize, blockchain: &Blockchain) -> Self {
+        let (total, history) = blockchain.address_history(address, offset, limit);
+        let transactions = history
+            .into_iter()
+            .map(|(block_hash, height, txn)| {
+                let sender = Address::from_public_key_bytes(&txn.public_key);
+                let direction = match (sender == *address, txn.transaction.receiver == *address) {
+                    (true, true) => Direction::ToSelf,
+                    (true, false) => Direction::Sent,
+                    _ => Direction::Received,
+                };
+                HistoryEntryView {
+                    transaction: TransactionView::new(txn),
+                    direction,
+                    block: BlockRef { hash: block_hash.to_string(), height },
+                    confirmations: blockchain.confirmations(&block_hash),
+                }
+            })
+            .collect();
+        Self {
+            address: address.to_hex_string(),
+            total,
+            offset,
+            limit,
+            transactions,
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use crate::config::string;
+use crate::types::{
+    block::Block,
+    hash::{H256, Hashable},
+};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use super::Blockchain;
+
+/// Blocks of a chain, parents before children, in a file that another node following
+/// the same chain spec can replay. The genesis block is left out, since every such
+/// node has it already.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Archive {
+    pub chain: String,              // the name of the chain spec
+    #[serde(with = "string")]
+    pub genesis_hash: H256,
+    pub blocks: Vec<Block>,
+}
+
+/// Which blocks an archive holds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scope {
+    LongestChain,
+    All,    // every branch of the block tree
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Bincode,
+    Json,
+}
+
+/// How far an import has got, reported after every block
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Progress {
+    pub done: usize,
+    pub total: usize,
+    pub inserted: usize,    // the blocks that were new to the blockchain
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "longest-chain" => Ok(Scope::LongestChain),
+            "all" => Ok(Scope::All),
+            _ => Err(format!("unknown scope {}, expected longest-chain or all", s)),
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bincode" => Ok(Format::Bincode),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("unknown format {}, expected bincode or json", s)),
+        }
+    }
+}
+
+impl Format {
+    /// JSON if the file name ends in `.json`, and bincode otherwise
+    pub fn of_path(path: &Path) -> Self {
+        match path.extension() {
+            Some(extension) if extension == "json" => Format::Json,
+            _ => Format::Bincode,
+        }
+    }
+}
+
+impl Archive {
+    /// The blocks of a blockchain, ordered by height
+    pub fn export(blockchain: &Blockchain, scope: Scope) -> Self {
+        let hashes = match scope {
+            Scope::LongestChain => blockchain.all_blocks_in_longest_chain(),
+            Scope::All => blockchain.all_blocks(),
+        };
+        let blocks = hashes
+            .iter()
+            .skip(1)    // the genesis block
+            .map(|hash| blockchain.get_block(hash).unwrap().clone())
+            .collect();
+        Self { chain: blockchain.spec().name.clone(), genesis_hash: blockchain.genesis_hash(), blocks }
+    }
+
+    /// Read an archive in either format, telling them apart by the first byte
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let bytes = fs::read(path).map_err(|e| format!("error reading {}: {}", path.display(), e))?;
+        let archive = if bytes.first() == Some(&b'{') {
+            serde_json::from_slice(&bytes).map_err(|e| e.to_string())
+        } else {
+            bincode::deserialize(&bytes).map_err(|e| e.to_string())
+        };
+        archive.map_err(|e| format!("error parsing {}: {}", path.display(), e))
+    }
+
+    pub fn write(&self, path: &Path, format: Format) -> Result<(), String> {
+        let bytes = match format {
+            Format::Bincode => bincode::serialize(self).unwrap(),
+            Format::Json => serde_json::to_vec(self).unwrap(),
+        };
+        fs::write(path, bytes).map_err(|e| format!("error writing {}: {}", path.display(), e))
+    }
+
+    /// Insert the blocks into a blockchain, in order, with all of the checks blocks from
+    /// peers get. Blocks the blockchain has already are skipped. Stops at the first block
+    /// that is invalid or whose parent is missing, keeping the blocks before it.
+    pub fn import(&self, blockchain: &mut Blockchain, mut progress: impl FnMut(Progress)) -> Result<Progress, String> {
+        if self.genesis_hash != blockchain.genesis_hash() {
+            return Err(format!(
+                "the archive is of chain {} with genesis {}, not of chain {} with genesis {}",
+                self.chain,
+                self.genesis_hash,
+                blockchain.spec().name,
+                blockchain.genesis_hash()
+            ));
+        }
+        let mut status = Progress { done: 0, total: self.blocks.len(), inserted: 0 };
+        for block in self.blocks.iter() {
+            let hash = block.hash();
+            if blockchain.get_block(&hash).is_err() {
+                match blockchain.insert(block) {
+                    Ok(_) => status.inserted += 1,
+                    Err(true) => return Err(format!("the parent of block {} (number {} of the archive) is missing", hash, status.done + 1)),
+                    Err(false) => return Err(format!("block {} (number {} of the archive) is invalid", hash, status.done + 1)),
+                }
+            }
+            status.done += 1;
+            progress(status);
+        }
+        Ok(status)
+    }
+}
+
+// DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. BEFORE TEST
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::blockchain::spec::ChainSpec;
+    use crate::types::block::generate_random_block;
+    use std::env;
+
+    #[test]
+    fn export_and_import() {
+        let mut blockchain = Blockchain::new();
+        blockchain.set_check_pow(false);
+        let genesis = blockchain.tip();
+        let first = generate_random_block(&genesis);
+        let second = generate_random_block(&first.hash());
+        let fork = generate_random_block(&genesis);
+        for block in [&first, &second, &fork] {
+            blockchain.insert(block).unwrap();
+        }
+        assert_eq!(Archive::export(&blockchain, Scope::LongestChain).blocks.len(), 2);
+        let archive = Archive::export(&blockchain, Scope::All);
+        assert_eq!(archive.blocks.len(), 3);
+
+        for format in [Format::Bincode, Format::Json] {
+            let path = env::temp_dir().join(format!("bitcoin-archive-{}-{:?}", std::process::id(), format));
+            archive.write(&path, format).unwrap();
+            let read = Archive::read(&path).unwrap();
+            fs::remove_file(&path).unwrap();
+
+            let mut imported = Blockchain::new();
+            imported.set_check_pow(false);
+            let mut reports = 0;
+            let status = read.import(&mut imported, |_| reports += 1).unwrap();
+            assert_eq!((status.inserted, reports), (3, 3));
+            assert_eq!(imported.tip(), second.hash());
+            assert_eq!(imported.num_blocks(), 4);
+            // importing again changes nothing
+            assert_eq!(read.import(&mut imported, |_| {}).unwrap().inserted, 0);
+        }
+
+        // blocks need their parents before them, and the same genesis
+        let mut reversed = archive.clone();
+        reversed.blocks.reverse();
+        let mut imported = Blockchain::new();
+        imported.set_check_pow(false);
+        assert!(reversed.import(&mut imported, |_| {}).is_err());
+        let spec = ChainSpec { name: "testnet".to_string(), ..Default::default() };
+        assert!(archive.import(&mut Blockchain::from_spec(&spec), |_| {}).is_err());
+    }
+}
+
+// DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. AFTER TEST
@@ -0,0 +1,89 @@
+use clap::ArgMatches;
+use std::env;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::Path;
+use crate::config::Config;
+use crate::simulation::Simulation;
+use super::archive::{Archive, Format};
+use super::spec::ChainSpec;
+use super::Blockchain;
+
+// how many blocks an import goes through between progress reports
+const PROGRESS_INTERVAL: usize = 100;
+
+/// The environment variable the API token for `chain export` is read from, so it stays off
+/// the command line; without it, the configured read or admin token is used
+pub const TOKEN_VAR: &str = "BITCOIN_API_TOKEN";
+
+/// Run the `chain` subcommand
+pub fn run(matches: &ArgMatches, config: &Config) -> Result<(), String> {
+    match matches.subcommand() {
+        ("export", Some(m)) => {
+            let node = m.value_of("node").unwrap();
+            let addr = node.parse::<SocketAddr>().map_err(|e| format!("error parsing node {}: {}", node, e))?;
+            let path = Path::new(m.value_of("file").unwrap());
+            let format = match m.value_of("format") {
+                Some(format) => format.parse::<Format>()?,
+                None => Format::of_path(path),
+            };
+            let scope = if m.is_present("all") { "all" } else { "longest-chain" };
+            let token = env::var(TOKEN_VAR).ok().or_else(|| config.api.read_token.clone()).or_else(|| config.api.admin_token.clone());
+            let body = get(addr, &format!("/blockchain/export?scope={}", scope), token.as_deref())
+                .map_err(|e| format!("error exporting from {}: {}", addr, e))?;
+            let archive: Archive = serde_json::from_slice(&body).map_err(|e| format!("error parsing the export of {}: {}", addr, e))?;
+            archive.write(path, format)?;
+            println!("Exported {} blocks of chain {} to {}", archive.blocks.len(), archive.chain, path.display());
+        }
+        ("import", Some(m)) => {
+            let path = Path::new(m.value_of("file").unwrap());
+            let archive = Archive::read(path)?;
+            let spec = match &config.consensus.chain_spec {
+                Some(path) => ChainSpec::load(path)?,
+                None => ChainSpec::default(),
+            };
+            let mut blockchain = Blockchain::from_spec(&spec);
+            blockchain.set_check_pow(Simulation::default().with_mining_mode(config.mining.mode).checks_pow());
+            let status = archive.import(&mut blockchain, |progress| {
+                if progress.done % PROGRESS_INTERVAL == 0 || progress.done == progress.total {
+                    eprint!("\rImported {}/{} blocks", progress.done, progress.total);
+                }
+            });
+            eprintln!();
+            let status = status?;
+            println!(
+                "{} blocks are valid, {} new to the genesis block; the longest chain has height {} and tip {}",
+                status.done,
+                status.inserted,
+                blockchain.tip_height(),
+                blockchain.tip()
+            );
+        }
+        _ => return Err(matches.usage().to_string()),
+    }
+    Ok(())
+}
+
+// a GET of the API of a node, returning the body of a 200 response; HTTP/1.0 keeps the
+// body in one piece, closed by the end of the connection
+fn get(addr: SocketAddr, path: &str, token: Option<&str>) -> io::Result<Vec<u8>> {
+    let mut stream = TcpStream::connect(addr)?;
+    let mut request = format!("GET {} HTTP/1.0\r\nHost: {}\r\n", path, addr);
+    if let Some(token) = token {
+        request.push_str(&format!("Authorization: Bearer {}\r\n", token));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes())?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid HTTP response");
+    let end = response.windows(4).position(|window| window == b"\r\n\r\n").ok_or_else(invalid)?;
+    let head = String::from_utf8_lossy(&response[..end]);
+    let status = head.split_whitespace().nth(1).ok_or_else(invalid)?;
+    let body = response[end + 4..].to_vec();
+    if status != "200" {
+        return Err(io::Error::other(format!("status {}: {}", status, String::from_utf8_lossy(&body))));
+    }
+    Ok(body)
+}
@@ -1,15 +1,31 @@
+pub mod archive;
+pub mod cli;
+pub mod spec;
+
+use crate::events::{Event, EventBus};
+use crate::metrics::Metrics;
 use crate::types::{
     address::Address,
-    block::{Block, Content, Header},
+    block::Block,
     hash::{H256, Hashable},
     transaction,
     transaction::SignedTransaction,
-    merkle::MerkleTree,
     state::State
 };
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
 use hex_literal::hex;
-use ring::signature::{Ed25519KeyPair, KeyPair};
+use log::debug;
+use spec::ChainSpec;
+
+/// The difficulty of the built-in chain spec's genesis block
+pub const DIFFICULTY: [u8; 32] = hex!("0000100000000000000000000000000000000000000000000000000000000000");
+
+/// What the first account of the built-in chain spec holds
+pub const GENESIS_BALANCE: u128 = 10000;
 
 // A BlockNode is a node in the Blockchain
 pub struct BlockNode {
@@ -21,69 +37,79 @@ pub struct BlockNode {
 // A Blockchain
 pub struct Blockchain {
     map: HashMap<H256, BlockNode>,
-    tip: H256
+    tip: H256,
+    longest_chain: Vec<H256>,    // block hashes of the longest chain, indexed by height
+    txn_index: HashMap<H256, Vec<H256>>,    // <transaction hash, hashes of the blocks containing it>
+    address_index: HashMap<Address, Vec<(u64, usize)>>,  // <address, (height, position in block) of the longest chain's transactions touching it, oldest first>
+    tip_version: Arc<AtomicU64>,
+    check_pow: bool,
+    spec: ChainSpec,
+    events: EventBus,
+    metrics: Metrics
+}
+
+impl Default for Blockchain {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 // Implement functions for the Blockchain
 impl Blockchain {
-    /// Create a new blockchain, only containing the genesis block
+    /// Create a new blockchain of the built-in chain spec, only containing the genesis block
     pub fn new() -> Self {
-        let mut map = HashMap::new();
-
-        let genesis_parent: H256 = (hex!("0000000000000000000000000000000000000000000000000000000000000000")).into();
-        let nonce: u32 = 0;
-        
-        let transactions: Vec<SignedTransaction> = Vec::new();
-        let merkle_tree = MerkleTree::new(&transactions);
-        let merkle_root = merkle_tree.root();
-        
-        let difficulty: H256 = hex!("0000100000000000000000000000000000000000000000000000000000000000").into();
-        let timestamp: u128 = 0;
-
-        let content = Content { transactions };
-
-        let header = Header {
-            parent: genesis_parent,
-            nonce: nonce,
-            difficulty: difficulty,
-            timestamp: timestamp,
-            merkle_root: merkle_root
-        };
+        Self::from_spec(&ChainSpec::default())
+    }
 
-        let genesis_block = Block { header, content };
+    /// Create a new blockchain following a chain spec, only containing its genesis block
+    pub fn from_spec(spec: &ChainSpec) -> Self {
+        let genesis_block = spec.genesis_block();
         let tip = genesis_block.hash();
-        println!("GENISIS HASH: {}", tip);
-
-        // Initialize the genesis block node's state with 3 accounts
-        let mut state = State::new();
-        for seed in 0..3 {
-            let key = Ed25519KeyPair::from_seed_unchecked(&[seed;32]).unwrap();
-            let public_key = key.public_key().as_ref().to_vec();
-            let addr = Address::from_public_key_bytes(&public_key);
-            
-            // Only first account has a nonzero balance
-            let balance = if seed == 0 { 10000u128 } else { 0 };
-            state.map.insert(addr, (0, balance));    // account_nonce initialized to 0
-        }
+        debug!(hash:% = tip, chain = spec.name; "Genesis block");
 
-        map.insert(genesis_block.hash(), BlockNode { block: genesis_block, height: 0, state: state });
-
-        Blockchain { map, tip }
+        let mut map = HashMap::new();
+        map.insert(tip, BlockNode { block: genesis_block, height: 0, state: spec.genesis_state() });
+
+        Blockchain {
+            map,
+            tip,
+            longest_chain: vec![tip],
+            txn_index: HashMap::new(),
+            address_index: HashMap::new(),
+            tip_version: Arc::new(AtomicU64::new(0)),
+            check_pow: true,
+            spec: spec.clone(),
+            events: EventBus::default(),
+            metrics: Metrics::default()
+        }
     }
 
     /// Insert a block into blockchain
     pub fn insert(&mut self, block: &Block) -> Result<(), bool> {
+        let _span = crate::span!("validate_block", hash = block.hash(), parent = block.get_parent());
         let parent_node = match self.map.get(&block.get_parent()) {
             Some(node) => node,    // parent exists in hashmap
             None => {
                 // parent is missing in hashmap, so return an error
+                debug!("Orphan block");
+                self.metrics.inc("bitcoin_orphan_blocks_total", &[]);
                 return Err(true);
             }
         };
 
         // Check if block is a duplicate
         if self.map.contains_key(&block.hash()) {
-            return Err(false);   // block already exists
+            return Err(self.invalid("duplicate"));   // block already exists
+        }
+
+        // Check proof of work
+        if !self.satisfies_pow(block) {
+            return Err(self.invalid("bad_pow"));   // block hash exceeds difficulty
+        }
+
+        // Check the chain spec's limit on block size
+        if self.spec.consensus.max_block_transactions.is_some_and(|limit| block.content.transactions.len() > limit) {
+            return Err(self.invalid("too_many_transactions"));
         }
 
         let height = parent_node.height + 1;
@@ -93,7 +119,7 @@ impl Blockchain {
         for txn in block.content.transactions.iter() {
             // Check transaction validity
             if !transaction::verify(&txn.transaction, &txn.public_key, &txn.signature) {
-                return Err(false);       // transaction verification failed
+                return Err(self.invalid("bad_signature"));       // transaction verification failed
             }
 
             // Check account state
@@ -101,7 +127,7 @@ impl Blockchain {
             let sender_info = match parent_state.map.get(&sender_address) {
                 Some(acc_info) => acc_info,
                 None => {
-                    return Err(false);   // sender's address not in state hashmap
+                    return Err(self.invalid("unknown_sender"));   // sender's address not in state hashmap
                 },
             };
             let sender_nonce = sender_info.0;
@@ -109,12 +135,12 @@ impl Blockchain {
 
             // Check if the new account nonce in the transaction is correct
             if sender_nonce + 1 != txn.transaction.account_nonce {
-                return Err(false);      // transaction has invalid account nonce
+                return Err(self.invalid("bad_nonce"));      // transaction has invalid account nonce
             }
             
             // Check if sender's balance is enough
             if sender_balance < txn.transaction.value {
-                return Err(false);      // balance is not enough
+                return Err(self.invalid("insufficient_balance"));      // balance is not enough
             }
         }
 
@@ -140,35 +166,94 @@ impl Blockchain {
         
         let blocknode = BlockNode { 
             block: block.clone(), 
-            height: height,
+            height,
             state: new_state.clone()
         }; 
 
         // Insert blocknode into hashmap
-        self.map.insert(block.hash(), blocknode);
+        let block_hash = block.hash();
+        self.map.insert(block_hash, blocknode);
+
+        // Index the block's transactions, wherever the block is
+        for txn in block.content.transactions.iter() {
+            self.txn_index.entry(txn.hash()).or_default().push(block_hash);
+        }
 
         // Update tip
         let tip_node = self.map.get(&self.tip).unwrap();        
         if height > tip_node.height {
-            self.tip = block.hash();
+            self.tip = block_hash;
+            self.update_longest_chain();
+            self.tip_version.fetch_add(1, Ordering::Release);
+            debug!(height; "New tip");
+        } else {
+            debug!(height; "Inserted block off the longest chain");
         }
 
         Ok(())    // Successfully inserted block
     }
 
+    /// Count a rejected block by reason, and return the error `insert` gives for it
+    fn invalid(&self, reason: &str) -> bool {
+        debug!(reason; "Rejected block");
+        self.metrics.inc("bitcoin_block_validation_failures_total", &[("reason", reason)]);
+        false
+    }
+
+    /// Whether a block's hash meets its difficulty, or proof of work is not checked at all
+    pub fn satisfies_pow(&self, block: &Block) -> bool {
+        !self.check_pow || block.hash() <= block.get_difficulty()
+    }
+
+    /// Turn the proof of work check off, for simulations whose miners skip hashing
+    pub fn set_check_pow(&mut self, check_pow: bool) {
+        self.check_pow = check_pow;
+    }
+
+    /// Count reorgs, orphans and invalid blocks in `metrics`
+    pub fn set_metrics(&mut self, metrics: Metrics) {
+        self.metrics = metrics;
+    }
+
+    /// The number of blocks on every branch, including the genesis block
+    pub fn num_blocks(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Publish changes to the longest chain on an event bus
+    pub fn set_events(&mut self, events: EventBus) {
+        self.events = events;
+    }
+
+    /// The chain spec the blockchain was created from
+    pub fn spec(&self) -> &ChainSpec {
+        &self.spec
+    }
+
+    /// The hash of the first block, which nodes following the same chain spec agree on
+    pub fn genesis_hash(&self) -> H256 {
+        self.longest_chain[0]
+    }
+
     /// Get the last block's hash of the longest chain
     pub fn tip(&self) -> H256 {
-        return self.tip;
+        self.tip
+    }
+
+    /// Get a counter that is bumped every time the tip changes, so other threads
+    /// can notice a new tip without locking the blockchain
+    pub fn tip_version(&self) -> Arc<AtomicU64> {
+        Arc::clone(&self.tip_version)
     }
 
     /// Get a desired block from the blockchain
     pub fn get_block(&self, blockhash: &H256) -> Result<&Block, &'static str> {
         match self.map.get(blockhash){
             Some(node) => {
-                return Ok(&node.block);     // block exists in hashmap
+                Ok(&node.block)     // block exists in hashmap
             }
             None => {
-                return Err("Block does not exist in blockchain.");   // block not found
+                Err("Block does not exist in blockchain.")   // block not found
             }
         }
     }
@@ -177,32 +262,153 @@ impl Blockchain {
     pub fn get_state(&self, blockhash: &H256) -> Result<&State, &'static str> {
         match self.map.get(blockhash){
             Some(node) => {
-                return Ok(&node.state);     // block exists in hashmap
+                Ok(&node.state)     // block exists in hashmap
             }
             None => {
-                return Err("Block does not exist in blockchain.");   // block not found
+                Err("Block does not exist in blockchain.")   // block not found
             }
         }
     }
 
     /// Get all blocks' hashes of the longest chain, ordered from genesis to the tip
     pub fn all_blocks_in_longest_chain(&self) -> Vec<H256> {
-        let mut longest_chain: Vec<H256> = Vec::new();
-        
-        // Start with the tip 
-        let mut cur_block_hash: H256 = self.tip; 
+        self.longest_chain.clone()
+    }
+
+    /// Get the hashes of the blocks on every branch, ordered by height, so each block
+    /// comes after its parent, and then by hash
+    pub fn all_blocks(&self) -> Vec<H256> {
+        let mut blocks: Vec<(u64, H256)> = self.map.iter().map(|(hash, node)| (node.height, *hash)).collect();
+        blocks.sort();
+        blocks.into_iter().map(|(_, hash)| hash).collect()
+    }
+
+    /// Get a block's height, or None if it is not in the blockchain
+    pub fn get_height(&self, blockhash: &H256) -> Option<u64> {
+        self.map.get(blockhash).map(|node| node.height)
+    }
+
+    /// Get the height of the tip
+    pub fn tip_height(&self) -> u64 {
+        (self.longest_chain.len() - 1) as u64
+    }
+
+    /// Get the hash of the block at a height of the longest chain
+    pub fn block_at_height(&self, height: u64) -> Option<H256> {
+        self.longest_chain.get(height as usize).copied()
+    }
+
+    /// Whether a block is part of the longest chain, rather than a fork
+    pub fn in_longest_chain(&self, blockhash: &H256) -> bool {
+        match self.get_height(blockhash) {
+            Some(height) => self.block_at_height(height) == Some(*blockhash),
+            None => false,
+        }
+    }
 
-        // Move upwards through chain until genesis block is reached
+    /// How many blocks of the longest chain, including itself, are built on a block.
+    /// Zero for blocks that are not in the longest chain.
+    pub fn confirmations(&self, blockhash: &H256) -> u64 {
+        if !self.in_longest_chain(blockhash) {
+            return 0;
+        }
+        self.tip_height() - self.map[blockhash].height + 1
+    }
+
+    /// Find a transaction in the blockchain, returning it with the hash of the block that
+    /// contains it. A block of the longest chain is preferred over forks.
+    pub fn get_transaction(&self, txn_hash: &H256) -> Option<(H256, &SignedTransaction)> {
+        let block_hashes = self.txn_index.get(txn_hash)?;
+        let block_hash = block_hashes
+            .iter()
+            .find(|hash| self.in_longest_chain(hash))
+            .unwrap_or(&block_hashes[0]);
+        let txn = self.map[block_hash]
+            .block
+            .content
+            .transactions
+            .iter()
+            .find(|txn| txn.hash() == *txn_hash)?;
+        Some((*block_hash, txn))
+    }
+
+    /// Get the transactions of the longest chain that an address sent or received, newest
+    /// first, skipping `offset` of them and returning at most `limit`, along with the hash
+    /// and height of their blocks. Also returns how many there are in total.
+    pub fn address_history(&self, address: &Address, offset: usize, limit: usize) -> (usize, Vec<(H256, u64, &SignedTransaction)>) {
+        let entries = match self.address_index.get(address) {
+            Some(entries) => entries,
+            None => return (0, Vec::new()),
+        };
+        let history = entries
+            .iter()
+            .rev()
+            .skip(offset)
+            .take(limit)
+            .map(|(height, position)| {
+                let block_hash = self.longest_chain[*height as usize];
+                (block_hash, *height, &self.map[&block_hash].block.content.transactions[*position])
+            })
+            .collect();
+        (entries.len(), history)
+    }
+
+    /// Walk back from a new tip until we meet the longest chain, and switch to the new branch
+    fn update_longest_chain(&mut self) {
+        let mut new_branch = Vec::new();
+        let mut hash = self.tip;
         loop {
-            longest_chain.push(cur_block_hash); 
-            let cur_blocknode = self.map.get(&cur_block_hash).unwrap();
-            if cur_blocknode.height == 0 { break; }   // end loop at genesis block
-            cur_block_hash = cur_blocknode.block.get_parent();   // move to parent
+            let node = &self.map[&hash];
+            if self.longest_chain.get(node.height as usize) == Some(&hash) {
+                break;    // the fork point
+            }
+            new_branch.push(hash);
+            hash = node.block.get_parent();
         }
+        let fork_height = self.map[&hash].height;
 
-        longest_chain.reverse();   // reverses longest_chain vector in-place
-        
-        longest_chain
+        // Unindex the blocks we switch away from, newest first, then index the new branch
+        let old_branch = self.longest_chain.split_off(fork_height as usize + 1);
+        if !old_branch.is_empty() {
+            self.metrics.inc("bitcoin_reorgs_total", &[]);
+        }
+        for (i, block_hash) in old_branch.iter().enumerate().rev() {
+            let height = fork_height + 1 + i as u64;
+            self.events.publish(Event::BlockDisconnected { hash: block_hash.to_string(), height });
+            for address in self.touched_addresses(block_hash) {
+                let entries = self.address_index.get_mut(&address).unwrap();
+                while entries.last().is_some_and(|(height, _)| *height > fork_height) {
+                    entries.pop();
+                }
+                if entries.is_empty() {
+                    self.address_index.remove(&address);
+                }
+            }
+        }
+        new_branch.reverse();
+        for block_hash in new_branch {
+            let height = self.map[&block_hash].height;
+            for (position, txn) in self.map[&block_hash].block.content.transactions.iter().enumerate() {
+                let sender = Address::from_public_key_bytes(&txn.public_key);
+                self.address_index.entry(sender).or_default().push((height, position));
+                if txn.transaction.receiver != sender {
+                    self.address_index.entry(txn.transaction.receiver).or_default().push((height, position));
+                }
+            }
+            self.longest_chain.push(block_hash);
+            self.events.publish(Event::BlockConnected { hash: block_hash.to_string(), height });
+        }
+        self.events.publish(Event::NewTip { hash: self.tip.to_string(), height: self.tip_height() });
+    }
+
+    /// The senders and receivers of a block's transactions
+    fn touched_addresses(&self, blockhash: &H256) -> HashSet<Address> {
+        let mut addresses = HashSet::new();
+        for txn in self.map[blockhash].block.content.transactions.iter() {
+            addresses.insert(Address::from_public_key_bytes(&txn.public_key));
+            addresses.insert(txn.transaction.receiver);
+        }
+        addresses
     }
 }
 
@@ -213,6 +419,7 @@ mod tests {
     use super::*;
     use crate::types::block::generate_random_block;
     use crate::types::hash::Hashable;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
 
     #[test]
     fn insert_one() {
@@ -325,21 +532,22 @@ mod tests {
         let g = blockchain.insert(&block6);
         assert_eq!(blockchain.tip(), block6.hash());
         
-        assert!(!a.is_err());   // Ok
-        assert!(!b.is_err());   // Ok (forked chain)
+        assert!(a.is_ok());     // Ok
+        assert!(b.is_ok());     // Ok (forked chain)
         assert!(c.is_err());    // Err (parent does not exist)
         assert!(d.is_err());    // Err (duplicate block)
-        assert!(!e.is_err());   // Ok (new tip)
-        assert!(!f.is_err());   // Ok
-        assert!(!g.is_err());   // Ok (new tip)
+        assert!(e.is_ok());     // Ok (new tip)
+        assert!(f.is_ok());     // Ok
+        assert!(g.is_ok());     // Ok (new tip)
 
         // Check longest chain
-        let mut hash_vec = Vec::new();
-        hash_vec.push(genesis_hash);
-        hash_vec.push(block2.hash());
-        hash_vec.push(block5.hash());
-        hash_vec.push(block6.hash());
+        let hash_vec = vec![genesis_hash, block2.hash(), block5.hash(), block6.hash()];
         assert_eq!(blockchain.all_blocks_in_longest_chain(), hash_vec);
+        assert_eq!(blockchain.block_at_height(2), Some(block5.hash()));
+        assert_eq!(blockchain.block_at_height(4), None);
+        assert!(!blockchain.in_longest_chain(&block4.hash()));
+        assert_eq!(blockchain.confirmations(&block2.hash()), 3);
+        assert_eq!(blockchain.confirmations(&block1.hash()), 0);
 
         // Check if height values are correct
         assert_eq!(blockchain.map.get(&genesis_hash).unwrap().height, 0);
@@ -349,6 +557,69 @@ mod tests {
         assert_eq!(blockchain.map.get(&block5.hash()).unwrap().height, 2);
         assert_eq!(blockchain.map.get(&block6.hash()).unwrap().height, 3);
     }
+
+    #[test]
+    fn find_transaction_after_fork() {
+        let mut blockchain = Blockchain::new();
+        let genesis_hash = blockchain.tip();
+        let key = Ed25519KeyPair::from_seed_unchecked(&[0; 32]).unwrap();
+        let transaction = transaction::Transaction {
+            account_nonce: 1,
+            receiver: Address::from_public_key_bytes(&[1u8; 32]),
+            value: 10
+        };
+        let txn = SignedTransaction {
+            signature: transaction::sign(&transaction, &key).as_ref().to_vec(),
+            public_key: key.public_key().as_ref().to_vec(),
+            transaction,
+        };
+        let block_with = |parent: H256, transactions: Vec<SignedTransaction>| {
+            let mut block = generate_random_block(&parent);
+            block.content.transactions = transactions;
+            block
+        };
+
+        // the transaction is mined on one branch, then on a longer one
+        let block1 = block_with(genesis_hash, vec![txn.clone()]);
+        let block2 = block_with(genesis_hash, Vec::new());
+        let block3 = block_with(block2.hash(), vec![txn.clone()]);
+        assert!(blockchain.insert(&block1).is_ok());
+        assert_eq!(blockchain.get_transaction(&txn.hash()).unwrap().0, block1.hash());
+        assert!(blockchain.insert(&block2).is_ok());
+        assert!(blockchain.insert(&block3).is_ok());
+        let (block_hash, found) = blockchain.get_transaction(&txn.hash()).unwrap();
+        assert_eq!(block_hash, block3.hash());
+        assert_eq!(found.hash(), txn.hash());
+        assert!(blockchain.get_transaction(&block1.hash()).is_none());
+
+        // only the longest chain counts towards an address's history
+        let sender = Address::from_public_key_bytes(key.public_key().as_ref());
+        let (total, history) = blockchain.address_history(&sender, 0, 10);
+        assert_eq!(total, 1);
+        assert_eq!(history[0].0, block3.hash());
+        assert_eq!(history[0].1, 2);
+
+        // switch back to the first branch, where the transaction is followed by another one
+        let mut transaction = txn.transaction.clone();
+        transaction.account_nonce = 2;
+        let txn2 = SignedTransaction {
+            signature: transaction::sign(&transaction, &key).as_ref().to_vec(),
+            public_key: key.public_key().as_ref().to_vec(),
+            transaction,
+        };
+        let block4 = block_with(block1.hash(), Vec::new());
+        let block5 = block_with(block4.hash(), vec![txn2.clone()]);
+        assert!(blockchain.insert(&block4).is_ok());
+        assert!(blockchain.insert(&block5).is_ok());
+        assert_eq!(blockchain.tip(), block5.hash());
+        let (total, history) = blockchain.address_history(&sender, 0, 10);
+        assert_eq!(total, 2);
+        assert_eq!(history[0].2.hash(), txn2.hash());
+        assert_eq!(history[1].0, block1.hash());
+        let (total, history) = blockchain.address_history(&sender, 1, 10);
+        assert_eq!((total, history.len()), (2, 1));
+        assert_eq!(blockchain.address_history(&txn.transaction.receiver, 0, 1).0, 2);
+    }
 }
 
 // DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. AFTER TEST
\ No newline at end of file
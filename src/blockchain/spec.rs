@@ -0,0 +1,193 @@
+use crate::config::{self, integer, string};
+use crate::types::{
+    address::Address,
+    block::{Block, Content, Header},
+    hash::{H256, Hashable},
+    merkle::MerkleTree,
+    state::State,
+    transaction::SignedTransaction,
+};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::Path;
+use super::{DIFFICULTY, GENESIS_BALANCE};
+
+/// What a chain starts from and the rules its blocks follow. Nodes only share blocks
+/// if they have the same spec, which they can check by comparing genesis hashes.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChainSpec {
+    pub name: String,
+    #[serde(with = "string")]
+    pub difficulty: H256,           // of the genesis block, and so of every block
+    #[serde(with = "integer")]
+    pub timestamp: u128,            // of the genesis block, in milliseconds since the epoch
+    pub allocations: Vec<Allocation>,
+    pub consensus: ConsensusParams,
+}
+
+/// An account funded by the genesis block
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Allocation {
+    #[serde(with = "string")]
+    pub address: Address,
+    #[serde(with = "integer")]
+    pub balance: u128,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConsensusParams {
+    pub max_block_transactions: Option<usize>,  // blocks with more are invalid; unlimited if not set
+}
+
+impl Default for ChainSpec {
+    /// The built-in chain: the accounts of seeds `[0; 32]`, `[1; 32]` and `[2; 32]`,
+    /// only the first of which is funded
+    fn default() -> Self {
+        let allocations = (0..3)
+            .map(|seed| {
+                let key = Ed25519KeyPair::from_seed_unchecked(&[seed; 32]).unwrap();
+                Allocation {
+                    address: Address::from_public_key_bytes(key.public_key().as_ref()),
+                    balance: if seed == 0 { GENESIS_BALANCE } else { 0 },
+                }
+            })
+            .collect();
+        Self {
+            name: "devnet".to_string(),
+            difficulty: DIFFICULTY.into(),
+            timestamp: 0,
+            allocations,
+            consensus: ConsensusParams::default(),
+        }
+    }
+}
+
+impl ChainSpec {
+    /// Read a chain spec, as JSON if its name ends in `.json` and as TOML otherwise
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let spec: Self = config::read_file(path)?;
+        spec.validate().map_err(|e| format!("error in {}: {}", path.display(), e))?;
+        Ok(spec)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.is_empty() {
+            return Err("the chain has no name".to_string());
+        }
+        let mut addresses = HashSet::new();
+        if let Some(allocation) = self.allocations.iter().find(|allocation| !addresses.insert(allocation.address)) {
+            return Err(format!("{} is allocated more than once", allocation.address));
+        }
+        if self.difficulty == H256::default() {
+            return Err("the difficulty is all zeros, so no block could meet it".to_string());
+        }
+        if self.consensus.max_block_transactions == Some(0) {
+            return Err("max_block_transactions must be at least 1".to_string());
+        }
+        Ok(())
+    }
+
+    /// A hash of everything in the spec. The order of the allocations doesn't matter.
+    pub fn commitment(&self) -> H256 {
+        let mut spec = self.clone();
+        spec.allocations.sort_by(|a, b| a.address.as_ref().cmp(b.address.as_ref()));
+        let bytes = serde_json::to_vec(&spec).unwrap();
+        ring::digest::digest(&ring::digest::SHA256, &bytes).into()
+    }
+
+    /// The first block of the chain. It has no parent, so for specs other than the
+    /// built-in one its parent field holds the spec's commitment, making the genesis hash
+    /// differ between specs. The built-in chain keeps an all-zero parent, and with it the
+    /// genesis hash it has always had.
+    pub fn genesis_block(&self) -> Block {
+        let transactions: Vec<SignedTransaction> = Vec::new();
+        let commitment = self.commitment();
+        let parent = if commitment == Self::default().commitment() { H256::default() } else { commitment };
+        let header = Header {
+            parent,
+            nonce: 0,
+            difficulty: self.difficulty,
+            timestamp: self.timestamp,
+            merkle_root: MerkleTree::new(&transactions).root(),
+        };
+        Block { header, content: Content { transactions } }
+    }
+
+    pub fn genesis_hash(&self) -> H256 {
+        self.genesis_block().hash()
+    }
+
+    /// The accounts after the genesis block, with the allocated balances and no
+    /// transactions sent yet
+    pub fn genesis_state(&self) -> State {
+        let mut state = State::new();
+        for allocation in self.allocations.iter() {
+            state.map.insert(allocation.address, (0, allocation.balance));
+        }
+        state
+    }
+}
+
+// DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. BEFORE TEST
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hex_literal::hex;
+    use std::{env, fs};
+
+    // the genesis hash of the built-in chain, from before chain specs
+    const GENESIS_HASH: [u8; 32] = hex!("77745e5344ad6d8e8c2ea8db750f7eb50c6747fd603101a116cf6a1a1177d5fe");
+
+    #[test]
+    fn genesis_follows_spec() {
+        let spec = ChainSpec::default();
+        assert_eq!(spec.genesis_hash(), ChainSpec::default().genesis_hash());
+        assert_eq!(spec.genesis_state().map.len(), 3);
+        assert_eq!(spec.genesis_block().get_parent(), H256::default());
+        assert_eq!(spec.genesis_hash(), GENESIS_HASH.into());
+
+        // allocations may be listed in any order
+        let mut reordered = spec.clone();
+        reordered.allocations.reverse();
+        assert_eq!(reordered.genesis_hash(), spec.genesis_hash());
+
+        let mut renamed = spec.clone();
+        renamed.name = "testnet".to_string();
+        assert_ne!(renamed.genesis_hash(), spec.genesis_hash());
+        let mut funded = spec.clone();
+        funded.allocations[1].balance = 1;
+        assert_ne!(funded.genesis_hash(), spec.genesis_hash());
+
+        let path = env::temp_dir().join(format!("bitcoin-chain-spec-{}.toml", std::process::id()));
+        fs::write(&path, r#"
+name = "testnet"
+difficulty = "0000ffff00000000000000000000000000000000000000000000000000000000"
+
+[[allocations]]
+address = "1851a0eae0060a132cf0f64a0ffaea248de6cba0"
+balance = 500
+
+[consensus]
+max_block_transactions = 10
+"#).unwrap();
+        let loaded = ChainSpec::load(&path).unwrap();
+        assert_eq!(loaded.allocations[0].balance, 500);
+        assert_eq!(loaded.consensus.max_block_transactions, Some(10));
+        assert_eq!(loaded.genesis_state().map.len(), 1);
+        assert_eq!(loaded.genesis_block().get_difficulty(), loaded.difficulty);
+
+        let mut duplicated = loaded.clone();
+        duplicated.allocations.push(loaded.allocations[0].clone());
+        assert!(duplicated.validate().is_err());
+        let impossible = ChainSpec { difficulty: H256::default(), ..loaded.clone() };
+        assert!(impossible.validate().is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
+
+// DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. AFTER TEST
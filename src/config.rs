@@ -0,0 +1,407 @@
+use crate::api::auth::{Auth, Scope, Token};
+use crate::logging;
+use crate::miner;
+use crate::simulation::MiningMode;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::env;
+use std::fmt::Display;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Everything a node can be configured with. Settings are read, each overriding the last,
+/// from the defaults, a TOML or JSON config file, `BITCOIN_*` environment variables and
+/// command line flags.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub network: NetworkConfig,
+    pub consensus: ConsensusConfig,
+    pub mining: MiningConfig,
+    pub mempool: MempoolConfig,
+    pub generator: GeneratorConfig,
+    pub wallet: WalletConfig,
+    pub api: ApiConfig,
+    pub log: LogConfig,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkConfig {
+    pub p2p_addr: SocketAddr,
+    pub connect: Vec<SocketAddr>,   // peers to connect to at start
+    pub workers: usize,             // threads handling the messages peers send
+    pub message_buffer: usize,      // messages received but not yet handled, before reading from peers waits
+    pub simulate_links: bool,       // carry messages over links whose conditions the API can change
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConsensusConfig {
+    pub chain_spec: Option<PathBuf>,    // the genesis block and consensus rules; the built-in chain if not set
+    pub import: Option<PathBuf>,        // a chain archive whose blocks are replayed at start
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MiningConfig {
+    pub threads: usize,
+    pub block_size_limit: usize,    // the most transactions in a mined block
+    #[serde(with = "string")]
+    pub mode: MiningMode,
+    pub seed: Option<u64>,          // makes the miner and transaction generator deterministic
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct MempoolConfig {
+    pub max_transactions: Option<usize>,    // unlimited if not set
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct GeneratorConfig {
+    pub scenario: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct WalletConfig {
+    pub keystore: Option<PathBuf>,  // unlocked for the wallet API; made by `bitcoin wallet create`
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiConfig {
+    pub addr: SocketAddr,
+    pub tokens_file: Option<PathBuf>,
+    pub tokens: Vec<Token>,
+    pub admin_token: Option<String>,
+    pub read_token: Option<String>,
+    pub public_read: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    #[serde(with = "string")]
+    pub format: logging::Format,
+    pub filter: Option<String>,     // e.g. `info,bitcoin::network=debug`, over the verbosity of `-v`
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            p2p_addr: ([127, 0, 0, 1], 6000).into(),
+            connect: Vec::new(),
+            workers: 4,
+            message_buffer: 10000,
+            simulate_links: false,
+        }
+    }
+}
+
+impl Default for MiningConfig {
+    fn default() -> Self {
+        Self {
+            threads: 1,
+            block_size_limit: miner::BLOCK_SIZE_LIMIT,
+            mode: MiningMode::ProofOfWork,
+            seed: None,
+        }
+    }
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            addr: ([127, 0, 0, 1], 7000).into(),
+            tokens_file: None,
+            tokens: Vec::new(),
+            admin_token: None,
+            read_token: None,
+            public_read: false,
+        }
+    }
+}
+
+/// The settings that environment variables and `--set` can override. The variable
+/// for `network.p2p_addr` is `BITCOIN_NETWORK_P2P_ADDR`. Lists are comma-separated.
+pub const KEYS: &[&str] = &[
+    "network.p2p_addr",
+    "network.connect",
+    "network.workers",
+    "network.message_buffer",
+    "network.simulate_links",
+    "consensus.chain_spec",
+    "consensus.import",
+    "mining.threads",
+    "mining.block_size_limit",
+    "mining.mode",
+    "mining.seed",
+    "mempool.max_transactions",
+    "generator.scenario",
+    "wallet.keystore",
+    "api.addr",
+    "api.tokens_file",
+    "api.admin_token",
+    "api.read_token",
+    "api.public_read",
+    "log.format",
+    "log.filter",
+];
+
+/// The environment variable overriding a setting
+pub fn env_var(key: &str) -> String {
+    format!("BITCOIN_{}", key.replace('.', "_").to_uppercase())
+}
+
+fn parse<T>(key: &str, value: &str) -> Result<T, String>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    value.parse::<T>().map_err(|e| format!("error parsing {} {:?}: {}", key, value, e))
+}
+
+// an empty value unsets an optional setting
+fn parse_optional<T>(key: &str, value: &str) -> Result<Option<T>, String>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if value.is_empty() {
+        Ok(None)
+    } else {
+        parse(key, value).map(Some)
+    }
+}
+
+fn parse_list<T>(key: &str, value: &str) -> Result<Vec<T>, String>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    value.split(',').map(str::trim).filter(|item| !item.is_empty()).map(|item| parse(key, item)).collect()
+}
+
+impl Config {
+    /// Read a config file, as JSON if its name ends in `.json` and as TOML otherwise
+    pub fn load(path: &Path) -> Result<Self, String> {
+        read_file(path)
+    }
+
+    /// Override one setting, named as in `KEYS`, with a value given as text
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let value = value.trim();
+        match key {
+            "network.p2p_addr" => self.network.p2p_addr = parse(key, value)?,
+            "network.connect" => self.network.connect = parse_list(key, value)?,
+            "network.workers" => self.network.workers = parse(key, value)?,
+            "network.message_buffer" => self.network.message_buffer = parse(key, value)?,
+            "network.simulate_links" => self.network.simulate_links = parse(key, value)?,
+            "consensus.chain_spec" => self.consensus.chain_spec = parse_optional(key, value)?,
+            "consensus.import" => self.consensus.import = parse_optional(key, value)?,
+            "mining.threads" => self.mining.threads = parse(key, value)?,
+            "mining.block_size_limit" => self.mining.block_size_limit = parse(key, value)?,
+            "mining.mode" => self.mining.mode = parse(key, value)?,
+            "mining.seed" => self.mining.seed = parse_optional(key, value)?,
+            "mempool.max_transactions" => self.mempool.max_transactions = parse_optional(key, value)?,
+            "generator.scenario" => self.generator.scenario = parse_optional(key, value)?,
+            "wallet.keystore" => self.wallet.keystore = parse_optional(key, value)?,
+            "api.addr" => self.api.addr = parse(key, value)?,
+            "api.tokens_file" => self.api.tokens_file = parse_optional(key, value)?,
+            "api.admin_token" => self.api.admin_token = parse_optional(key, value)?,
+            "api.read_token" => self.api.read_token = parse_optional(key, value)?,
+            "api.public_read" => self.api.public_read = parse(key, value)?,
+            "log.format" => self.log.format = parse(key, value)?,
+            "log.filter" => self.log.filter = parse_optional(key, value)?,
+            _ => return Err(format!("unknown setting {}", key)),
+        }
+        Ok(())
+    }
+
+    /// Check the settings that parse but cannot work, such as a node without P2P workers
+    /// or a message buffer of zero, which would stall the peers' readers
+    pub fn validate(&self) -> Result<(), String> {
+        let counts = [
+            ("network.workers", Some(self.network.workers)),
+            ("network.message_buffer", Some(self.network.message_buffer)),
+            ("mining.threads", Some(self.mining.threads)),
+            ("mining.block_size_limit", Some(self.mining.block_size_limit)),
+            ("mempool.max_transactions", self.mempool.max_transactions),
+        ];
+        if let Some((key, _)) = counts.iter().find(|(_, count)| *count == Some(0)) {
+            return Err(format!("{} must be at least 1", key));
+        }
+        Ok(())
+    }
+
+    /// Apply the `BITCOIN_*` environment variables that are set
+    pub fn apply_env(&mut self) -> Result<(), String> {
+        for key in KEYS {
+            if let Ok(value) = env::var(env_var(key)) {
+                self.set(key, &value).map_err(|e| format!("{} (from {})", e, env_var(key)))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The config with every API token hidden, to be shown to people
+    pub fn redacted(&self) -> Self {
+        let hide = |token: &mut String| *token = "<redacted>".to_string();
+        let mut config = self.clone();
+        config.api.tokens.iter_mut().for_each(|token| hide(&mut token.token));
+        config.api.admin_token.iter_mut().for_each(hide);
+        config.api.read_token.iter_mut().for_each(hide);
+        config
+    }
+
+    /// The config in TOML, as a config file would have it. Unset settings are left out.
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).unwrap()
+    }
+}
+
+impl ApiConfig {
+    /// Who may use the API: the tokens from the tokens file, the config and the token settings
+    pub fn auth(&self) -> Result<Auth, String> {
+        let mut auth = match &self.tokens_file {
+            Some(path) => Auth::load(path).map_err(|e| format!("error loading API tokens {}: {}", path.display(), e))?,
+            None => Auth::default(),
+        };
+        auth.tokens.extend(self.tokens.iter().cloned());
+        if let Some(token) = &self.admin_token {
+            auth.add_token("admin", token, Scope::Admin);
+        }
+        if let Some(token) = &self.read_token {
+            auth.add_token("read", token, Scope::Read);
+        }
+        auth.public_read |= self.public_read;
+        if let Some(token) = auth.tokens.iter().find(|token| token.token.is_empty()) {
+            return Err(format!("API token {:?} is empty", token.name));
+        }
+        Ok(auth)
+    }
+}
+
+/// Read a TOML file, or a JSON one if its name ends in `.json`, into `T`
+pub(crate) fn read_file<T: DeserializeOwned>(path: &Path) -> Result<T, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("error reading {}: {}", path.display(), e))?;
+    if path.extension().is_some_and(|extension| extension == "json") {
+        serde_json::from_str(&text).map_err(|e| format!("error in {}: {}", path.display(), e))
+    } else {
+        toml::from_str(&text).map_err(|e| format!("error in {}: {}", path.display(), e))
+    }
+}
+
+/// Settings kept as their string form, like the mining mode or a hash in hex
+pub(crate) mod string {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+    use std::fmt::Display;
+    use std::str::FromStr;
+
+    pub fn serialize<T: Display, S: Serializer>(value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(value)
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: FromStr,
+        T::Err: Display,
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?.parse().map_err(de::Error::custom)
+    }
+}
+
+/// Wide integers, like balances, kept as integers where they fit in 64 bits, which is
+/// as wide as TOML goes, and as strings where they don't
+pub(crate) mod integer {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+    use std::convert::TryFrom;
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Integer {
+        Number(u64),
+        Text(String),
+    }
+
+    pub fn serialize<S: Serializer>(value: &u128, serializer: S) -> Result<S::Ok, S::Error> {
+        match u64::try_from(*value) {
+            Ok(value) => serializer.serialize_u64(value),
+            Err(_) => serializer.collect_str(value),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u128, D::Error> {
+        match Integer::deserialize(deserializer)? {
+            Integer::Number(value) => Ok(value as u128),
+            Integer::Text(text) => text.parse().map_err(de::Error::custom),
+        }
+    }
+}
+
+// DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. BEFORE TEST
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn load_and_override() {
+        let dir = env::temp_dir().join(format!("bitcoin-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("node.toml");
+        fs::write(&path, r#"
+[network]
+p2p_addr = "127.0.0.1:6001"
+connect = ["127.0.0.1:6000"]
+
+[mining]
+mode = "instant"
+block_size_limit = 5
+
+[[api.tokens]]
+name = "ops"
+token = "secret"
+scope = "admin"
+"#).unwrap();
+        let mut config = Config::load(&path).unwrap();
+        assert_eq!(config.network.p2p_addr, "127.0.0.1:6001".parse().unwrap());
+        assert_eq!(config.network.connect, vec!["127.0.0.1:6000".parse().unwrap()]);
+        assert_eq!(config.network.workers, 4);
+        assert_eq!(config.mining.mode, MiningMode::Instant);
+        assert_eq!(config.mining.block_size_limit, 5);
+        assert_eq!(config.api.auth().unwrap().check(Some("Bearer secret"), Scope::Admin).unwrap().as_deref(), Some("ops"));
+
+        config.set("mining.seed", "42").unwrap();
+        config.set("network.connect", "127.0.0.1:6002, 127.0.0.1:6003").unwrap();
+        config.set("api.admin_token", "12345").unwrap();
+        assert_eq!(config.mining.seed, Some(42));
+        assert_eq!(config.network.connect.len(), 2);
+        assert!(config.set("mining.threads", "many").is_err());
+        assert!(config.set("mining.speed", "1").is_err());
+        assert!(config.validate().is_ok());
+        config.set("network.message_buffer", "0").unwrap();
+        assert_eq!(config.validate(), Err("network.message_buffer must be at least 1".to_string()));
+        config.set("network.message_buffer", "100").unwrap();
+        assert_eq!(env_var("network.p2p_addr"), "BITCOIN_NETWORK_P2P_ADDR");
+
+        // the printed config reads back the same, without its tokens
+        let printed = config.redacted().to_toml();
+        assert!(!printed.contains("secret") && !printed.contains("12345"));
+        let json_path = dir.join("node.json");
+        fs::write(&json_path, serde_json::to_string(&config).unwrap()).unwrap();
+        assert_eq!(Config::load(&json_path).unwrap(), config);
+        fs::write(&path, config.to_toml()).unwrap();
+        assert_eq!(Config::load(&path).unwrap(), config);
+        config.set("consensus.chain_spec", "testnet.toml").unwrap();
+        assert!(config.to_toml().contains("chain_spec = \"testnet.toml\""));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
+
+// DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. AFTER TEST
@@ -0,0 +1,127 @@
+use crossbeam::channel::{self, Receiver, Sender, TrySendError};
+use log::debug;
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+
+// how many events a subscriber may fall behind before it is dropped
+const SUBSCRIBER_BUFFER: usize = 1024;
+
+/// Something that happened in the node
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    NewTip { hash: String, height: u64 },
+    BlockConnected { hash: String, height: u64 },       // joined the longest chain
+    BlockDisconnected { hash: String, height: u64 },    // left the longest chain in a reorg
+    TransactionAdded { hash: String },                  // entered the mempool
+    TransactionRemoved { hash: String },                // left the mempool, usually into a block
+    PeerConnected { addr: String },
+    PeerDisconnected { addr: String },
+}
+
+impl Event {
+    /// The name of the event, as in its `type` field
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Event::NewTip { .. } => "new_tip",
+            Event::BlockConnected { .. } => "block_connected",
+            Event::BlockDisconnected { .. } => "block_disconnected",
+            Event::TransactionAdded { .. } => "transaction_added",
+            Event::TransactionRemoved { .. } => "transaction_removed",
+            Event::PeerConnected { .. } => "peer_connected",
+            Event::PeerDisconnected { .. } => "peer_disconnected",
+        }
+    }
+
+    pub const KINDS: &'static [&'static str] = &[
+        "new_tip",
+        "block_connected",
+        "block_disconnected",
+        "transaction_added",
+        "transaction_removed",
+        "peer_connected",
+        "peer_disconnected",
+    ];
+}
+
+/// Delivers events to every subscriber, numbered in the order they were published.
+/// Publishing never blocks: a subscriber that falls too far behind is dropped,
+/// which ends its stream.
+#[derive(Debug, Clone, Default)]
+pub struct EventBus {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    next_id: u64,
+    subscribers: Vec<Sender<(u64, Event)>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn subscribe(&self) -> Receiver<(u64, Event)> {
+        let (sender, receiver) = channel::bounded(SUBSCRIBER_BUFFER);
+        self.inner.lock().unwrap().subscribers.push(sender);
+        receiver
+    }
+
+    pub fn publish(&self, event: Event) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.subscribers.is_empty() {
+            return;
+        }
+        inner.next_id += 1;
+        let id = inner.next_id;
+        inner.subscribers.retain(|subscriber| match subscriber.try_send((id, event.clone())) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                debug!("Dropping an event subscriber that fell behind");
+                false
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+    }
+
+    /// Drop every subscriber, ending their streams, e.g. when the node shuts down
+    pub fn close(&self) {
+        self.inner.lock().unwrap().subscribers.clear();
+    }
+}
+
+// DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. BEFORE TEST
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn publish_to_subscribers() {
+        let bus = EventBus::new();
+        bus.publish(Event::PeerConnected { addr: "nobody is listening".to_string() });
+
+        let first = bus.subscribe();
+        let second = bus.subscribe();
+        bus.publish(Event::TransactionAdded { hash: "00".to_string() });
+        drop(second);
+        bus.publish(Event::TransactionRemoved { hash: "00".to_string() });
+
+        assert_eq!(first.try_recv().unwrap(), (1, Event::TransactionAdded { hash: "00".to_string() }));
+        assert_eq!(first.try_recv().unwrap(), (2, Event::TransactionRemoved { hash: "00".to_string() }));
+        assert!(first.try_recv().is_err());
+        assert_eq!(bus.inner.lock().unwrap().subscribers.len(), 1);
+
+        // a subscriber that stops reading is dropped once its buffer is full
+        for _ in 0..=SUBSCRIBER_BUFFER {
+            bus.publish(Event::NewTip { hash: "00".to_string(), height: 1 });
+        }
+        assert!(bus.inner.lock().unwrap().subscribers.is_empty());
+        assert_eq!(serde_json::to_string(&Event::NewTip { hash: "00".to_string(), height: 1 }).unwrap(),
+                   r#"{"type":"new_tip","hash":"00","height":1}"#);
+    }
+}
+
+// DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. AFTER TEST
@@ -1,17 +1,13 @@
-use crossbeam::channel::{unbounded, Receiver, Sender, TryRecvError};
-use log::info;
-use std::{
-    time,
-    sync::{Arc, Mutex},
-    thread,
-};
+use crossbeam::channel::Receiver;
+use log::{debug, info};
+use std::thread;
 use crate::{
+    miner::Handle as MinerHandle,
     network::server::Handle as ServerHandle,
     network::message::Message,
     types::{
         hash::Hashable,
         transaction::SignedTransaction,
-        mempool::Mempool,
     },
 };
 
@@ -20,47 +16,44 @@ use crate::{
 pub struct TransactionGenerator {
     server: ServerHandle,
     finished_txn_chan: Receiver<SignedTransaction>,
-    mempool: Arc<Mutex<Mempool>>
+    miner: MinerHandle
 }
 
 impl TransactionGenerator {
     pub fn new(
         server: &ServerHandle,
         finished_txn_chan: Receiver<SignedTransaction>,
-        mempool: &Arc<Mutex<Mempool>>
+        miner: &MinerHandle
     ) -> Self {
         Self {
             server: server.clone(),
-            finished_txn_chan: finished_txn_chan,
-            mempool: Arc::clone(mempool)
+            finished_txn_chan,
+            miner: miner.clone()
         }
     }
 
-    pub fn start(self) {
-        thread::Builder::new()
+    pub fn start(self) -> thread::JoinHandle<()> {
+        let handle = thread::Builder::new()
             .name("transaction-generator".to_string())
             .spawn(move || {
                 self.generate_transactions();
             })
             .unwrap();
         info!("Transaction generator started");
+        handle
     }
 
+    /// Announce the generated transactions, which the generator has already added to the
+    /// mempool, until the generator has exited and every transaction is handled
     fn generate_transactions(&self) {
-        loop {
-            // Receive transaction from channel
-            let txn = self.finished_txn_chan.recv()
-                .expect("Error in getting finished transaction");
-            
-            // Insert this transaction into mempool
-            let mut mempool = self.mempool.lock().unwrap();
-            mempool.map.insert(txn.hash(), txn.clone());    // insert txn into mempool
-            println!("Inserted transaction into mempool");
-            drop(mempool);
+        while let Ok(txn) = self.finished_txn_chan.recv() {
+            let hash = txn.hash();
+            debug!(hash:%; "Announcing generated transaction");
+            self.miner.update();
             
             // Broadcast transaction hash as a NewTransactionHashes message
-            let hash = vec![txn.hash()];
-            self.server.broadcast(Message::NewTransactionHashes(hash));
+            self.server.broadcast(Message::NewTransactionHashes(vec![hash]));
         }
+        debug!("Transaction generator worker exited");
     }
 }
@@ -1,27 +1,29 @@
+#[allow(clippy::module_inception)]
 pub mod generator;
+pub mod scenario;
 
 use log::info;
-use crossbeam::channel::{unbounded, Receiver, Sender, TryRecvError};
-use rand::Rng;
+use crossbeam::channel::{unbounded, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use rand::{rngs::StdRng, Rng};
 use std::{
     sync::{Arc, Mutex},
-    time::{SystemTime, UNIX_EPOCH},
-    time,
     thread,
 };
 use crate::blockchain::Blockchain;
+use crate::metrics::Metrics;
+use crate::simulation::{clock::{self, Clock}, Simulation};
 use crate::types::{
     transaction,
     transaction::{SignedTransaction, Transaction},
     mempool::Mempool,
     address::Address,
-    key_pair,
 };
-use ring::signature::{Ed25519KeyPair, KeyPair};
+use scenario::{Account, Scenario, TransactionKind};
 
 enum ControlSignal {
     Start(u64), // the number controls the theta of interval between transaction generation
     Update, // update the transaction in generation (not sure if necessary)
+    SetScenario(Scenario),
     Exit,
 }
 
@@ -38,6 +40,12 @@ pub struct Context {
     finished_txn_chan: Sender<SignedTransaction>,
     mempool: Arc<Mutex<Mempool>>,
     blockchain: Arc<Mutex<Blockchain>>,
+    rng: StdRng,
+    scenario: Scenario,
+    accounts: Vec<Account>,
+    clock: Arc<dyn Clock>,
+    next_send: f64,     // when the next transaction is due on the clock, in milliseconds
+    metrics: Metrics,
 }
 
 #[derive(Clone)]
@@ -46,7 +54,10 @@ pub struct Handle {
     control_chan: Sender<ControlSignal>,
 }
 
-pub fn new(blockchain: &Arc<Mutex<Blockchain>>, mempool: &Arc<Mutex<Mempool>>) -> (Context, Handle, Receiver<SignedTransaction>) {
+// the generator's random number stream in a simulation
+const RNG_STREAM: u64 = 2;
+
+pub fn new(blockchain: &Arc<Mutex<Blockchain>>, mempool: &Arc<Mutex<Mempool>>, simulation: &Simulation, scenario: Scenario) -> (Context, Handle, Receiver<SignedTransaction>) {
     let (signal_chan_sender, signal_chan_receiver) = unbounded();
     let (finished_txn_sender, finished_txn_receiver) = unbounded();
 
@@ -55,7 +66,13 @@ pub fn new(blockchain: &Arc<Mutex<Blockchain>>, mempool: &Arc<Mutex<Mempool>>) -
         operating_state: OperatingState::Paused,
         finished_txn_chan: finished_txn_sender,
         mempool: Arc::clone(mempool),
-        blockchain: Arc::clone(blockchain)
+        blockchain: Arc::clone(blockchain),
+        rng: simulation.rng(RNG_STREAM),
+        accounts: (0..scenario.num_accounts).map(Account::new).collect(),
+        scenario,
+        next_send: simulation.clock().now_millis() as f64,
+        clock: simulation.clock(),
+        metrics: Metrics::default(),
     };
 
     let handle = Handle {
@@ -66,8 +83,10 @@ pub fn new(blockchain: &Arc<Mutex<Blockchain>>, mempool: &Arc<Mutex<Mempool>>) -
 }
 
 impl Handle {
+    /// Stop generating for good, ending the generator thread
     pub fn exit(&self) {
-        self.control_chan.send(ControlSignal::Exit).unwrap();
+        // the generator may already have shut down
+        let _ = self.control_chan.send(ControlSignal::Exit);
     }
 
     pub fn start(&self, lambda: u64) {
@@ -79,17 +98,30 @@ impl Handle {
     pub fn update(&self) {
         self.control_chan.send(ControlSignal::Update).unwrap();
     }
+
+    /// Switch to a new scenario, unless it is invalid
+    pub fn set_scenario(&self, scenario: Scenario) -> Result<(), String> {
+        scenario.validate()?;
+        self.control_chan.send(ControlSignal::SetScenario(scenario)).unwrap();
+        Ok(())
+    }
 }
 
 impl Context {
-    pub fn start(mut self) {
-        thread::Builder::new()
+    /// Count the generated transactions by kind in `metrics`
+    pub fn set_metrics(&mut self, metrics: Metrics) {
+        self.metrics = metrics;
+    }
+
+    pub fn start(mut self) -> thread::JoinHandle<()> {
+        let handle = thread::Builder::new()
             .name("generator".to_string())
             .spawn(move || {
                 self.generator_loop();
             })
             .unwrap();
         info!("Transaction Generator initialized into paused mode");
+        handle
     }
 
     fn generator_loop(&mut self) {
@@ -99,40 +131,14 @@ impl Context {
             match self.operating_state {
                 OperatingState::Paused => {
                     let signal = self.control_chan.recv().unwrap();
-                    match signal {
-                        ControlSignal::Exit => {
-                            info!("Transaction Generator shutting down");
-                            self.operating_state = OperatingState::ShutDown;
-                        }
-                        ControlSignal::Start(i) => {
-                            info!("Transaction Generator starting in continuous mode with theta {}", i);
-                            self.operating_state = OperatingState::Run(i);
-                        }
-                        ControlSignal::Update => {
-                            // in paused state, don't need to update
-                        }
-                    };
+                    self.apply_signal(signal);
                     continue;
                 }
                 OperatingState::ShutDown => {
                     return;
                 }
                 _ => match self.control_chan.try_recv() {
-                    Ok(signal) => {
-                        match signal {
-                            ControlSignal::Exit => {
-                                info!("Transaction Generator shutting down");
-                                self.operating_state = OperatingState::ShutDown;
-                            }
-                            ControlSignal::Start(i) => {
-                                info!("Transaction Generator starting in continuous mode with theta {}", i);
-                                self.operating_state = OperatingState::Run(i);
-                            }
-                            ControlSignal::Update => {
-                                unimplemented!()
-                            }
-                        };
-                    }
+                    Ok(signal) => self.apply_signal(signal),
                     Err(TryRecvError::Empty) => {}
                     Err(TryRecvError::Disconnected) => panic!("Transaction Generator control channel detached"),
                 },
@@ -142,80 +148,200 @@ impl Context {
             }
 
             // Begin actual transaction generation process
-            println!("Starting the Transaction Generation Process...");
-
-            // Get current tip of blockchain to find parent_state 
-            let blockchain = self.blockchain.lock().unwrap();
-            let parent_hash = blockchain.tip();
-            let parent_state = match blockchain.get_state(&parent_hash) {
-                Ok(state) => state.clone(),
-                Err(_) => panic!("Parent node does not exist in blockchain.")
-            };
-            drop(blockchain);
-
-            let mut rng = rand::thread_rng();
-
-            // Choose a random sender
-            let sender_seed = rng.gen_range(0..3);     // random seed from {1,2,3}
-            let sender_key = Ed25519KeyPair::from_seed_unchecked(&[sender_seed;32]).unwrap();
-            let sender_public_key = sender_key.public_key().as_ref().to_vec();
-            let sender_address = Address::from_public_key_bytes(&sender_public_key);
+            for signed_transaction in self.generate() {
+                // Send signed transaction to channel
+                self.finished_txn_chan.send(signed_transaction).expect("Sending to finished_txn_chan resulted in error.");
+            }
 
-            // Get the chosen sender's info from parent state
-            let sender_info = parent_state.map[&sender_address];
-            let sender_nonce = sender_info.0;
-            let sender_balance = sender_info.1;
+            self.pace();
+        }
+    }
 
-            // Skip if the chosen sender has no balance
-            if sender_balance == 0 {
-                continue;
+    fn apply_signal(&mut self, signal: ControlSignal) {
+        match signal {
+            ControlSignal::Exit => {
+                info!("Transaction Generator shutting down");
+                self.operating_state = OperatingState::ShutDown;
+            }
+            ControlSignal::Start(i) => {
+                info!("Transaction Generator starting in continuous mode with theta {}", i);
+                self.operating_state = OperatingState::Run(i);
             }
-            
-            // Choose a random receiver
-            let mut receiver_seed = rng.gen_range(0..3);     // random seed from {1,2,3}
-            while receiver_seed == sender_seed {        
-                receiver_seed = rng.gen_range(0..3);     // ensure receiver is different from sender
+            ControlSignal::Update => {
+                // every transaction is generated from the current state anyway
             }
-            let receiver_key = Ed25519KeyPair::from_seed_unchecked(&[receiver_seed;32]).unwrap();
-            let receiver_public_key = receiver_key.public_key().as_ref().to_vec();
-            let receiver_address = Address::from_public_key_bytes(&receiver_public_key);
-
-            // Choose a random value for the transaction
-            // value must be between [1, balance/2]
-            let max_value = sender_balance / 2;
-            if max_value <= 1 {
-                continue;
+            ControlSignal::SetScenario(scenario) => {
+                self.set_scenario(scenario);
             }
-            let value = rng.gen_range(1..max_value);
+        }
+    }
 
-            // Form the transaction
-            let transaction = Transaction {
-                account_nonce: sender_nonce + 1,    // increment previous nonce
-                receiver: receiver_address, 
-                value: value
+    /// Wait until the clock reaches `deadline` milliseconds, reacting to control signals
+    /// meanwhile, unless the generator stops running first
+    fn wait(&mut self, deadline: f64) {
+        while let OperatingState::Run(_) = self.operating_state {
+            let timeout = match clock::until(self.clock.as_ref(), deadline) {
+                Some(timeout) => timeout,
+                None => return,
             };
+            match self.control_chan.recv_timeout(timeout) {
+                Ok(signal) => self.apply_signal(signal),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => panic!("Transaction Generator control channel detached"),
+            }
+        }
+    }
 
-            // Sign the transaction
-            let signature = transaction::sign(&transaction, &sender_key).as_ref().to_vec();
+    /// Generate the next transactions according to the scenario, and add them to the
+    /// mempool. This is usually one transaction, none if no account can afford one, or two
+    /// for a double spend.
+    fn generate(&mut self) -> Vec<SignedTransaction> {
+        // Work out what every account will have once its transactions in the mempool are
+        // confirmed, holding the blockchain and then the mempool lock until the new
+        // transactions are in the mempool too, so the next ones follow on from them
+        let blockchain = Arc::clone(&self.blockchain);
+        let blockchain = blockchain.lock().unwrap();
+        let parent_hash = blockchain.tip();
+        let parent_state = match blockchain.get_state(&parent_hash) {
+            Ok(state) => state,
+            Err(_) => panic!("Parent node does not exist in blockchain."),
+        };
+        let mempool = Arc::clone(&self.mempool);
+        let mut mempool = mempool.lock().unwrap();
+        let confirmed: Vec<(u128, u128)> = self.accounts
+            .iter()
+            .map(|account| parent_state.map.get(&account.address).copied().unwrap_or((0, 0)))
+            .collect();
+        let pending: Vec<(u128, u128)> = self.accounts
+            .iter()
+            .zip(confirmed.iter())
+            .map(|(account, confirmed)| mempool.pending_account(&account.address, *confirmed))
+            .collect();
+        let transactions = self.pick_transactions(&confirmed, &pending);
+        for txn in transactions.iter() {
+            mempool.insert(txn.clone());
+        }
+        transactions
+    }
 
-            // Form the signed transaction
-            let signed_transaction = SignedTransaction {
-                transaction: transaction, 
-                signature: signature, 
-                public_key: sender_public_key
-            };
+    // the transactions to generate, given every account's confirmed and pending (nonce, balance)
+    fn pick_transactions(&mut self, confirmed: &[(u128, u128)], pending: &[(u128, u128)]) -> Vec<SignedTransaction> {
+        let rng = &mut self.rng;
+        let scenario = &self.scenario;
+        let accounts = &self.accounts;
 
-            println!("Transaction generated!");
-   
-            // Send signed transaction to channel
-            self.finished_txn_chan.send(signed_transaction.clone()).expect("Sending to finished_txn_chan resulted in error.");
-            
-            if let OperatingState::Run(i) = self.operating_state {
-                if i != 0 {
-                    let interval = time::Duration::from_micros(i * 200 as u64);
-                    thread::sleep(interval);
+        // Only accounts with a balance left can send
+        let funded: Vec<usize> = (0..accounts.len()).filter(|i| pending[*i].1 > 0).collect();
+        let sender_index = match scenario.pick_sender(rng, &funded) {
+            Some(sender_index) => sender_index,
+            None => return Vec::new(),
+        };
+        let (sender_nonce, sender_balance) = pending[sender_index];
+        let value = match scenario.pick_value(rng, sender_balance) {
+            Some(value) => value,
+            None => return Vec::new(),
+        };
+        let receiver_index = scenario.pick_receiver(rng, sender_index);
+
+        let sender = &accounts[sender_index];
+        let receiver = accounts[receiver_index].address;
+        let kind = scenario.pick_kind(rng);
+        self.metrics.inc("bitcoin_transactions_generated_total", &[("kind", kind.name())]);
+        match kind {
+            TransactionKind::Valid => {
+                vec![sign(sender, sender_nonce + 1, receiver, value)]
+            }
+            TransactionKind::DoubleSpend => {
+                // two transactions with the same nonce, at most one of which can be included.
+                // The second one pays someone else, or the sender if there is nobody else left.
+                let mut other_receiver_index = scenario.pick_receiver(rng, sender_index);
+                if other_receiver_index == receiver_index {
+                    other_receiver_index = sender_index;
                 }
+                vec![
+                    sign(sender, sender_nonce + 1, receiver, value),
+                    sign(sender, sender_nonce + 1, accounts[other_receiver_index].address, value),
+                ]
+            }
+            TransactionKind::BadNonce => {
+                // either replay a confirmed nonce or skip ahead, leaving a gap
+                let confirmed_nonce = confirmed[sender_index].0;
+                let nonce = if confirmed_nonce > 0 && rng.gen_bool(0.5) {
+                    rng.gen_range(1..=confirmed_nonce)
+                } else {
+                    sender_nonce + rng.gen_range(2..10)
+                };
+                vec![sign(sender, nonce, receiver, value)]
             }
+            TransactionKind::BadSignature => {
+                let mut signed_transaction = sign(sender, sender_nonce + 1, receiver, value);
+                let i = rng.gen_range(0..signed_transaction.signature.len());
+                signed_transaction.signature[i] ^= 0xff;
+                vec![signed_transaction]
+            }
+        }
+    }
+
+    /// Wait before generating the next transactions, at the scenario's target rate if it
+    /// has one and at the rate set by theta otherwise
+    fn pace(&mut self) {
+        // in milliseconds on the simulation's clock, so a run under a manual clock is reproducible
+        let interval = match (self.scenario.tps, &self.operating_state) {
+            (Some(tps), _) => 1000.0 / tps,
+            (None, OperatingState::Run(i)) if *i != 0 => *i as f64 * 0.2,
+            _ => return,
+        };
+        // due times add up, so intervals under the clock's millisecond still keep the rate,
+        // but don't try to catch up on time spent paused or blocked
+        let now = self.clock.now_millis() as f64;
+        self.next_send = f64::max(self.next_send + interval, now);
+        self.wait(self.next_send);
+    }
+
+    fn set_scenario(&mut self, scenario: Scenario) {
+        info!("Transaction Generator switching to scenario with {} accounts", scenario.num_accounts);
+        self.accounts = (0..scenario.num_accounts).map(Account::new).collect();
+        self.scenario = scenario;
+    }
+}
+
+/// Sign a transaction from the given account
+fn sign(sender: &Account, account_nonce: u128, receiver: Address, value: u128) -> SignedTransaction {
+    let transaction = Transaction { account_nonce, receiver, value };
+    let signature = transaction::sign(&transaction, &sender.key).as_ref().to_vec();
+    SignedTransaction {
+        transaction,
+        signature,
+        public_key: sender.public_key.clone()
+    }
+}
+
+// DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. BEFORE TEST
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::blockchain::GENESIS_BALANCE;
+    use scenario::ValueDistribution;
+
+    #[test]
+    fn generate_sequential_transactions() {
+        let blockchain = Arc::new(Mutex::new(Blockchain::new()));
+        let mempool = Arc::new(Mutex::new(Mempool::new()));
+        let scenario = Scenario { value: ValueDistribution::Fixed { value: 10 }, ..Default::default() };
+        let (mut ctx, _handle, _finished) = new(&blockchain, &mempool, &Simulation::seeded(1), scenario);
+        let sender = ctx.accounts[0].address;
+
+        // only the first account is funded until a block confirms what it sends, and each
+        // transaction follows on from the ones already in the mempool
+        for nonce in 1..=20 {
+            let txns = ctx.generate();
+            assert_eq!(txns.len(), 1);
+            assert_eq!(txns[0].transaction.account_nonce, nonce);
         }
+        let mempool = mempool.lock().unwrap();
+        assert_eq!(mempool.pending_account(&sender, (0, GENESIS_BALANCE)), (20, GENESIS_BALANCE - 200));
     }
-}
\ No newline at end of file
+}
+
+// DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. AFTER TEST
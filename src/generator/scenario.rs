@@ -0,0 +1,292 @@
+use rand::Rng;
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use serde::{Serialize, Deserialize};
+use crate::types::address::Address;
+
+// the slowest and fastest target rates a scenario can set, in transactions per second
+pub const MIN_TPS: f64 = 0.001;
+pub const MAX_TPS: f64 = 1_000_000.0;
+
+// A Scenario describes the load the transaction generator puts on the network: which
+// accounts it controls, how much they send, how fast, and how much of it is invalid.
+// Every field has a default, so a scenario file only needs the fields it changes.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scenario {
+    pub num_accounts: usize,
+    pub value: ValueDistribution,
+    pub tps: Option<f64>,       // target transactions per second, overriding theta when set
+    pub hot_spot: Option<HotSpot>,
+    pub invalid: InvalidMix,
+}
+
+/// How the value of a transaction is chosen. Configured values are u64, since serde
+/// cannot read u128 fields of internally tagged enums.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ValueDistribution {
+    Fixed { value: u64 },
+    Uniform { min: u64, max: u64 },
+    /// uniform between 1 and this fraction of the sender's balance
+    FractionOfBalance { max_fraction: f64 },
+}
+
+/// A few accounts that send most of the transactions
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct HotSpot {
+    pub senders: usize,     // the first `senders` accounts are hot
+    pub probability: f64,   // chance that a transaction comes from a hot account
+}
+
+/// The share of transactions that are deliberately invalid, by kind
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct InvalidMix {
+    pub double_spend: f64,
+    pub bad_nonce: f64,
+    pub bad_signature: f64,
+}
+
+/// What kind of transaction to generate next
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransactionKind {
+    Valid,
+    /// reuses the nonce of an earlier transaction from the same sender
+    DoubleSpend,
+    /// uses a nonce that is already spent or leaves a gap
+    BadNonce,
+    /// is signed correctly, then has its signature corrupted
+    BadSignature,
+}
+
+impl TransactionKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TransactionKind::Valid => "valid",
+            TransactionKind::DoubleSpend => "double_spend",
+            TransactionKind::BadNonce => "bad_nonce",
+            TransactionKind::BadSignature => "bad_signature",
+        }
+    }
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        Self {
+            num_accounts: 3,
+            value: ValueDistribution::FractionOfBalance { max_fraction: 0.5 },
+            tps: None,
+            hot_spot: None,
+            invalid: InvalidMix::default(),
+        }
+    }
+}
+
+impl Scenario {
+    /// Check that the scenario can be run, describing the first problem otherwise
+    pub fn validate(&self) -> Result<(), String> {
+        if self.num_accounts < 2 {
+            return Err("a scenario needs at least 2 accounts".to_string());
+        }
+        match self.value {
+            ValueDistribution::Fixed { value: 0 } => {
+                return Err("fixed value must be positive".to_string());
+            }
+            ValueDistribution::Uniform { min, max } if min == 0 || min > max => {
+                return Err("uniform value range must satisfy 0 < min <= max".to_string());
+            }
+            ValueDistribution::FractionOfBalance { max_fraction } if !(max_fraction > 0.0 && max_fraction <= 1.0) => {
+                return Err("max_fraction must be in (0, 1]".to_string());
+            }
+            _ => {}
+        }
+        if let Some(tps) = self.tps {
+            if !(MIN_TPS..=MAX_TPS).contains(&tps) {
+                return Err(format!("tps must be between {} and {}", MIN_TPS, MAX_TPS));
+            }
+        }
+        if let Some(hot_spot) = &self.hot_spot {
+            if hot_spot.senders == 0 || hot_spot.senders > self.num_accounts {
+                return Err("hot spot senders must be between 1 and num_accounts".to_string());
+            }
+            if !(0.0..=1.0).contains(&hot_spot.probability) {
+                return Err("hot spot probability must be in [0, 1]".to_string());
+            }
+        }
+        let invalid = &self.invalid;
+        let shares = [invalid.double_spend, invalid.bad_nonce, invalid.bad_signature];
+        if shares.iter().any(|share| !(0.0..=1.0).contains(share)) || shares.iter().sum::<f64>() > 1.0 {
+            return Err("invalid transaction shares must be between 0 and 1 and sum to at most 1".to_string());
+        }
+        Ok(())
+    }
+
+    /// Pick a sender among the accounts in `funded`, favouring hot accounts if there are any
+    pub fn pick_sender<R: Rng>(&self, rng: &mut R, funded: &[usize]) -> Option<usize> {
+        if let Some(hot_spot) = &self.hot_spot {
+            let hot: Vec<usize> = funded.iter().copied().filter(|i| *i < hot_spot.senders).collect();
+            if !hot.is_empty() && rng.gen_bool(hot_spot.probability) {
+                return Some(hot[rng.gen_range(0..hot.len())]);
+            }
+        }
+        if funded.is_empty() {
+            return None;
+        }
+        Some(funded[rng.gen_range(0..funded.len())])
+    }
+
+    /// Pick a receiver among all accounts other than the sender
+    pub fn pick_receiver<R: Rng>(&self, rng: &mut R, sender: usize) -> usize {
+        let receiver = rng.gen_range(0..self.num_accounts - 1);
+        if receiver >= sender { receiver + 1 } else { receiver }
+    }
+
+    /// Pick a value the sender can afford, if there is one
+    pub fn pick_value<R: Rng>(&self, rng: &mut R, balance: u128) -> Option<u128> {
+        let value = match self.value {
+            ValueDistribution::Fixed { value } => value as u128,
+            ValueDistribution::Uniform { min, max } => rng.gen_range(min..=max) as u128,
+            ValueDistribution::FractionOfBalance { max_fraction } => {
+                let max_value = (balance as f64 * max_fraction) as u128;
+                if max_value < 1 {
+                    return None;
+                }
+                rng.gen_range(1..=max_value)
+            }
+        };
+        if value > balance { None } else { Some(value) }
+    }
+
+    pub fn pick_kind<R: Rng>(&self, rng: &mut R) -> TransactionKind {
+        let roll: f64 = rng.gen();
+        let invalid = &self.invalid;
+        if roll < invalid.double_spend {
+            TransactionKind::DoubleSpend
+        } else if roll < invalid.double_spend + invalid.bad_nonce {
+            TransactionKind::BadNonce
+        } else if roll < invalid.double_spend + invalid.bad_nonce + invalid.bad_signature {
+            TransactionKind::BadSignature
+        } else {
+            TransactionKind::Valid
+        }
+    }
+}
+
+// An account controlled by the generator
+pub struct Account {
+    pub key: Ed25519KeyPair,
+    pub public_key: Vec<u8>,
+    pub address: Address,
+}
+
+impl Account {
+    /// The account with the given index. The first accounts use the same keys as the
+    /// accounts funded at genesis, seeded with `[index; 32]`.
+    pub fn new(index: usize) -> Self {
+        let seed: [u8; 32] = if index < 256 {
+            [index as u8; 32]
+        } else {
+            let digest = ring::digest::digest(&ring::digest::SHA256, &(index as u64).to_be_bytes());
+            let mut seed = [0; 32];
+            seed.copy_from_slice(digest.as_ref());
+            seed
+        };
+        let key = Ed25519KeyPair::from_seed_unchecked(&seed).unwrap();
+        let public_key = key.public_key().as_ref().to_vec();
+        let address = Address::from_public_key_bytes(&public_key);
+        Self { key, public_key, address }
+    }
+}
+
+// DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. BEFORE TEST
+
+#[cfg(test)]
+mod test {
+    use super::{Scenario, TransactionKind, ValueDistribution, HotSpot, InvalidMix};
+    use crate::simulation::Simulation;
+
+    #[test]
+    fn parse_partial_scenario() {
+        let scenario: Scenario = serde_json::from_str(r#"{
+            "num_accounts": 10,
+            "value": { "kind": "uniform", "min": 1, "max": 5 },
+            "invalid": { "bad_signature": 0.1 }
+        }"#).unwrap();
+        assert_eq!(scenario.num_accounts, 10);
+        assert_eq!(scenario.value, ValueDistribution::Uniform { min: 1, max: 5 });
+        assert_eq!(scenario.invalid.bad_signature, 0.1);
+        assert_eq!(scenario.invalid.double_spend, 0.0);
+        assert_eq!(scenario.tps, None);
+        assert!(scenario.validate().is_ok());
+
+        assert!(serde_json::from_str::<Scenario>(r#"{ "accounts": 10 }"#).is_err());
+    }
+
+    #[test]
+    fn reject_invalid_scenarios() {
+        let mut scenario = Scenario { num_accounts: 1, ..Scenario::default() };
+        assert!(scenario.validate().is_err());
+
+        scenario.num_accounts = 4;
+        scenario.hot_spot = Some(HotSpot { senders: 5, probability: 0.5 });
+        assert!(scenario.validate().is_err());
+
+        scenario.hot_spot = None;
+        scenario.invalid = InvalidMix { double_spend: 0.5, bad_nonce: 0.4, bad_signature: 0.2 };
+        assert!(scenario.validate().is_err());
+        scenario.invalid = InvalidMix { double_spend: f64::NAN, ..InvalidMix::default() };
+        assert!(scenario.validate().is_err());
+
+        scenario.invalid = InvalidMix::default();
+        for tps in [0.0, 1e-30, f64::NAN, f64::INFINITY, 1e12] {
+            scenario.tps = Some(tps);
+            assert!(scenario.validate().is_err());
+        }
+        scenario.tps = Some(0.5);
+        assert!(scenario.validate().is_ok());
+    }
+
+    #[test]
+    fn hot_spot_senders_dominate() {
+        let scenario = Scenario {
+            num_accounts: 10,
+            hot_spot: Some(HotSpot { senders: 2, probability: 0.9 }),
+            ..Scenario::default()
+        };
+        let mut rng = Simulation::seeded(1).rng(0);
+        let funded: Vec<usize> = (0..10).collect();
+        let hot = (0..1000)
+            .filter(|_| scenario.pick_sender(&mut rng, &funded).unwrap() < 2)
+            .count();
+        assert!(hot > 850);
+    }
+
+    #[test]
+    fn receiver_differs_from_sender() {
+        let scenario = Scenario::default();
+        let mut rng = Simulation::seeded(2).rng(0);
+        for sender in 0..3 {
+            for _ in 0..100 {
+                let receiver = scenario.pick_receiver(&mut rng, sender);
+                assert_ne!(receiver, sender);
+                assert!(receiver < 3);
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_mix_shares() {
+        let scenario = Scenario {
+            invalid: InvalidMix { double_spend: 0.2, bad_nonce: 0.2, bad_signature: 0.2 },
+            ..Scenario::default()
+        };
+        let mut rng = Simulation::seeded(3).rng(0);
+        let valid = (0..1000)
+            .filter(|_| scenario.pick_kind(&mut rng) == TransactionKind::Valid)
+            .count();
+        assert!(valid > 320 && valid < 480);
+    }
+}
+
+// DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. AFTER TEST
@@ -0,0 +1,335 @@
+use crate::config::Config;
+use crate::miner::template::BlockTemplateBuilder;
+use crate::network::{conditions::LinkConditions, message::Message};
+use crate::node::{Node, NodeBuilder};
+use crate::simulation::{
+    clock::{Clock, SystemClock},
+    MiningMode,
+};
+use crate::types::{
+    address::Address,
+    hash::{H256, Hashable},
+    transaction::{self, SignedTransaction, Transaction},
+};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// how long `wait_until` waits before failing the test
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How the nodes of a cluster are connected when it starts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Topology {
+    Line,   // each node connects to the one before it
+    Ring,   // a line whose last node also connects to the first
+    Star,   // every node connects to the first
+    Full,   // every node connects to every node before it
+}
+
+impl Topology {
+    /// The links between `n` nodes, as (node that connects, node it connects to)
+    pub fn links(self, n: usize) -> Vec<(usize, usize)> {
+        match self {
+            Topology::Line => (1..n).map(|i| (i, i - 1)).collect(),
+            Topology::Ring if n > 2 => (1..n).map(|i| (i, i - 1)).chain(std::iter::once((0, n - 1))).collect(),
+            Topology::Ring => Topology::Line.links(n),
+            Topology::Star => (1..n).map(|i| (i, 0)).collect(),
+            Topology::Full => (1..n).flat_map(|i| (0..i).map(move |j| (i, j))).collect(),
+        }
+    }
+}
+
+/// Several full nodes in one process, talking over loopback sockets on ephemeral ports,
+/// for tests that drive mining and transactions across a network. Nodes mine instantly
+/// rather than by proof of work, each has its own random seed, and their links are simulated.
+pub struct Cluster {
+    nodes: Vec<Node>,
+    links: Vec<(usize, usize)>,     // the connected links
+    cut: Vec<(usize, usize)>,       // the links a partition cut, until it heals
+    clock: Arc<dyn Clock>,          // the nodes stamp blocks and deliver messages by it
+}
+
+impl Cluster {
+    pub fn start(n: usize, topology: Topology) -> Self {
+        Self::with_config(n, topology, |_, _| {})
+    }
+
+    /// Start a cluster, letting `configure` change each node's config, by index
+    pub fn with_config(n: usize, topology: Topology, configure: impl Fn(usize, &mut Config)) -> Self {
+        Self::build(n, topology, Arc::new(SystemClock), configure)
+    }
+
+    /// Start a cluster whose nodes go by `clock` rather than the wall clock, such as a
+    /// `ManualClock` the test moves, so messages on slow links arrive when the test says
+    pub fn with_clock(n: usize, topology: Topology, clock: Arc<dyn Clock>) -> Self {
+        Self::build(n, topology, clock, |_, _| {})
+    }
+
+    fn build(n: usize, topology: Topology, clock: Arc<dyn Clock>, configure: impl Fn(usize, &mut Config)) -> Self {
+        let nodes = (0..n)
+            .map(|i| {
+                let mut config = Config::default();
+                config.network.p2p_addr = ([127, 0, 0, 1], 0).into();
+                config.api.addr = ([127, 0, 0, 1], 0).into();
+                config.mining.mode = MiningMode::Instant;
+                config.mining.seed = Some(i as u64);
+                config.network.simulate_links = true;
+                configure(i, &mut config);
+                NodeBuilder::new(config)
+                    .clock(Arc::clone(&clock))
+                    .start()
+                    .unwrap_or_else(|e| panic!("error starting node {}: {}", i, e))
+            })
+            .collect();
+        let mut cluster = Self { nodes, links: Vec::new(), cut: Vec::new(), clock };
+        for (i, j) in topology.links(n) {
+            cluster.connect(i, j);
+        }
+        cluster.wait_for_links();
+        cluster
+    }
+
+    pub fn node(&self, i: usize) -> &Node {
+        &self.nodes[i]
+    }
+
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Connect node `i` to node `j`
+    pub fn connect(&mut self, i: usize, j: usize) {
+        let addr = self.nodes[j].p2p_addr();
+        self.nodes[i]
+            .network
+            .connect(addr)
+            .unwrap_or_else(|e| panic!("error connecting node {} to node {}: {}", i, j, e));
+        self.links.push((i, j));
+    }
+
+    /// Simulate conditions on the link from node `i` to node `j`, which `i` applies to
+    /// the messages both ways. The link has to be one `i` connected.
+    pub fn set_link(&self, i: usize, j: usize, link: LinkConditions) {
+        assert!(self.links.contains(&(i, j)), "node {} did not connect to node {}", i, j);
+        let conditions = self.nodes[i].network_conditions.as_ref().expect("the cluster's links are simulated");
+        conditions.set_link(self.nodes[j].p2p_addr(), link);
+    }
+
+    /// Cut every link between nodes of different groups. Nodes in no group are cut off
+    /// from every other node.
+    pub fn partition(&mut self, groups: &[&[usize]]) {
+        let group_of = |i: usize| groups.iter().position(|group| group.contains(&i));
+        let (cut, kept): (Vec<_>, Vec<_>) = self
+            .links
+            .iter()
+            .partition(|(i, j)| group_of(*i).is_none() || group_of(*i) != group_of(*j));
+        for (i, j) in cut.iter() {
+            self.nodes[*i].network.disconnect(self.nodes[*j].p2p_addr());
+        }
+        self.links = kept;
+        self.cut.extend(cut);
+        self.wait_for_links();
+    }
+
+    /// Reconnect the links partitions cut, and have every node announce its tip, as it
+    /// would with its next block, so the nodes catch up on the blocks they missed
+    pub fn heal(&mut self) {
+        for (i, j) in std::mem::take(&mut self.cut) {
+            self.connect(i, j);
+        }
+        self.wait_for_links();
+        for node in self.nodes.iter() {
+            let tip = node.blockchain.lock().unwrap().tip();
+            node.network.broadcast(Message::NewBlockHashes(vec![tip]));
+        }
+    }
+
+    /// Have node `i` mine blocks on its longest chain, from its mempool like its miner
+    /// would but one after another and exactly as many as asked, and announce them to its
+    /// peers. Returns the node's new height.
+    pub fn mine(&self, i: usize, blocks: u64) -> u64 {
+        let node = &self.nodes[i];
+        let template_builder = BlockTemplateBuilder::new(node.miner.status().block_size_limit);
+        for _ in 0..blocks {
+            let mut blockchain = node.blockchain.lock().unwrap();
+            let mut mempool = node.mempool.lock().unwrap();
+            let template = template_builder.build(&blockchain, &mut mempool);
+            let mut header = template.header().clone();
+            header.nonce = rand::random();
+            header.timestamp = self.clock.now_millis();
+            let block = template.seal(header);
+            assert!(blockchain.insert(&block).is_ok(), "node {} rejected its own block", i);
+            for txn in block.content.transactions.iter() {
+                mempool.remove(&txn.hash());
+            }
+            drop(mempool);
+            drop(blockchain);
+            node.network.broadcast(Message::NewBlockHashes(vec![block.hash()]));
+        }
+        // the miner's template is now stale
+        node.miner.update();
+        self.height(i)
+    }
+
+    /// Sign a transaction from the account of the given seed, with the next nonce after
+    /// its pending transactions, and submit it to node `i`, which relays it to its peers.
+    /// The accounts of seeds `[0; 32]` to `[2; 32]` exist at genesis of the built-in chain.
+    pub fn transfer(&self, i: usize, seed: u8, receiver: Address, value: u128) -> H256 {
+        let node = &self.nodes[i];
+        let key = Ed25519KeyPair::from_seed_unchecked(&[seed; 32]).unwrap();
+        let sender = Address::from_public_key_bytes(key.public_key().as_ref());
+        let blockchain = node.blockchain.lock().unwrap();
+        let mut mempool = node.mempool.lock().unwrap();
+        let state = blockchain.get_state(&blockchain.tip()).unwrap();
+        let confirmed = state.map.get(&sender).copied().unwrap_or((0, 0));
+        let (nonce, _) = mempool.pending_account(&sender, confirmed);
+        let transaction = Transaction { account_nonce: nonce + 1, receiver, value };
+        let txn = SignedTransaction {
+            signature: transaction::sign(&transaction, &key).as_ref().to_vec(),
+            public_key: key.public_key().as_ref().to_vec(),
+            transaction,
+        };
+        let hash = mempool.admit(txn, state).unwrap_or_else(|e| panic!("node {} rejected the transaction: {}", i, e));
+        drop(mempool);
+        drop(blockchain);
+        node.miner.update();
+        node.network.broadcast(Message::NewTransactionHashes(vec![hash]));
+        hash
+    }
+
+    pub fn height(&self, i: usize) -> u64 {
+        self.nodes[i].blockchain.lock().unwrap().tip_height()
+    }
+
+    pub fn tips(&self) -> Vec<H256> {
+        self.nodes.iter().map(|node| node.blockchain.lock().unwrap().tip()).collect()
+    }
+
+    /// Wait until every node has the same tip, and return it
+    pub fn wait_for_convergence(&self) -> H256 {
+        self.wait_until("the nodes to agree on a tip", |cluster| {
+            let tips = cluster.tips();
+            tips.iter().all(|tip| *tip == tips[0])
+        });
+        self.tips()[0]
+    }
+
+    /// Wait until every node has a transaction in its mempool
+    pub fn wait_for_mempools(&self, hash: &H256) {
+        self.wait_until(&format!("every mempool to have {}", hash), |cluster| {
+            cluster.nodes.iter().all(|node| node.mempool.lock().unwrap().contains(hash))
+        });
+    }
+
+    /// Poll `condition` until it holds, failing the test if it doesn't in time
+    pub fn wait_until(&self, what: &str, condition: impl Fn(&Cluster) -> bool) {
+        let deadline = Instant::now() + TIMEOUT;
+        while !condition(self) {
+            if Instant::now() >= deadline {
+                let heights: Vec<u64> = (0..self.nodes.len()).map(|i| self.height(i)).collect();
+                panic!("timed out waiting for {}, with the nodes at heights {:?} and tips {:?}", what, heights, self.tips());
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Stop every node
+    pub fn stop(self) {
+        for node in self.nodes {
+            node.stop();
+        }
+    }
+
+    // wait until both ends of every link, and no other, count each other as peers
+    fn wait_for_links(&self) {
+        let mut degrees = vec![0; self.nodes.len()];
+        for (i, j) in self.links.iter() {
+            degrees[*i] += 1;
+            degrees[*j] += 1;
+        }
+        self.wait_until("the links to be set up", |cluster| {
+            cluster.nodes.iter().zip(degrees.iter()).all(|(node, degree)| node.network.peers().len() == *degree)
+        });
+    }
+}
+
+// DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. BEFORE TEST
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::simulation::clock::ManualClock;
+
+    #[test]
+    fn converge_after_partition() {
+        assert_eq!(Topology::Ring.links(3), vec![(1, 0), (2, 1), (0, 2)]);
+        assert_eq!(Topology::Full.links(3).len(), 3);
+
+        let mut cluster = Cluster::start(3, Topology::Line);
+        // blocks only credit accounts that are already in the state, like the genesis ones
+        let key = Ed25519KeyPair::from_seed_unchecked(&[1; 32]).unwrap();
+        let receiver = Address::from_public_key_bytes(key.public_key().as_ref());
+        let hash = cluster.transfer(0, 0, receiver, 25);
+        cluster.wait_for_mempools(&hash);
+        cluster.mine(0, 2);
+        let tip = cluster.wait_for_convergence();
+        for node in cluster.nodes() {
+            let blockchain = node.blockchain.lock().unwrap();
+            assert!(blockchain.get_transaction(&hash).is_some());
+            assert_eq!(blockchain.get_state(&tip).unwrap().map[&receiver], (0, 25));
+            assert!(node.mempool.lock().unwrap().is_empty());
+        }
+
+        // both sides of a partition grow their own branch, until the longer one wins
+        cluster.partition(&[&[0], &[1, 2]]);
+        assert_eq!(cluster.mine(0, 2), 4);
+        assert_eq!(cluster.mine(2, 3), 5);
+        cluster.wait_until("node 1 to follow node 2", |cluster| cluster.tips()[1] == cluster.tips()[2]);
+        let abandoned = cluster.tips()[0];
+        assert!(cluster.node(2).blockchain.lock().unwrap().get_block(&abandoned).is_err());
+        cluster.heal();
+        cluster.wait_for_convergence();
+        assert_eq!(cluster.height(0), 5);
+        let blockchain = cluster.node(0).blockchain.lock().unwrap();
+        assert!(blockchain.get_block(&abandoned).is_ok());
+        assert!(!blockchain.in_longest_chain(&abandoned));
+        drop(blockchain);
+        cluster.stop();
+    }
+
+    #[test]
+    fn delay_blocks_over_slow_link() {
+        let clock = Arc::new(ManualClock::new(1_000_000, 0));
+        let cluster = Cluster::with_clock(2, Topology::Line, clock.clone());
+        cluster.set_link(1, 0, LinkConditions { latency_ms: 300, ..Default::default() });
+        cluster.mine(0, 1);
+        // the announcement, the request for the block and the block each take the latency,
+        // so the block can't arrive before the clock has moved on by three latencies
+        cluster.wait_until("node 1 to get the block", |cluster| {
+            let arrived = cluster.height(1) == 1;
+            if !arrived {
+                clock.advance(10);
+            }
+            arrived
+        });
+        let elapsed = clock.now_millis() - 1_000_000;
+        assert!(elapsed >= 900);
+        let metrics = &cluster.node(1).metrics;
+        assert_eq!(metrics.get("bitcoin_new_blocks_received_total", &[]), 1.0);
+        let propagation = metrics.get("bitcoin_block_propagation_milliseconds_total", &[]);
+        assert!(propagation >= 900.0 && propagation <= elapsed as f64);
+        cluster.stop();
+    }
+}
+
+// DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. AFTER TEST
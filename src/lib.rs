@@ -0,0 +1,22 @@
+#[cfg(test)]
+#[macro_use]
+extern crate hex_literal;
+
+pub mod api;
+pub mod blockchain;
+pub mod config;
+pub mod events;
+pub mod logging;
+pub mod metrics;
+pub mod types;
+pub mod miner;
+pub mod network;
+pub mod node;
+pub mod shutdown;
+pub mod generator;
+#[cfg(any(test, feature = "test-utilities"))]
+pub mod harness;
+pub mod simulation;
+pub mod wallet;
+
+pub use node::{Node, NodeBuilder};
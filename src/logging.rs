@@ -0,0 +1,285 @@
+use log::kv::{self, Key, Value, VisitSource};
+use log::{LevelFilter, Log, Metadata, Record};
+use serde_json::{json, Map, Value as Json};
+use std::cell::RefCell;
+use std::fmt::{self, Write as _};
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// How log records are written to stderr
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    #[default]
+    Text,   // `INFO  bitcoin::miner: span{field=1}: message key=value`
+    Json,   // one object per line, for analysis
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Format::Text => write!(f, "text"),
+            Format::Json => write!(f, "json"),
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("unknown log format {:?}, expected text or json", s)),
+        }
+    }
+}
+
+/// Writes the records of the `log` macros, with their key-values and the fields of
+/// the spans they were logged in. The level is set per target by a filter like
+/// `info,bitcoin::network=debug`, the most specific target prefix winning.
+pub struct Logger {
+    format: Format,
+    default: LevelFilter,
+    directives: Vec<(String, LevelFilter)>,
+}
+
+impl Logger {
+    /// A logger at the level given by the number of `-v` flags: errors only with none,
+    /// then warnings, info, debug and trace
+    pub fn new(verbosity: usize, format: Format) -> Self {
+        let default = match verbosity {
+            0 => LevelFilter::Error,
+            1 => LevelFilter::Warn,
+            2 => LevelFilter::Info,
+            3 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        };
+        Self { format, default, directives: Vec::new() }
+    }
+
+    /// Apply a filter like `info,bitcoin::network=debug` over the verbosity
+    pub fn with_filter(mut self, filter: &str) -> Result<Self, String> {
+        for directive in filter.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let (target, level) = match directive.split_once('=') {
+                Some((target, level)) => (Some(target), level),
+                None => (None, directive),
+            };
+            let level = level.parse::<LevelFilter>().map_err(|_| format!("unknown log level {:?}", level))?;
+            match target {
+                Some(target) => self.directives.push((target.to_string(), level)),
+                None => self.default = level,
+            }
+        }
+        // longest first, so the first prefix that matches is the most specific
+        self.directives.sort_by_key(|(target, _)| std::cmp::Reverse(target.len()));
+        Ok(self)
+    }
+
+    pub fn init(self) -> Result<(), log::SetLoggerError> {
+        let max = self.directives.iter().map(|(_, level)| *level).fold(self.default, Ord::max);
+        log::set_boxed_logger(Box::new(self))?;
+        log::set_max_level(max);
+        Ok(())
+    }
+
+    fn level(&self, target: &str) -> LevelFilter {
+        self.directives
+            .iter()
+            .find(|(prefix, _)| {
+                target.starts_with(prefix.as_str())
+                    && (target.len() == prefix.len() || target[prefix.len()..].starts_with("::"))
+            })
+            .map_or(self.default, |(_, level)| *level)
+    }
+
+    fn format(&self, record: &Record) -> String {
+        let mut fields = Fields::default();
+        let _ = record.key_values().visit(&mut fields);
+        SPANS.with(|spans| match self.format {
+            Format::Text => {
+                let mut line = format!("{:<5} {}: ", record.level(), record.target());
+                for span in spans.borrow().iter() {
+                    line.push_str(span.name);
+                    if !span.fields.is_empty() {
+                        line.push('{');
+                        line.push_str(&text_fields(&span.fields).join(" "));
+                        line.push('}');
+                    }
+                    line.push_str(": ");
+                }
+                write!(line, "{}", record.args()).unwrap();
+                for field in text_fields(&fields.0) {
+                    line.push(' ');
+                    line.push_str(&field);
+                }
+                line
+            }
+            Format::Json => {
+                let ts = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+                let spans: Vec<Json> = spans
+                    .borrow()
+                    .iter()
+                    .map(|span| {
+                        let mut object = Map::new();
+                        object.insert("name".to_string(), json!(span.name));
+                        object.extend(span.fields.iter().cloned());
+                        Json::Object(object)
+                    })
+                    .collect();
+                json!({
+                    "ts": ts,
+                    "level": record.level().as_str(),
+                    "target": record.target(),
+                    "message": record.args().to_string(),
+                    "fields": fields.0.into_iter().collect::<Map<_, _>>(),
+                    "spans": spans,
+                })
+                .to_string()
+            }
+        })
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let mut line = self.format(record);
+        line.push('\n');
+        // one write per record, so lines from different threads don't interleave
+        let _ = io::stderr().lock().write_all(line.as_bytes());
+    }
+
+    fn flush(&self) {
+        let _ = io::stderr().flush();
+    }
+}
+
+fn text_fields(fields: &[(String, Json)]) -> Vec<String> {
+    fields
+        .iter()
+        .map(|(key, value)| match value {
+            Json::String(s) if !s.is_empty() && !s.contains(char::is_whitespace) => format!("{}={}", key, s),
+            value => format!("{}={}", key, value),
+        })
+        .collect()
+}
+
+/// The key-values of a record, keeping numbers and booleans as such for JSON
+#[derive(Default)]
+struct Fields(Vec<(String, Json)>);
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        self.0.push((key.to_string(), json_value(&value)));
+        Ok(())
+    }
+}
+
+fn json_value(value: &Value) -> Json {
+    if let Some(n) = value.to_u64() {
+        json!(n)
+    } else if let Some(n) = value.to_i64() {
+        json!(n)
+    } else if let Some(b) = value.to_bool() {
+        json!(b)
+    } else if let Some(x) = value.to_f64() {
+        json!(x)
+    } else {
+        json!(value.to_string())
+    }
+}
+
+struct Span {
+    name: &'static str,
+    fields: Vec<(String, Json)>,
+}
+
+thread_local! {
+    // the spans entered on this thread, outermost first
+    static SPANS: RefCell<Vec<Span>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Leaves its span when dropped, logging how long it took at trace level
+#[must_use = "the span is left as soon as the guard is dropped"]
+pub struct SpanGuard {
+    name: &'static str,
+    start: Instant,
+}
+
+/// Enter a span: until the guard is dropped, records logged on this thread carry
+/// its name and fields. Usually called through `span!`.
+pub fn enter(name: &'static str, fields: Vec<(&'static str, Json)>) -> SpanGuard {
+    let fields = fields.into_iter().map(|(key, value)| (key.to_string(), value)).collect();
+    SPANS.with(|spans| spans.borrow_mut().push(Span { name, fields }));
+    SpanGuard { name, start: Instant::now() }
+}
+
+impl Drop for SpanGuard {
+    fn drop(&mut self) {
+        let elapsed_us = self.start.elapsed().as_micros() as u64;
+        log::trace!(elapsed_us; "{} finished", self.name);
+        SPANS.with(|spans| spans.borrow_mut().pop());
+    }
+}
+
+/// Enter a span with the given fields, e.g. `span!("validate_block", hash = hash, height = 3)`.
+/// Field values are anything with a string form.
+#[macro_export]
+macro_rules! span {
+    ($name:expr $(, $key:ident = $value:expr)* $(,)?) => {
+        $crate::logging::enter($name, vec![$((stringify!($key), $crate::logging::field(&$value))),*])
+    };
+}
+
+/// A span field, from anything with a string form
+pub fn field<T: ToString + ?Sized>(value: &T) -> Json {
+    json!(value.to_string())
+}
+
+// DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. BEFORE TEST
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use log::Level;
+
+    #[test]
+    fn format_records() {
+        let logger = Logger::new(0, Format::Text).with_filter("info,bitcoin::network=debug").unwrap();
+        assert_eq!(logger.level("bitcoin::network::worker"), LevelFilter::Debug);
+        assert_eq!(logger.level("bitcoin::networking"), LevelFilter::Info);
+        assert_eq!(logger.level("bitcoin::miner"), LevelFilter::Info);
+        assert!(Logger::new(0, Format::Text).with_filter("bitcoin=loud").is_err());
+
+        let kvs: &[(&str, &dyn kv::ToValue)] = &[("hash", &"00ff"), ("height", &3u64)];
+        let args = format_args!("Mined block");
+        let record = Record::builder()
+            .level(Level::Info)
+            .target("bitcoin::miner")
+            .args(args)
+            .key_values(&kvs)
+            .build();
+        let _outer = crate::span!("validate_block", peer = "127.0.0.1:6000");
+        let text = logger.format(&record);
+        assert_eq!(text, "INFO  bitcoin::miner: validate_block{peer=127.0.0.1:6000}: Mined block hash=00ff height=3");
+
+        let logger = Logger { format: Format::Json, ..logger };
+        let json: Json = serde_json::from_str(&logger.format(&record)).unwrap();
+        assert_eq!(json["message"], "Mined block");
+        assert_eq!(json["fields"], json!({"hash": "00ff", "height": 3}));
+        assert_eq!(json["spans"], json!([{"name": "validate_block", "peer": "127.0.0.1:6000"}]));
+        drop(_outer);
+        let json: Json = serde_json::from_str(&logger.format(&record)).unwrap();
+        assert_eq!(json["spans"], json!([]));
+    }
+}
+
+// DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. AFTER TEST
@@ -1,25 +1,13 @@
-#[cfg(test)]
-#[macro_use]
-extern crate hex_literal;
-
-pub mod api;
-pub mod blockchain;
-pub mod types;
-pub mod miner;
-pub mod network;
-pub mod generator;
-
-use blockchain::Blockchain;
-use types::mempool::Mempool;
+use bitcoin::config::{self, Config};
+use bitcoin::logging::Logger;
+use bitcoin::shutdown::Shutdown;
+use bitcoin::wallet;
+use bitcoin::NodeBuilder;
 use clap::clap_app;
-use smol::channel;
 use log::{error, info};
-use api::Server as ApiServer;
-use std::net;
+use std::env;
+use std::path;
 use std::process;
-use std::sync::{Arc, Mutex};
-use std::thread;
-use std::time;
 
 fn main() {
     // parse command line arguments
@@ -27,127 +15,174 @@ fn main() {
      (version: "0.1")
      (about: "Bitcoin client")
      (@arg verbose: -v ... "Increases the verbosity of logging")
-     (@arg peer_addr: --p2p [ADDR] default_value("127.0.0.1:6000") "Sets the IP address and the port of the P2P server")
-     (@arg api_addr: --api [ADDR] default_value("127.0.0.1:7000") "Sets the IP address and the port of the API server")
+     (@arg config: --config [FILE] "Loads settings from a TOML file, or JSON if it ends in .json, before BITCOIN_* variables and flags")
+     (@arg set: --set ... [SETTING] "Sets any setting of the config file but the API tokens, e.g. mempool.max_transactions=1000")
+     (@arg print_config: --("print-config") "Prints the effective config and exits")
+     (@arg log_format: --("log-format") [FORMAT] "Sets the log format: text, the default, or json for one object per line")
+     (@arg log_filter: --("log-filter") [FILTER] "Sets log levels per module over -v, e.g. info,bitcoin::network=debug")
+     (@arg peer_addr: --p2p [ADDR] "Sets the IP address and the port of the P2P server [default: 127.0.0.1:6000]")
+     (@arg api_addr: --api [ADDR] "Sets the IP address and the port of the API server [default: 127.0.0.1:7000]")
      (@arg known_peer: -c --connect ... [PEER] "Sets the peers to connect to at start")
-     (@arg p2p_workers: --("p2p-workers") [INT] default_value("4") "Sets the number of worker threads for P2P server")
+     (@arg p2p_workers: --("p2p-workers") [INT] "Sets the number of worker threads for P2P server [default: 4]")
+     (@arg miner_threads: --("miner-threads") [INT] "Sets the number of nonce search threads for the miner [default: 1]")
+     (@arg seed: --seed [INT] "Makes the miner and transaction generator deterministic, using this random seed")
+     (@arg mining_mode: --("mining-mode") [MODE] "Sets how blocks are mined: pow, the default, instant, or poisson:<mean interval in ms>")
+     (@arg import: --import [FILE] "Replays the blocks of a chain archive at start, checking each like a block from a peer")
+     (@arg scenario: --scenario [FILE] "Loads the transaction generator scenario from a JSON file")
+     (@arg wallet: --wallet [FILE] "Unlocks a wallet keystore, made by wallet create, for the wallet API")
+     (@arg api_tokens: --("api-tokens") [FILE] "Loads API tokens and their scopes from a JSON file")
+     (@arg api_public_read: --("api-public-read") "Allows reads without a token when the API requires tokens")
+     (@subcommand chain =>
+      (about: "Moves blocks between nodes in chain archive files, of bincode or JSON")
+      (@subcommand export =>
+       (about: "Saves the blocks of a running node's longest chain, or of every branch, to an archive; a node that requires a token gets the one in BITCOIN_API_TOKEN, or else api.read_token or api.admin_token")
+       (@arg node: --node [ADDR] default_value("127.0.0.1:7000") "Sets the address of the node's API server")
+       (@arg all: --all "Exports every branch of the block tree, not only the longest chain")
+       (@arg format: --format [FORMAT] "Sets the format, bincode or json [default: json if the file ends in .json]")
+       (@arg file: +required "Sets the archive to write")
+      )
+      (@subcommand import =>
+       (about: "Replays an archive onto the genesis block of the configured chain spec, checking every block, and prints the chain it makes; nodes load archives with --import")
+       (@arg file: +required "Sets the archive to read")
+      )
+     )
+     (@subcommand wallet =>
+      (about: "Manages the keys in a wallet keystore, reading the passphrase from WALLET_PASSPHRASE or the terminal")
+      (@arg keystore: --keystore [FILE] default_value("wallet.json") "Sets the keystore file")
+      (@subcommand create =>
+       (about: "Creates an empty keystore, asking for its passphrase twice")
+      )
+      (@subcommand new =>
+       (about: "Generates a new key and prints its address")
+       (@arg name: +required "Sets the name of the key")
+      )
+      (@subcommand import =>
+       (about: "Imports a key from its 32 byte Ed25519 seed, in hex from WALLET_SEED or the terminal, and prints its address")
+       (@arg name: +required "Sets the name of the key")
+      )
+      (@subcommand list =>
+       (about: "Lists the addresses in the keystore")
+      )
+      (@subcommand sign =>
+       (about: "Signs a transaction and prints it as hex-encoded bincode")
+       (@arg from: --from +required [ADDR] "Sets the sender, one of the keystore's addresses")
+       (@arg to: --to +required [ADDR] "Sets the receiver")
+       (@arg value: --value +required [INT] "Sets the value to send")
+       (@arg nonce: --nonce +required [INT] "Sets the account nonce")
+      )
+     )
     )
     .get_matches();
 
+    // settings come from the config file, then the environment, then the flags
+    let config = load_config(&matches).unwrap_or_else(|e| {
+        eprintln!("Error in config: {}", e);
+        process::exit(1);
+    });
+    if matches.is_present("print_config") {
+        print!("{}", config.redacted().to_toml());
+        return;
+    }
+
     // init logger
     let verbosity = matches.occurrences_of("verbose") as usize;
-    stderrlog::new().verbosity(verbosity).init().unwrap();
-    
-    let blockchain = Blockchain::new();
-    let blockchain = Arc::new(Mutex::new(blockchain));
-    let mempool = Mempool::new();
-    let mempool = Arc::new(Mutex::new(mempool));
-
-    // parse p2p server address
-    let p2p_addr = matches
-        .value_of("peer_addr")
-        .unwrap()
-        .parse::<net::SocketAddr>()
+    let logger = match &config.log.filter {
+        Some(filter) => Logger::new(verbosity, config.log.format).with_filter(filter),
+        None => Ok(Logger::new(verbosity, config.log.format)),
+    };
+    logger
         .unwrap_or_else(|e| {
-            error!("Error parsing P2P server address: {}", e);
+            eprintln!("Error setting up logging: {}", e);
             process::exit(1);
-        });
+        })
+        .init()
+        .unwrap();
 
-    // parse api server address
-    let api_addr = matches
-        .value_of("api_addr")
-        .unwrap()
-        .parse::<net::SocketAddr>()
-        .unwrap_or_else(|e| {
-            error!("Error parsing API server address: {}", e);
+    // the wallet and chain subcommands work on files without starting a node
+    if let Some(matches) = matches.subcommand_matches("wallet") {
+        if let Err(e) = wallet::cli::run(matches) {
+            error!("{}", e);
             process::exit(1);
-        });
-
-    // create channels between server and worker
-    let (msg_tx, msg_rx) = channel::bounded(10000);
-
-    // start the p2p server
-    let (server_ctx, server) = network::server::new(p2p_addr, msg_tx).unwrap();
-    server_ctx.start().unwrap();
+        }
+        return;
+    }
+    if let Some(matches) = matches.subcommand_matches("chain") {
+        if let Err(e) = bitcoin::blockchain::cli::run(matches, &config) {
+            error!("{}", e);
+            process::exit(1);
+        }
+        return;
+    }
+    info!("Effective config:\n{}", config.redacted().to_toml());
 
-    // create blockchain
-    // NOT SURE HOW TO DO THIS
+    // stop on SIGINT, SIGTERM or a request to /node/shutdown
+    let shutdown = Shutdown::new();
+    shutdown.on_signals().unwrap_or_else(|e| {
+        error!("Error handling signals: {}", e);
+        process::exit(1);
+    });
 
-    // start the worker
-    let p2p_workers = matches
-        .value_of("p2p_workers")
-        .unwrap()
-        .parse::<usize>()
-        .unwrap_or_else(|e| {
-            error!("Error parsing P2P workers: {}", e);
+    let mut builder = NodeBuilder::new(config.clone()).shutdown(shutdown);
+    if config.wallet.keystore.is_some() {
+        let passphrase = wallet::cli::read_passphrase().unwrap_or_else(|e| {
+            error!("Error reading wallet passphrase: {}", e);
             process::exit(1);
         });
-    let worker_ctx = network::worker::Worker::new(
-        p2p_workers,
-        msg_rx,
-        &server,
-        &blockchain,
-        &mempool,
-    );
-    worker_ctx.start();
-
-    // start the miner
-    let (miner_ctx, miner, finished_block_chan) = miner::new(&blockchain, &mempool);
-    let miner_worker_ctx = miner::worker::Worker::new(&server, finished_block_chan, &blockchain);
-    miner_ctx.start();
-    miner_worker_ctx.start();
-    
-    // start the transaction generator
-    let (generator_ctx, txn_generator, finished_txn_chan) = generator::new(&blockchain, &mempool);
-    let generator_worker_ctx = generator::generator::TransactionGenerator::new(&server, finished_txn_chan, &mempool);
-    generator_ctx.start();
-    generator_worker_ctx.start();
-    
-    // connect to known peers
-    if let Some(known_peers) = matches.values_of("known_peer") {
-        let known_peers: Vec<String> = known_peers.map(|x| x.to_owned()).collect();
-        let server = server.clone();
-        thread::spawn(move || {
-            for peer in known_peers {
-                loop {
-                    let addr = match peer.parse::<net::SocketAddr>() {
-                        Ok(x) => x,
-                        Err(e) => {
-                            error!("Error parsing peer address {}: {}", &peer, e);
-                            break;
-                        }
-                    };
-                    match server.connect(addr) {
-                        Ok(_) => {
-                            info!("Connected to outgoing peer {}", &addr);
-                            break;
-                        }
-                        Err(e) => {
-                            error!(
-                                "Error connecting to peer {}, retrying in one second: {}",
-                                addr, e
-                            );
-                            thread::sleep(time::Duration::from_millis(1000));
-                            continue;
-                        }
-                    }
-                }
-            }
-        });
+        builder = builder.wallet_passphrase(&passphrase);
     }
+    let node = builder.start().unwrap_or_else(|e| {
+        error!("Error starting node: {}", e);
+        process::exit(1);
+    });
+    node.run();
+    log::logger().flush();
+}
 
+// the settings kept off the command line, where other users of the machine can read them
+const SECRETS: &[&str] = &["api.admin_token", "api.read_token"];
 
-    // start the API server
-    ApiServer::start(
-        api_addr,
-        &miner,
-        &txn_generator,
-        &server,
-        &blockchain,
-        &mempool,
-    );
+// the flags that set a setting of the config, by the name of their argument
+const FLAGS: &[(&str, &str)] = &[
+    ("peer_addr", "network.p2p_addr"),
+    ("known_peer", "network.connect"),
+    ("p2p_workers", "network.workers"),
+    ("miner_threads", "mining.threads"),
+    ("seed", "mining.seed"),
+    ("mining_mode", "mining.mode"),
+    ("import", "consensus.import"),
+    ("scenario", "generator.scenario"),
+    ("wallet", "wallet.keystore"),
+    ("api_addr", "api.addr"),
+    ("api_tokens", "api.tokens_file"),
+    ("log_format", "log.format"),
+    ("log_filter", "log.filter"),
+];
 
-    loop {
-        std::thread::park();
+/// The node's config: the defaults, overridden by the config file named by `--config` or
+/// BITCOIN_CONFIG, then by the BITCOIN_* variables, then by `--set` and the other flags
+fn load_config(matches: &clap::ArgMatches) -> Result<Config, String> {
+    let path = matches.value_of("config").map(String::from).or_else(|| env::var("BITCOIN_CONFIG").ok());
+    let mut config = match path {
+        Some(path) => Config::load(path::Path::new(&path))?,
+        None => Config::default(),
+    };
+    config.apply_env()?;
+    for setting in matches.values_of("set").into_iter().flatten() {
+        let (key, value) = setting.split_once('=').ok_or_else(|| format!("expected KEY=VALUE in --set {}", setting))?;
+        let key = key.trim();
+        if SECRETS.contains(&key) {
+            return Err(format!("set {} in the config file or {}, not on the command line", key, config::env_var(key)));
+        }
+        config.set(key, value)?;
+    }
+    for (arg, key) in FLAGS {
+        if let Some(values) = matches.values_of(arg) {
+            config.set(key, &values.collect::<Vec<_>>().join(","))?;
+        }
+    }
+    if matches.is_present("api_public_read") {
+        config.api.public_read = true;
     }
+    config.validate()?;
+    Ok(config)
 }
@@ -0,0 +1,144 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+
+/// A metric the node exports, with its Prometheus type and help text
+struct Definition {
+    name: &'static str,
+    kind: &'static str,
+    help: &'static str,
+}
+
+const fn counter(name: &'static str, help: &'static str) -> Definition {
+    Definition { name, kind: "counter", help }
+}
+
+const fn gauge(name: &'static str, help: &'static str) -> Definition {
+    Definition { name, kind: "gauge", help }
+}
+
+// Every metric, in the order they are rendered. The gauges set when /metrics is
+// scraped are read from the blockchain, mempool and miner at that moment.
+const DEFINITIONS: &[Definition] = &[
+    gauge("bitcoin_chain_height", "Height of the tip of the longest chain"),
+    gauge("bitcoin_tip_age_seconds", "Seconds since the tip block's timestamp"),
+    gauge("bitcoin_known_blocks", "Blocks in the blockchain, on any branch"),
+    gauge("bitcoin_stale_blocks", "Blocks in the blockchain that are not on the longest chain"),
+    counter("bitcoin_reorgs_total", "Times the longest chain switched to a branch not extending the old tip"),
+    counter("bitcoin_orphan_blocks_total", "Blocks received before their parent"),
+    counter("bitcoin_block_validation_failures_total", "Blocks rejected by the blockchain, by reason"),
+    counter("bitcoin_transaction_rejections_total", "Transactions not admitted to the mempool, by reason"),
+    gauge("bitcoin_mempool_transactions", "Transactions waiting in the mempool"),
+    counter("bitcoin_messages_received_total", "Messages received from peers, by type"),
+    counter("bitcoin_messages_broadcast_total", "Messages broadcast to every peer, by type"),
+    counter("bitcoin_blocks_received_total", "Blocks received from peers"),
+    counter("bitcoin_transactions_received_total", "Transactions received from peers"),
+    gauge("bitcoin_peers", "Connected peers"),
+    counter("bitcoin_peer_bytes_received_total", "Bytes received from a connected peer, including framing"),
+    counter("bitcoin_peer_bytes_sent_total", "Bytes sent to a connected peer, including framing"),
+    counter("bitcoin_link_messages_lost_total", "Messages lost to simulated link conditions, by direction"),
+    counter("bitcoin_new_blocks_received_total", "Blocks from peers that were new to the blockchain and valid"),
+    counter("bitcoin_block_propagation_milliseconds_total", "Milliseconds from the timestamps of the new blocks received from peers to their insertion"),
+    counter("bitcoin_blocks_mined_total", "Blocks mined by this node"),
+    counter("bitcoin_stale_blocks_mined_total", "Blocks mined by this node on a parent that was no longer the tip"),
+    gauge("bitcoin_hash_rate", "Hashes per second over the miner's recent history"),
+    counter("bitcoin_transactions_generated_total", "Transactions made by the transaction generator, by kind, counting a double spend pair once"),
+];
+
+type Labels = Vec<(&'static str, String)>;
+
+/// The node's counters and gauges, shared by the components that update them.
+/// Rendered in the Prometheus text format by /metrics.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    values: Arc<Mutex<BTreeMap<(&'static str, Labels), f64>>>,
+}
+
+fn labels(labels: &[(&'static str, &str)]) -> Labels {
+    labels.iter().map(|(name, value)| (*name, value.to_string())).collect()
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn inc(&self, name: &'static str, labels: &[(&'static str, &str)]) {
+        self.add(name, labels, 1);
+    }
+
+    pub fn add(&self, name: &'static str, labels: &[(&'static str, &str)], n: u64) {
+        debug_assert!(DEFINITIONS.iter().any(|d| d.name == name), "undefined metric {}", name);
+        let mut values = self.values.lock().unwrap();
+        *values.entry((name, self::labels(labels))).or_insert(0.0) += n as f64;
+    }
+
+    pub fn set(&self, name: &'static str, labels: &[(&'static str, &str)], value: f64) {
+        debug_assert!(DEFINITIONS.iter().any(|d| d.name == name), "undefined metric {}", name);
+        self.values.lock().unwrap().insert((name, self::labels(labels)), value);
+    }
+
+    /// Forget a labelled series, e.g. of a peer that disconnected
+    pub fn remove(&self, name: &'static str, labels: &[(&'static str, &str)]) {
+        self.values.lock().unwrap().remove(&(name, self::labels(labels)));
+    }
+
+    pub fn get(&self, name: &'static str, labels: &[(&'static str, &str)]) -> f64 {
+        let values = self.values.lock().unwrap();
+        values.get(&(name, self::labels(labels))).copied().unwrap_or(0.0)
+    }
+
+    /// The metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let values = self.values.lock().unwrap();
+        let mut out = String::new();
+        for definition in DEFINITIONS {
+            writeln!(out, "# HELP {} {}", definition.name, definition.help).unwrap();
+            writeln!(out, "# TYPE {} {}", definition.name, definition.kind).unwrap();
+            let series: Vec<_> = values.iter().filter(|((name, _), _)| *name == definition.name).collect();
+            if series.is_empty() && definition.kind == "counter" {
+                writeln!(out, "{} 0", definition.name).unwrap();
+            }
+            for ((name, labels), value) in series {
+                if labels.is_empty() {
+                    writeln!(out, "{} {}", name, value).unwrap();
+                } else {
+                    let labels: Vec<String> = labels
+                        .iter()
+                        .map(|(label, value)| format!("{}=\"{}\"", label, value.replace('\\', "\\\\").replace('"', "\\\"")))
+                        .collect();
+                    writeln!(out, "{}{{{}}} {}", name, labels.join(","), value).unwrap();
+                }
+            }
+        }
+        out
+    }
+}
+
+// DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. BEFORE TEST
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn render_metrics() {
+        let metrics = Metrics::new();
+        metrics.inc("bitcoin_messages_received_total", &[("type", "blocks")]);
+        metrics.add("bitcoin_messages_received_total", &[("type", "blocks")], 2);
+        metrics.inc("bitcoin_messages_received_total", &[("type", "ping")]);
+        metrics.set("bitcoin_chain_height", &[], 7.0);
+        metrics.add("bitcoin_peer_bytes_sent_total", &[("peer", "127.0.0.1:6000")], 100);
+        metrics.remove("bitcoin_peer_bytes_sent_total", &[("peer", "127.0.0.1:6000")]);
+        assert_eq!(metrics.get("bitcoin_messages_received_total", &[("type", "blocks")]), 3.0);
+
+        let text = metrics.render();
+        assert!(text.contains("# TYPE bitcoin_chain_height gauge\nbitcoin_chain_height 7\n"));
+        assert!(text.contains("bitcoin_messages_received_total{type=\"blocks\"} 3\n"));
+        assert!(text.contains("bitcoin_messages_received_total{type=\"ping\"} 1\n"));
+        assert!(text.contains("bitcoin_reorgs_total 0\n"));
+        assert!(!text.contains("127.0.0.1:6000"));
+    }
+}
+
+// DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. AFTER TEST
@@ -1,26 +1,37 @@
+pub mod stats;
+pub mod template;
 pub mod worker;
 
-use log::info;
-use crossbeam::channel::{unbounded, Receiver, Sender, TryRecvError};
-use rand::Rng;
+use log::{error, info};
+use crossbeam::channel::{select, unbounded, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use rand::{rngs::StdRng, Rng};
 use std::{
-    sync::{Arc, Mutex},
-    time::{SystemTime, UNIX_EPOCH},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
     time,
     thread,
 };
 use crate::blockchain::Blockchain;
+use crate::simulation::{clock::{self, Clock}, MiningMode, Simulation};
 use crate::types::{
+    block::{Block, Header},
+    hash::Hashable,
+    mempool::Mempool,
     address::Address,
-    block::{Block, Content, Header},
-    hash::{Hashable, H256},
-    transaction::SignedTransaction,
-    merkle::MerkleTree,
-    mempool::Mempool
 };
+use stats::{MinerState, MinerStatus, Stats};
+use template::{BlockTemplate, BlockTemplateBuilder};
 
 enum ControlSignal {
     Start(u64), // the number controls the lambda of interval between block generation
+    Pause,
+    Resume, // continue mining with the last lambda
+    SetLambda(u64), // change lambda without changing whether the miner runs
+    SetThreads(usize), // the number of threads searching for a nonce in parallel
+    SetBlockSizeLimit(usize),
+    SetPayoutAddress(Address),
     Update, // update the block in mining, it may due to new blockchain tip or new transaction
     Exit,
 }
@@ -35,49 +46,108 @@ pub struct Context {
     /// Channel for receiving control signal
     control_chan: Receiver<ControlSignal>,
     operating_state: OperatingState,
+    lambda: u64,
     finished_block_chan: Sender<Block>,
     blockchain: Arc<Mutex<Blockchain>>,
     mempool: Arc<Mutex<Mempool>>,
+    /// Bumped by the blockchain whenever its tip changes
+    tip_version: Arc<AtomicU64>,
+    /// Bumped by the mempool whenever its transactions change
+    mempool_version: Arc<AtomicU64>,
+    template_builder: BlockTemplateBuilder,
+    num_threads: usize,
+    stats: Arc<Stats>,
+    mining_mode: MiningMode,
+    clock: Arc<dyn Clock>,
+    rng: StdRng,
 }
 
 #[derive(Clone)]
 pub struct Handle {
     /// Channel for sending signal to the miner thread
     control_chan: Sender<ControlSignal>,
+    stats: Arc<Stats>,
 }
 
-// set upper limit on number of transactions per block
-const BLOCK_SIZE_LIMIT: usize = 30;      
+/// The most transactions per block, unless configured otherwise
+pub const BLOCK_SIZE_LIMIT: usize = 30;      
 
-pub fn new(blockchain: &Arc<Mutex<Blockchain>>, mempool: &Arc<Mutex<Mempool>>) -> (Context, Handle, Receiver<Block>) {
+// how often to check whether new transactions should be pulled into the block in mining
+const TEMPLATE_REFRESH_INTERVAL: time::Duration = time::Duration::from_millis(100);
+
+// how many hashes a search thread counts locally before adding them to the shared stats
+const HASH_COUNT_FLUSH: u64 = 4096;
+
+// the miner's random number stream in a simulation
+const RNG_STREAM: u64 = 1;
+
+pub fn new(
+    blockchain: &Arc<Mutex<Blockchain>>,
+    mempool: &Arc<Mutex<Mempool>>,
+    num_threads: usize,
+    simulation: &Simulation,
+) -> (Context, Handle, Receiver<Block>) {
     let (signal_chan_sender, signal_chan_receiver) = unbounded();
     let (finished_block_sender, finished_block_receiver) = unbounded();
+    let tip_version = blockchain.lock().unwrap().tip_version();
+    let mempool_version = mempool.lock().unwrap().version();
+    let num_threads = num_threads.max(1);
+    let stats = Arc::new(Stats::new(num_threads, BLOCK_SIZE_LIMIT));
 
     let ctx = Context {
         control_chan: signal_chan_receiver,
         operating_state: OperatingState::Paused,
+        lambda: 0,
         finished_block_chan: finished_block_sender,
         blockchain: Arc::clone(blockchain),
-        mempool: Arc::clone(mempool)
+        mempool: Arc::clone(mempool),
+        tip_version,
+        mempool_version,
+        template_builder: BlockTemplateBuilder::new(BLOCK_SIZE_LIMIT),
+        num_threads,
+        stats: Arc::clone(&stats),
+        mining_mode: simulation.mining_mode(),
+        clock: simulation.clock(),
+        rng: simulation.rng(RNG_STREAM),
     };
 
     let handle = Handle {
         control_chan: signal_chan_sender,
+        stats,
     };
 
     (ctx, handle, finished_block_receiver)
 }
 
-#[cfg(any(test,test_utilities))]
+#[cfg(any(test, feature = "test-utilities"))]
+impl Handle {
+    /// A handle that is not connected to any miner, for testing the components that notify it
+    pub fn new_for_test() -> Handle {
+        let (signal_chan_sender, _) = unbounded();
+        Handle {
+            control_chan: signal_chan_sender,
+            stats: Arc::new(Stats::new(1, BLOCK_SIZE_LIMIT)),
+        }
+    }
+}
+
+#[cfg(test)]
 fn test_new() -> (Context, Handle, Receiver<Block>) {
+    test_new_with_threads(1)
+}
+
+#[cfg(test)]
+fn test_new_with_threads(num_threads: usize) -> (Context, Handle, Receiver<Block>) {
     let blockchain = Arc::new(Mutex::new(Blockchain::new()));
     let mempool = Arc::new(Mutex::new(Mempool::new()));
-    new(&blockchain, &mempool)
+    new(&blockchain, &mempool, num_threads, &Simulation::default())
 }
 
 impl Handle {
+    /// Stop mining for good, ending the miner thread
     pub fn exit(&self) {
-        self.control_chan.send(ControlSignal::Exit).unwrap();
+        // the miner may already have shut down
+        let _ = self.control_chan.send(ControlSignal::Exit);
     }
 
     pub fn start(&self, lambda: u64) {
@@ -86,20 +156,66 @@ impl Handle {
             .unwrap();
     }
 
+    /// Stop mining until resumed; unlike exit, the miner can be started again
+    pub fn pause(&self) {
+        self.control_chan.send(ControlSignal::Pause).unwrap();
+    }
+
+    /// Start mining again with the last lambda
+    pub fn resume(&self) {
+        self.control_chan.send(ControlSignal::Resume).unwrap();
+    }
+
+    /// Change lambda, which is used right away if running or on the next resume if paused
+    pub fn set_lambda(&self, lambda: u64) {
+        self.control_chan
+            .send(ControlSignal::SetLambda(lambda))
+            .unwrap();
+    }
+
+    /// Set the number of nonce search threads, taking effect from the next block
+    pub fn set_threads(&self, num_threads: usize) {
+        self.control_chan
+            .send(ControlSignal::SetThreads(num_threads))
+            .unwrap();
+    }
+
+    /// Set the maximum number of transactions per block, taking effect from the next block
+    pub fn set_block_size_limit(&self, limit: usize) {
+        self.control_chan
+            .send(ControlSignal::SetBlockSizeLimit(limit))
+            .unwrap();
+    }
+
+    /// Set the address that block rewards are paid to, taking effect from the next block
+    pub fn set_payout_address(&self, address: Address) {
+        self.control_chan
+            .send(ControlSignal::SetPayoutAddress(address))
+            .unwrap();
+    }
+
+    /// Tell the miner the tip or mempool changed, so it can rebuild the block in mining
     pub fn update(&self) {
-        self.control_chan.send(ControlSignal::Update).unwrap();
+        // the miner may already have shut down, in which case there is nothing to update
+        let _ = self.control_chan.send(ControlSignal::Update);
+    }
+
+    /// Get a snapshot of the miner's statistics and the block it is working on
+    pub fn status(&self) -> MinerStatus {
+        self.stats.snapshot()
     }
 }
 
 impl Context {
-    pub fn start(mut self) {
-        thread::Builder::new()
+    pub fn start(mut self) -> thread::JoinHandle<()> {
+        let handle = thread::Builder::new()
             .name("miner".to_string())
             .spawn(move || {
                 self.miner_loop();
             })
             .unwrap();
         info!("Miner initialized into paused mode");
+        handle
     }
 
     fn miner_loop(&mut self) {
@@ -109,19 +225,7 @@ impl Context {
             match self.operating_state {
                 OperatingState::Paused => {
                     let signal = self.control_chan.recv().unwrap();
-                    match signal {
-                        ControlSignal::Exit => {
-                            info!("Miner shutting down");
-                            self.operating_state = OperatingState::ShutDown;
-                        }
-                        ControlSignal::Start(i) => {
-                            info!("Miner starting in continuous mode with lambda {}", i);
-                            self.operating_state = OperatingState::Run(i);
-                        }
-                        ControlSignal::Update => {
-                            // in paused state, don't need to update
-                        }
-                    };
+                    self.apply_signal(signal);
                     continue;
                 }
                 OperatingState::ShutDown => {
@@ -129,19 +233,8 @@ impl Context {
                 }
                 _ => match self.control_chan.try_recv() {
                     Ok(signal) => {
-                        match signal {
-                            ControlSignal::Exit => {
-                                info!("Miner shutting down");
-                                self.operating_state = OperatingState::ShutDown;
-                            }
-                            ControlSignal::Start(i) => {
-                                info!("Miner starting in continuous mode with lambda {}", i);
-                                self.operating_state = OperatingState::Run(i);
-                            }
-                            ControlSignal::Update => {
-                                unimplemented!()
-                            }
-                        };
+                        // the block is rebuilt below anyway, so there is nothing to interrupt
+                        self.apply_signal(signal);
                     }
                     Err(TryRecvError::Empty) => {}
                     Err(TryRecvError::Disconnected) => panic!("Miner control channel detached"),
@@ -151,121 +244,273 @@ impl Context {
                 return;
             }
 
-            // TODO for student: actual mining, create a block
-            // TODO for student: if block mining finished, you can have something like: 
-            // self.finished_block_chan.send(block.clone()).expect("Send finished block error");
-
-            // println!("Starting the Mining Process...");
-            
-            // Get current tip of blockchain to get parent_block, parent_state, difficulty 
-            let blockchain = self.blockchain.lock().unwrap();
-            let parent_hash = blockchain.tip();
-            let parent_block = match blockchain.get_block(&parent_hash) {
-                Ok(block) => block,    // parent exists in blockchain
-                Err(_) => panic!("Parent node does not exist in blockchain."),   // parent not found
+            // Assemble the next block once; the search only varies nonce and timestamp
+            let template = {
+                let blockchain = self.blockchain.lock().unwrap();
+                let mut mempool = self.mempool.lock().unwrap();
+                self.template_builder.build(&blockchain, &mut mempool)
             };
-            let parent_state = match blockchain.get_state(&parent_hash) {
-                Ok(state) => state.clone(),    // parent exists in blockchain
-                Err(_) => panic!("Parent node does not exist in blockchain."),   // parent not found
+            self.stats.set_template(&template.parent(), template.num_transactions());
+
+            // Search for a nonce on all threads until one of them finds it or the template goes stale
+            let search_start = time::Instant::now();
+            let found = match self.mining_mode {
+                MiningMode::ProofOfWork => self.search(&template),
+                _ => self.simulate(&template),
             };
-            let difficulty = parent_block.get_difficulty();
-            let mut rng = rand::thread_rng();
-            drop(blockchain);
-
-            // Prepare to get transactions for the new block
-            let mut mempool = self.mempool.lock().unwrap();
-            let mut transactions: Vec<SignedTransaction> = Vec::new();
-            let mut removal_hashes = Vec::new();
-
-            // Iterate over the transactions in the mempool
-            for txn in mempool.map.values() {
-                // Break if the block transaction limit is reached
-                if transactions.len() == BLOCK_SIZE_LIMIT {
-                    break;
+            self.stats.add_mining_time(search_start.elapsed());
+
+            if let Some(header) = found {
+                let block = template.seal(header);
+
+                // Insert block into blockchain, so the next template builds on top of it
+                let mut blockchain = self.blockchain.lock().unwrap();
+                let stale = blockchain.tip() != template.parent();
+                let result = blockchain.insert(&block);
+                if result.is_ok() {
+                    let mut mempool = self.mempool.lock().unwrap();
+                    for txn in block.content.transactions.iter() {
+                        mempool.remove(&txn.hash());
+                    }
                 }
+                drop(blockchain);
 
-                let sender_address = Address::from_public_key_bytes(&txn.public_key);
-                let sender_info = parent_state.map[&sender_address];
+                match result {
+                    Ok(_) => {
+                        self.stats.add_block(stale);
+                        info!(hash:% = block.hash(), parent:% = template.parent(),
+                            transactions = template.num_transactions(), stale; "Mined block");
 
-                // Check nonce, balance, and if sender is already included in the new block
-                let is_nonce_valid = txn.transaction.account_nonce == sender_info.0 + 1;
-                let is_balance_sufficient = txn.transaction.value <= sender_info.1;
-                let is_sender_unique = !transactions.iter().any(|x| Address::from_public_key_bytes(&x.public_key) == sender_address);
-                
-                if is_nonce_valid && is_balance_sufficient && is_sender_unique {
-                    transactions.push(txn.clone());
+                        // Send to channel
+                        self.finished_block_chan.send(block).expect("Sending to channel resulted in error.");
+                    }
+                    Err(_) => error!(hash:% = block.hash(); "Mined block was rejected by the blockchain"),
                 }
-                else {
-                    println!("Miner found invalid transaction");
+            }
+            
+            if let OperatingState::Run(i) = self.operating_state {
+                if i != 0 {
+                    self.wait(time::Duration::from_micros(i));
                 }
+            }
+        }
+    }
 
-                // Schedule the processed transaction for removal from the mempool
-                removal_hashes.push(txn.hash());
+    /// Wait between mining rounds, reacting to control signals meanwhile, until
+    /// `interval` is over or the miner stops running
+    fn wait(&mut self, interval: time::Duration) {
+        let deadline = time::Instant::now() + interval;
+        while let OperatingState::Run(_) = self.operating_state {
+            let timeout = deadline.saturating_duration_since(time::Instant::now());
+            match self.control_chan.recv_timeout(timeout) {
+                Ok(signal) => {
+                    self.apply_signal(signal);
+                }
+                Err(RecvTimeoutError::Timeout) => return,
+                Err(RecvTimeoutError::Disconnected) => panic!("Miner control channel detached"),
             }
+        }
+    }
 
-            // Remove the processed transactions from the mempool
-            for txn_hash in removal_hashes {
-                mempool.map.remove(&txn_hash);
+    /// Spread the nonce search for `template` over `num_threads` threads. Returns the
+    /// solved header, or None if the template went stale or a control signal interrupted mining.
+    fn search(&mut self, template: &BlockTemplate) -> Option<Header> {
+        let num_threads = self.num_threads;
+        let stop = AtomicBool::new(false);
+        let current_tip_version = Arc::clone(&self.tip_version);
+        let stats = Arc::clone(&self.stats);
+        let clock = Arc::clone(&self.clock);
+        let (found_sender, found_receiver) = unbounded();
+
+        thread::scope(|scope| {
+            for id in 0..num_threads {
+                let found_sender = found_sender.clone();
+                let stop = &stop;
+                let current_tip_version = &current_tip_version;
+                let stats = &stats;
+                let clock = clock.as_ref();
+                thread::Builder::new()
+                    .name(format!("miner-search-{}", id))
+                    .spawn_scoped(scope, move || {
+                        search_nonces(template.header(), id as u32, num_threads as u32, template.tip_version(), current_tip_version, stop, stats, clock, found_sender);
+                    })
+                    .unwrap();
             }
-            
-            // Stop mining current block if there are no transactions
-            if transactions.len() == 0 {
-                continue;
+            // the search is over once every thread has dropped its sender
+            drop(found_sender);
+
+            let result = loop {
+                select! {
+                    recv(found_receiver) -> header => break header.ok(),
+                    recv(self.control_chan) -> signal => {
+                        let signal = signal.expect("Miner control channel detached");
+                        if let ControlSignal::Update = signal {
+                            if self.is_stale(template) {
+                                break None;
+                            }
+                        } else if self.apply_signal(signal) {
+                            break None;
+                        }
+                    }
+                    default(TEMPLATE_REFRESH_INTERVAL) => {
+                        // in case a change was not announced with an update signal
+                        if self.is_stale(template) {
+                            break None;
+                        }
+                    }
+                }
+            };
+            stop.store(true, Ordering::Relaxed);
+            result
+        })
+    }
+
+    /// Seal `template` without proof of work, after the delay the mining mode asks for.
+    /// Returns None if the template went stale or a control signal interrupted mining.
+    fn simulate(&mut self, template: &BlockTemplate) -> Option<Header> {
+        // the delay is on the simulation's clock, so a run under a manual clock is reproducible
+        let delay = self.mining_mode.next_delay(&mut self.rng);
+        let deadline = self.clock.now_millis() as f64 + delay.as_secs_f64() * 1000.0;
+        while let Some(timeout) = clock::until(self.clock.as_ref(), deadline) {
+            select! {
+                recv(self.control_chan) -> signal => {
+                    let signal = signal.expect("Miner control channel detached");
+                    if let ControlSignal::Update = signal {
+                        if self.is_stale(template) {
+                            return None;
+                        }
+                    } else if self.apply_signal(signal) {
+                        return None;
+                    }
+                }
+                default(timeout) => {}
             }
+        }
+        if self.is_stale(template) {
+            return None;
+        }
 
-            drop(mempool);
+        let mut header = template.header().clone();
+        header.nonce = self.rng.gen();
+        header.timestamp = self.clock.now_millis();
+        Some(header)
+    }
 
-            // Get other attributes for current block
-            let merkle_tree = MerkleTree::new(&transactions.clone());
-            let merkle_root = merkle_tree.root();
-            
-            // Loop to generate random nonces until desired hash is achieved
-            while self.blockchain.lock().unwrap().tip() == parent_hash  {
-                let content = Content{ 
-                    transactions: transactions.clone() 
-                };
-
-                let nonce: u32 = rng.gen::<u32>();      // generate a random nonce
-                
-                let timestamp: u128 = match SystemTime::now().duration_since(UNIX_EPOCH) {
-                    Ok(time) => time.as_millis(),
-                    Err(_) => panic!("SystemTime before UNIX EPOCH!"), 
-                };
-                
-                let header = Header {
-                    parent: parent_hash,
-                    nonce: nonce,
-                    difficulty: difficulty,
-                    timestamp: timestamp,
-                    merkle_root: merkle_root
-                };
-
-                let block = Block{ header, content };
-                
-                if block.hash() <= difficulty {
-                    // Desired nonce found!
-                    println!("Desired nonce found!");
-                    println!("Parent Hash: {}", parent_hash);
-                    println!("Block Hash : {}", block.hash());
-
-                    // Insert block into blockchain (temporary)
-                    // match {self.blockchain.lock().unwrap().insert(&block)} {
-                    //     Ok(_) => println!("SUCCESS - inserted block into blockchain"),
-                    //     Err(e) => panic!("{}", e)
-                    // };
-
-                    // Send to channel
-                    self.finished_block_chan.send(block.clone()).expect("Sending to channel resulted in error.");
-                    break;
-                }
+    /// Whether the tip moved, or new transactions could be added, since `template` was built
+    fn is_stale(&self, template: &BlockTemplate) -> bool {
+        self.tip_version.load(Ordering::Acquire) != template.tip_version()
+            || template.is_outdated(self.mempool_version.load(Ordering::Acquire))
+    }
+
+    fn set_operating_state(&mut self, state: OperatingState) {
+        self.stats.set_state(match state {
+            OperatingState::Paused => MinerState::Paused,
+            OperatingState::Run(i) => MinerState::Run { lambda: i },
+            OperatingState::ShutDown => MinerState::ShutDown,
+        });
+        self.operating_state = state;
+    }
+
+    /// React to a control signal, returning whether the block in mining has to be abandoned
+    fn apply_signal(&mut self, signal: ControlSignal) -> bool {
+        match signal {
+            ControlSignal::Exit => {
+                info!("Miner shutting down");
+                self.set_operating_state(OperatingState::ShutDown);
+                true
             }
-            
-            if let OperatingState::Run(i) = self.operating_state {
-                if i != 0 {
-                    let interval = time::Duration::from_micros(i as u64);
-                    thread::sleep(interval);
+            ControlSignal::Start(i) => {
+                info!("Miner starting in continuous mode with lambda {}", i);
+                self.lambda = i;
+                self.stats.set_lambda(i);
+                self.set_operating_state(OperatingState::Run(i));
+                false
+            }
+            ControlSignal::Pause => {
+                info!("Miner paused");
+                self.set_operating_state(OperatingState::Paused);
+                true
+            }
+            ControlSignal::Resume => {
+                info!("Miner resuming with lambda {}", self.lambda);
+                self.set_operating_state(OperatingState::Run(self.lambda));
+                false
+            }
+            ControlSignal::SetLambda(i) => {
+                info!("Miner lambda set to {}", i);
+                self.lambda = i;
+                self.stats.set_lambda(i);
+                if let OperatingState::Run(_) = self.operating_state {
+                    self.set_operating_state(OperatingState::Run(i));
                 }
+                false
+            }
+            ControlSignal::SetThreads(n) => {
+                info!("Miner using {} threads", n.max(1));
+                self.num_threads = n.max(1);
+                self.stats.set_num_threads(self.num_threads);
+                false
             }
+            ControlSignal::SetBlockSizeLimit(limit) => {
+                info!("Miner block size limit set to {}", limit);
+                self.template_builder.set_block_size_limit(limit);
+                self.stats.set_block_size_limit(limit);
+                true
+            }
+            ControlSignal::SetPayoutAddress(address) => {
+                info!("Miner payout address set to {}", address);
+                self.template_builder.set_payout_address(address);
+                self.stats.set_payout_address(&address);
+                true
+            }
+            ControlSignal::Update => true,
+        }
+    }
+}
+
+/// Hash `template` with every nonce congruent to `id` modulo `stride`, refreshing the
+/// timestamp whenever that share of the nonce space is used up. Gives up as soon as
+/// `stop` is set or the blockchain tip moves away from `tip_version`.
+#[allow(clippy::too_many_arguments)]
+fn search_nonces(
+    template: &Header,
+    id: u32,
+    stride: u32,
+    tip_version: u64,
+    current_tip_version: &AtomicU64,
+    stop: &AtomicBool,
+    stats: &Stats,
+    clock: &dyn Clock,
+    found_chan: Sender<Header>,
+) {
+    let mut header = template.clone();
+    let mut hashes: u64 = 0;
+    loop {
+        header.timestamp = clock.now_millis();
+
+        let mut nonce = Some(id);
+        while let Some(n) = nonce {
+            if stop.load(Ordering::Relaxed) || current_tip_version.load(Ordering::Relaxed) != tip_version {
+                stats.add_hashes(hashes);
+                return;
+            }
+
+            header.nonce = n;
+            hashes += 1;
+            if header.hash() <= header.difficulty {
+                stop.store(true, Ordering::Relaxed);
+                stats.add_hashes(hashes);
+                let _ = found_chan.send(header);
+                return;
+            }
+
+            // flush the local count now and then, so the shared counter is not contended
+            if hashes == HASH_COUNT_FLUSH {
+                stats.add_hashes(hashes);
+                hashes = 0;
+            }
+
+            nonce = n.checked_add(stride);
         }
     }
 }
@@ -276,6 +521,9 @@ impl Context {
 mod test {
     use ntest::timeout;
     use crate::types::hash::Hashable;
+    use crate::simulation::clock::ManualClock;
+    use super::stats::MinerState;
+    use super::*;
 
     #[test]
     #[timeout(60000)]
@@ -295,6 +543,72 @@ mod test {
         }
     }
 
+    #[test]
+    #[timeout(60000)]
+    fn miner_multi_threaded() {
+        let (miner_ctx, miner_handle, finished_block_chan) = super::test_new_with_threads(4);
+        miner_ctx.start();
+        miner_handle.start(0);
+        let mut block_prev = finished_block_chan.recv().unwrap();
+        for _ in 0..4 {
+            let block_next = finished_block_chan.recv().unwrap();
+            assert_eq!(block_prev.hash(), block_next.get_parent());
+            assert!(block_next.hash() <= block_next.get_difficulty());
+            block_prev = block_next;
+        }
+    }
+
+    #[test]
+    #[timeout(60000)]
+    fn miner_status() {
+        let (miner_ctx, miner_handle, finished_block_chan) = super::test_new();
+        assert_eq!(miner_handle.status().state, MinerState::Paused);
+        miner_ctx.start();
+        miner_handle.start(0);
+        for _ in 0..3 {
+            finished_block_chan.recv().unwrap();
+        }
+        let status = miner_handle.status();
+        assert_eq!(status.state, MinerState::Run { lambda: 0 });
+        assert!(status.blocks_found >= 3);
+        assert!(status.hashes_attempted >= status.blocks_found);
+        assert!(status.avg_block_time_ms.is_some());
+        assert_eq!(status.stale_blocks, 0);
+
+        assert_eq!(status.template.unwrap().num_transactions, 0);
+        miner_handle.exit();
+    }
+
+    #[test]
+    #[timeout(60000)]
+    fn miner_pause_resume() {
+        let (miner_ctx, miner_handle, finished_block_chan) = super::test_new();
+        miner_ctx.start();
+        miner_handle.start(0);
+        let block_prev = finished_block_chan.recv().unwrap();
+
+        miner_handle.pause();
+        miner_handle.set_lambda(10);
+        miner_handle.set_block_size_limit(5);
+        // signals are handled in order, so the pause has landed once the size limit has
+        while miner_handle.status().block_size_limit != 5 {
+            std::thread::yield_now();
+        }
+        let status = miner_handle.status();
+        assert_eq!(status.state, MinerState::Paused);
+        assert_eq!(status.lambda, 10);
+
+        miner_handle.resume();
+        let mut block_next = finished_block_chan.recv().unwrap();
+        // skip a block that may have been found right before the pause took effect
+        if block_next.get_parent() == block_prev.hash() {
+            block_next = finished_block_chan.recv().unwrap();
+        }
+        assert_eq!(miner_handle.status().state, MinerState::Run { lambda: 10 });
+        assert!(block_next.hash() <= block_next.get_difficulty());
+        miner_handle.exit();
+    }
+
     #[test]
     #[timeout(60000)]
     fn miner_ten_block() {
@@ -312,6 +626,29 @@ mod test {
             block_prev = block_next;
         }
     }
+
+    #[test]
+    #[timeout(60000)]
+    fn poisson_delay_on_the_clock() {
+        let clock = Arc::new(ManualClock::new(1_000_000, 0));
+        let simulation = Simulation::seeded(7)
+            .with_mining_mode(MiningMode::Poisson { mean_interval_ms: 1000 })
+            .with_clock(clock.clone());
+        let mut blockchain = Blockchain::new();
+        blockchain.set_check_pow(simulation.checks_pow());
+        let blockchain = Arc::new(Mutex::new(blockchain));
+        let mempool = Arc::new(Mutex::new(Mempool::new()));
+        let (miner_ctx, miner_handle, finished_block_chan) = super::new(&blockchain, &mempool, 1, &simulation);
+        miner_ctx.start();
+        miner_handle.start(0);
+
+        // the clock stands still, so the delay never passes
+        assert!(finished_block_chan.recv_timeout(time::Duration::from_millis(200)).is_err());
+        clock.advance(1_000_000);
+        let block = finished_block_chan.recv().unwrap();
+        assert_eq!(block.header.timestamp, 2_000_000);
+        miner_handle.exit();
+    }
 }
 
 // DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. AFTER TEST
\ No newline at end of file
@@ -0,0 +1,160 @@
+use crate::types::{address::Address, hash::H256};
+use serde::Serialize;
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+// the hash rate is recomputed at most this often, so it is averaged over at least this window
+const HASH_RATE_WINDOW: Duration = Duration::from_secs(1);
+
+// Counters shared between the miner thread, its search threads and miner handles
+pub struct Stats {
+    hashes: AtomicU64,
+    blocks_found: AtomicU64,
+    stale_blocks: AtomicU64,
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    state: MinerState,
+    lambda: u64,
+    num_threads: usize,
+    block_size_limit: usize,
+    payout_address: Option<Address>,
+    template: Option<TemplateInfo>,
+    mining_time: Duration,      // total time spent searching for nonces
+    rate_sample: (Instant, u64),    // (when, hashes) at the start of the current hash rate window
+    hash_rate: f64,
+}
+
+/// The operating state of the miner, as reported by the status endpoint
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum MinerState {
+    Paused,
+    Run { lambda: u64 },
+    ShutDown,
+}
+
+/// The block the miner is currently working on
+#[derive(Serialize, Debug, Clone)]
+pub struct TemplateInfo {
+    pub parent: String,
+    pub num_transactions: usize,
+}
+
+/// A snapshot of the miner's statistics
+#[derive(Serialize, Debug, Clone)]
+pub struct MinerStatus {
+    pub state: MinerState,
+    pub lambda: u64,
+    pub num_threads: usize,
+    pub block_size_limit: usize,
+    pub payout_address: Option<String>,
+    pub hashes_attempted: u64,
+    pub hash_rate: f64,     // hashes per second
+    pub blocks_found: u64,
+    pub stale_blocks: u64,
+    pub avg_block_time_ms: Option<f64>,
+    pub template: Option<TemplateInfo>,
+}
+
+impl Stats {
+    pub fn new(num_threads: usize, block_size_limit: usize) -> Self {
+        Self {
+            hashes: AtomicU64::new(0),
+            blocks_found: AtomicU64::new(0),
+            stale_blocks: AtomicU64::new(0),
+            inner: Mutex::new(Inner {
+                state: MinerState::Paused,
+                lambda: 0,
+                num_threads,
+                block_size_limit,
+                payout_address: None,
+                template: None,
+                mining_time: Duration::default(),
+                rate_sample: (Instant::now(), 0),
+                hash_rate: 0.0,
+            }),
+        }
+    }
+
+    pub fn add_hashes(&self, n: u64) {
+        self.hashes.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// Record a found block; a stale block is one found after the tip moved away from its parent
+    pub fn add_block(&self, stale: bool) {
+        self.blocks_found.fetch_add(1, Ordering::Relaxed);
+        if stale {
+            self.stale_blocks.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn add_mining_time(&self, elapsed: Duration) {
+        self.inner.lock().unwrap().mining_time += elapsed;
+    }
+
+    pub fn set_state(&self, state: MinerState) {
+        self.inner.lock().unwrap().state = state;
+    }
+
+    pub fn set_lambda(&self, lambda: u64) {
+        self.inner.lock().unwrap().lambda = lambda;
+    }
+
+    pub fn set_num_threads(&self, num_threads: usize) {
+        self.inner.lock().unwrap().num_threads = num_threads;
+    }
+
+    pub fn set_block_size_limit(&self, block_size_limit: usize) {
+        self.inner.lock().unwrap().block_size_limit = block_size_limit;
+    }
+
+    pub fn set_payout_address(&self, address: &Address) {
+        self.inner.lock().unwrap().payout_address = Some(*address);
+    }
+
+    pub fn set_template(&self, parent: &H256, num_transactions: usize) {
+        self.inner.lock().unwrap().template = Some(TemplateInfo {
+            parent: parent.to_string(),
+            num_transactions,
+        });
+    }
+
+    pub fn snapshot(&self) -> MinerStatus {
+        let hashes = self.hashes.load(Ordering::Relaxed);
+        let blocks_found = self.blocks_found.load(Ordering::Relaxed);
+        let mut inner = self.inner.lock().unwrap();
+
+        let (sample_time, sample_hashes) = inner.rate_sample;
+        let elapsed = sample_time.elapsed();
+        if elapsed >= HASH_RATE_WINDOW {
+            inner.hash_rate = (hashes - sample_hashes) as f64 / elapsed.as_secs_f64();
+            inner.rate_sample = (Instant::now(), hashes);
+        }
+
+        let avg_block_time_ms = if blocks_found == 0 {
+            None
+        } else {
+            Some(inner.mining_time.as_secs_f64() * 1000.0 / blocks_found as f64)
+        };
+
+        MinerStatus {
+            state: inner.state,
+            lambda: inner.lambda,
+            num_threads: inner.num_threads,
+            block_size_limit: inner.block_size_limit,
+            payout_address: inner.payout_address.map(|a| a.to_hex_string()),
+            hashes_attempted: hashes,
+            hash_rate: inner.hash_rate,
+            blocks_found,
+            stale_blocks: self.stale_blocks.load(Ordering::Relaxed),
+            avg_block_time_ms,
+            template: inner.template.clone(),
+        }
+    }
+}
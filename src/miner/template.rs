@@ -0,0 +1,245 @@
+use crate::blockchain::Blockchain;
+use crate::types::{
+    address::Address,
+    block::{Block, Content, Header},
+    hash::{H256, Hashable},
+    merkle::MerkleTree,
+    mempool::Mempool,
+    transaction::{self, SignedTransaction},
+};
+use std::sync::atomic::Ordering;
+
+// A BlockTemplateBuilder decides what goes into the next block the miner works on
+#[derive(Debug, Clone)]
+pub struct BlockTemplateBuilder {
+    block_size_limit: usize,    // upper limit on number of transactions per block
+    payout_address: Option<Address>,   // who a coinbase transaction would pay, once blocks carry one
+}
+
+// A BlockTemplate is a block whose header and content are assembled once, so the
+// hashing loop only has to fill in the nonce and timestamp
+#[derive(Debug, Clone)]
+pub struct BlockTemplate {
+    header: Header,
+    content: Content,
+    tip_version: u64,
+    mempool_version: u64,
+    block_size_limit: usize,
+}
+
+impl BlockTemplateBuilder {
+    pub fn new(block_size_limit: usize) -> Self {
+        Self { block_size_limit, payout_address: None }
+    }
+
+    pub fn block_size_limit(&self) -> usize {
+        self.block_size_limit
+    }
+
+    pub fn set_block_size_limit(&mut self, block_size_limit: usize) {
+        self.block_size_limit = block_size_limit;
+    }
+
+    pub fn payout_address(&self) -> Option<Address> {
+        self.payout_address
+    }
+
+    pub fn set_payout_address(&mut self, address: Address) {
+        self.payout_address = Some(address);
+    }
+
+    /// Build a template on top of the current tip, taking as many valid transactions
+    /// from the mempool as the size limit allows. The template may be empty.
+    /// Transactions that can never be included on this chain are evicted from the mempool.
+    pub fn build(&self, blockchain: &Blockchain, mempool: &mut Mempool) -> BlockTemplate {
+        // read both versions while holding the locks, so they match what we build on
+        let tip_version = blockchain.tip_version().load(Ordering::Acquire);
+        let mempool_version = mempool.version().load(Ordering::Acquire);
+
+        // Get current tip of blockchain to get parent_block, parent_state, difficulty
+        let parent_hash = blockchain.tip();
+        let parent_block = match blockchain.get_block(&parent_hash) {
+            Ok(block) => block,    // parent exists in blockchain
+            Err(_) => panic!("Parent node does not exist in blockchain."),   // parent not found
+        };
+        let parent_state = match blockchain.get_state(&parent_hash) {
+            Ok(state) => state,    // parent exists in blockchain
+            Err(_) => panic!("Parent node does not exist in blockchain."),   // parent not found
+        };
+        let difficulty = parent_block.get_difficulty();
+
+        let mut transactions: Vec<SignedTransaction> = Vec::new();
+        let mut removal_hashes = Vec::new();
+
+        // Iterate over the transactions in the mempool
+        for txn in mempool.transactions() {
+            // Break if the block transaction limit is reached
+            if transactions.len() == self.block_size_limit {
+                break;
+            }
+
+            // A transaction with a bad signature can never be valid
+            if !transaction::verify(&txn.transaction, &txn.public_key, &txn.signature) {
+                removal_hashes.push(txn.hash());
+                continue;
+            }
+
+            let sender_address = Address::from_public_key_bytes(&txn.public_key);
+            let (sender_nonce, sender_balance) = match parent_state.map.get(&sender_address) {
+                Some(sender_info) => *sender_info,
+                None => {
+                    // sender has no account, so this transaction can never be valid
+                    removal_hashes.push(txn.hash());
+                    continue;
+                }
+            };
+
+            // A transaction whose nonce is already used is a replay or a double spend
+            if txn.transaction.account_nonce <= sender_nonce {
+                removal_hashes.push(txn.hash());
+                continue;
+            }
+
+            // Check nonce, balance, and if sender is already included in the new block.
+            // Transactions failing these checks may become valid later, so they stay in the mempool.
+            let is_nonce_valid = txn.transaction.account_nonce == sender_nonce + 1;
+            let is_balance_sufficient = txn.transaction.value <= sender_balance;
+            let is_sender_unique = !transactions.iter().any(|x| Address::from_public_key_bytes(&x.public_key) == sender_address);
+
+            if is_nonce_valid && is_balance_sufficient && is_sender_unique {
+                transactions.push(txn.clone());
+            }
+        }
+
+        // Remove the transactions that can never be included from the mempool
+        for txn_hash in removal_hashes {
+            mempool.remove(&txn_hash);
+        }
+
+        let merkle_root = MerkleTree::new(&transactions).root();
+
+        let header = Header {
+            parent: parent_hash,
+            nonce: 0,
+            difficulty,
+            timestamp: 0,
+            merkle_root
+        };
+
+        BlockTemplate {
+            header,
+            content: Content { transactions },
+            tip_version,
+            mempool_version,
+            block_size_limit: self.block_size_limit,
+        }
+    }
+}
+
+impl BlockTemplate {
+    /// The header to hash, with the nonce and timestamp left for the miner to fill in
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn parent(&self) -> H256 {
+        self.header.parent
+    }
+
+    pub fn num_transactions(&self) -> usize {
+        self.content.transactions.len()
+    }
+
+    /// The blockchain tip version this template was built against
+    pub fn tip_version(&self) -> u64 {
+        self.tip_version
+    }
+
+    /// Whether the mempool has changed since this template was built in a way that
+    /// could add transactions to it
+    pub fn is_outdated(&self, mempool_version: u64) -> bool {
+        mempool_version != self.mempool_version && self.num_transactions() < self.block_size_limit
+    }
+
+    /// Turn a solved header into a full block
+    pub fn seal(&self, header: Header) -> Block {
+        Block { header, content: self.content.clone() }
+    }
+}
+
+// DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. BEFORE TEST
+
+#[cfg(test)]
+mod test {
+    use super::BlockTemplateBuilder;
+    use crate::blockchain::Blockchain;
+    use crate::types::{
+        address::Address,
+        hash::Hashable,
+        mempool::Mempool,
+        transaction::{self, SignedTransaction, Transaction},
+    };
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    fn signed_transaction(seed: u8, account_nonce: u128, value: u128) -> SignedTransaction {
+        let key = Ed25519KeyPair::from_seed_unchecked(&[seed; 32]).unwrap();
+        let receiver = Address::from_public_key_bytes(&[1u8; 32]);
+        let transaction = Transaction { account_nonce, receiver, value };
+        let signature = transaction::sign(&transaction, &key).as_ref().to_vec();
+        SignedTransaction {
+            transaction,
+            signature,
+            public_key: key.public_key().as_ref().to_vec(),
+        }
+    }
+
+    #[test]
+    fn empty_template() {
+        let blockchain = Blockchain::new();
+        let mut mempool = Mempool::new();
+        let template = BlockTemplateBuilder::new(30).build(&blockchain, &mut mempool);
+        assert_eq!(template.parent(), blockchain.tip());
+        assert_eq!(template.num_transactions(), 0);
+    }
+
+    #[test]
+    fn template_selects_valid_transactions() {
+        let blockchain = Blockchain::new();
+        let mut mempool = Mempool::new();
+        let valid = signed_transaction(0, 1, 10);
+        let future = signed_transaction(0, 2, 10);
+        let stale = signed_transaction(0, 0, 10);
+        let unknown_sender = signed_transaction(7, 1, 10);
+        let mut bad_signature = signed_transaction(0, 1, 20);
+        bad_signature.signature[0] ^= 0xff;
+        for txn in [&valid, &future, &stale, &unknown_sender, &bad_signature] {
+            mempool.insert(txn.clone());
+        }
+
+        let template = BlockTemplateBuilder::new(30).build(&blockchain, &mut mempool);
+        let block = template.seal(template.header().clone());
+        assert_eq!(block.content.transactions.len(), 1);
+        assert_eq!(block.content.transactions[0].hash(), valid.hash());
+
+        // stale, unknown-sender and badly signed transactions are evicted, the rest stay
+        assert!(mempool.contains(&valid.hash()));
+        assert!(mempool.contains(&future.hash()));
+        assert!(!mempool.contains(&stale.hash()));
+        assert!(!mempool.contains(&unknown_sender.hash()));
+        assert!(!mempool.contains(&bad_signature.hash()));
+    }
+
+    #[test]
+    fn template_outdated_by_mempool() {
+        let blockchain = Blockchain::new();
+        let mut mempool = Mempool::new();
+        let version = mempool.version();
+        let template = BlockTemplateBuilder::new(30).build(&blockchain, &mut mempool);
+        assert!(!template.is_outdated(version.load(std::sync::atomic::Ordering::Acquire)));
+
+        mempool.insert(signed_transaction(0, 1, 10));
+        assert!(template.is_outdated(version.load(std::sync::atomic::Ordering::Acquire)));
+    }
+}
+
+// DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. AFTER TEST
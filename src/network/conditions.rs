@@ -0,0 +1,210 @@
+use crate::simulation::clock::{Clock, SystemClock};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::cmp;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+// the most latency or jitter a link can have, an hour
+pub const MAX_DELAY_MS: u64 = 60 * 60 * 1000;
+
+/// What a simulated link does to the messages on it. A node applies the conditions of
+/// a peer to the messages it sends the peer and to those it receives from it, so a link
+/// only needs them set at one end.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LinkConditions {
+    pub latency_ms: u64,            // added to every message, each way
+    pub jitter_ms: u64,             // up to this much more latency at random, keeping messages in order
+    pub bandwidth: Option<u64>,     // bytes per second each way; unlimited if not set
+    pub drop_rate: f64,             // the chance of losing each message
+    pub partitioned: bool,          // lose every message, keeping the connection open
+}
+
+/// The conditions of a node's links to its peers, shared by the P2P server and the API.
+/// Peers are named by the address they have in the server's peer list. Messages arrive
+/// by the clock of the conditions, which is the simulation's.
+#[derive(Clone)]
+pub struct Conditions {
+    inner: Arc<Mutex<Inner>>,
+    clock: Arc<dyn Clock>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    default: LinkConditions,    // for peers without conditions of their own
+    links: HashMap<SocketAddr, LinkConditions>,
+    rng: StdRng,                // for drops and jitter
+}
+
+/// Schedules the messages going one way over a link, as they would arrive
+#[derive(Debug)]
+pub struct Shaper {
+    conditions: Conditions,
+    peer: SocketAddr,
+    next_free: u64,     // when the link is done transmitting the previous messages
+    last_arrival: u64,
+}
+
+impl Default for Conditions {
+    fn default() -> Self {
+        Self::new(StdRng::from_entropy(), Arc::new(SystemClock))
+    }
+}
+
+impl fmt::Debug for Conditions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Conditions").field("inner", &self.inner).finish_non_exhaustive()
+    }
+}
+
+impl LinkConditions {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&self.drop_rate) {
+            return Err(format!("drop_rate {} is not between 0 and 1", self.drop_rate));
+        }
+        if self.latency_ms > MAX_DELAY_MS {
+            return Err(format!("latency_ms {} is more than {}", self.latency_ms, MAX_DELAY_MS));
+        }
+        if self.jitter_ms > MAX_DELAY_MS {
+            return Err(format!("jitter_ms {} is more than {}", self.jitter_ms, MAX_DELAY_MS));
+        }
+        if self.bandwidth == Some(0) {
+            return Err("bandwidth must be at least 1 byte per second".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl Conditions {
+    /// Ideal links, with drops and jitter drawn from `rng`, on which messages arrive by `clock`
+    pub fn new(rng: StdRng, clock: Arc<dyn Clock>) -> Self {
+        let inner = Inner { default: LinkConditions::default(), links: HashMap::new(), rng };
+        Self { inner: Arc::new(Mutex::new(inner)), clock }
+    }
+
+    /// The time by the clock of the links, in milliseconds
+    pub fn now(&self) -> u64 {
+        self.clock.now_millis() as u64
+    }
+
+    /// The conditions of links to peers that have none of their own
+    pub fn default_link(&self) -> LinkConditions {
+        self.inner.lock().unwrap().default.clone()
+    }
+
+    pub fn set_default_link(&self, link: LinkConditions) {
+        self.inner.lock().unwrap().default = link;
+    }
+
+    /// The conditions of the link to a peer
+    pub fn link(&self, peer: &SocketAddr) -> LinkConditions {
+        let inner = self.inner.lock().unwrap();
+        inner.links.get(peer).unwrap_or(&inner.default).clone()
+    }
+
+    pub fn set_link(&self, peer: SocketAddr, link: LinkConditions) {
+        self.inner.lock().unwrap().links.insert(peer, link);
+    }
+
+    /// Return the link to a peer to the default conditions
+    pub fn reset_link(&self, peer: &SocketAddr) {
+        self.inner.lock().unwrap().links.remove(peer);
+    }
+
+    /// Make every link ideal again
+    pub fn reset(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.default = LinkConditions::default();
+        inner.links.clear();
+    }
+
+    /// The peers with conditions of their own, ordered by address
+    pub fn links(&self) -> Vec<(SocketAddr, LinkConditions)> {
+        let mut links: Vec<_> = self.inner.lock().unwrap().links.iter().map(|(peer, link)| (*peer, link.clone())).collect();
+        links.sort_by_key(|(peer, _)| *peer);
+        links
+    }
+
+    /// A shaper for the messages going one way over the link to a peer
+    pub fn shaper(&self, peer: SocketAddr) -> Shaper {
+        let now = self.now();
+        Shaper { conditions: self.clone(), peer, next_free: now, last_arrival: now }
+    }
+
+    // the conditions of a link, whether the next message is lost and how much jitter it gets
+    fn sample(&self, peer: &SocketAddr) -> (LinkConditions, bool, u64) {
+        let mut inner = self.inner.lock().unwrap();
+        let link = inner.links.get(peer).unwrap_or(&inner.default).clone();
+        let lost = link.partitioned || (link.drop_rate > 0.0 && inner.rng.gen_bool(link.drop_rate));
+        let jitter = if link.jitter_ms > 0 { inner.rng.gen_range(0..=link.jitter_ms) } else { 0 };
+        (link, lost, jitter)
+    }
+}
+
+impl Shaper {
+    /// When a message of `len` bytes, sent now, arrives at the other end of the link, in
+    /// milliseconds by the clock of the conditions, or None if it is lost. A message takes
+    /// its turn on the link's bandwidth, then the latency, and never overtakes the messages
+    /// sent before it. A message that would arrive too far in the future to represent is
+    /// lost too.
+    pub fn schedule(&mut self, len: usize) -> Option<u64> {
+        let (link, lost, jitter) = self.conditions.sample(&self.peer);
+        if lost {
+            return None;
+        }
+        let now = self.conditions.now();
+        // rounded up to whole milliseconds
+        let transmission = link.bandwidth.map_or(0, |bandwidth| (len as u64 * 1000).div_ceil(bandwidth));
+        self.next_free = cmp::max(self.next_free, now).checked_add(transmission)?;
+        let arrival = self.next_free.checked_add(link.latency_ms + jitter)?;
+        self.last_arrival = cmp::max(self.last_arrival, arrival);
+        Some(self.last_arrival)
+    }
+}
+
+// DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. BEFORE TEST
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::simulation::clock::ManualClock;
+
+    #[test]
+    fn schedule_messages() {
+        let clock = Arc::new(ManualClock::new(10_000, 0));
+        let conditions = Conditions::new(StdRng::seed_from_u64(0), clock.clone());
+        let peer: SocketAddr = "127.0.0.1:6000".parse().unwrap();
+        let other: SocketAddr = "127.0.0.1:6001".parse().unwrap();
+        let mut shaper = conditions.shaper(peer);
+        assert_eq!(shaper.schedule(100), Some(10_000));
+
+        // 1000 bytes take a second each at 1000 bytes per second, then the latency
+        conditions.set_link(peer, LinkConditions { latency_ms: 50, bandwidth: Some(1000), ..Default::default() });
+        assert_eq!(shaper.schedule(1000), Some(11_050));
+        assert_eq!(shaper.schedule(1000), Some(12_050));
+        // the link is free again once the clock passes the last transmission
+        clock.advance(5_000);
+        assert_eq!(shaper.schedule(500), Some(15_550));
+        // jitter never lets a message overtake an earlier one
+        conditions.set_link(peer, LinkConditions { jitter_ms: 1_000, ..Default::default() });
+        let arrivals: Vec<_> = (0..100).map(|_| shaper.schedule(10).unwrap()).collect();
+        assert!(arrivals.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(arrivals.iter().all(|arrival| *arrival >= 15_550 && *arrival <= 16_500));
+        assert_eq!(conditions.link(&other), LinkConditions::default());
+
+        conditions.set_link(peer, LinkConditions { partitioned: true, ..Default::default() });
+        assert!(shaper.schedule(10).is_none());
+        conditions.set_default_link(LinkConditions { drop_rate: 0.5, ..Default::default() });
+        conditions.reset_link(&peer);
+        let lost = (0..1000).filter(|_| shaper.schedule(10).is_none()).count();
+        assert!(lost > 400 && lost < 600);
+        assert!(LinkConditions { drop_rate: 1.5, ..Default::default() }.validate().is_err());
+        assert!(LinkConditions { latency_ms: u64::MAX, ..Default::default() }.validate().is_err());
+        assert!(LinkConditions { jitter_ms: MAX_DELAY_MS + 1, ..Default::default() }.validate().is_err());
+    }
+}
+
+// DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. AFTER TEST
@@ -13,3 +13,19 @@ pub enum Message {
     GetTransactions(Vec<H256>),
     Transactions(Vec<SignedTransaction>),
 }
+
+impl Message {
+    /// The name of the message type, for metrics
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Message::Ping(_) => "ping",
+            Message::Pong(_) => "pong",
+            Message::NewBlockHashes(_) => "new_block_hashes",
+            Message::GetBlocks(_) => "get_blocks",
+            Message::Blocks(_) => "blocks",
+            Message::NewTransactionHashes(_) => "new_transaction_hashes",
+            Message::GetTransactions(_) => "get_transactions",
+            Message::Transactions(_) => "transactions",
+        }
+    }
+}
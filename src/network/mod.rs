@@ -1,4 +1,6 @@
+pub mod conditions;
 pub mod message;
 pub mod peer;
 pub mod server;
+pub mod transport;
 pub mod worker;
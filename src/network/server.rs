@@ -1,12 +1,15 @@
+use crate::events::{Event, EventBus};
+use crate::metrics::Metrics;
 use crate::types::address::Address;
 use super::peer;
 use super::message;
+use super::transport::Transport;
 
 use async_dup::Arc as AsyncArc;
 use futures::io::{AsyncReadExt, AsyncWriteExt};
 use futures::io::{BufReader, BufWriter};
 use futures::{channel::oneshot, stream::StreamExt};
-use smol::{Async, Executor};
+use smol::{Async, Executor, Task};
 use log::{debug, info, trace};
 use std::net;
 use std::sync::Arc;
@@ -23,42 +26,72 @@ pub fn new(
     };
     let ctx = Context {
         peers: std::collections::HashMap::new(),
+        streams: std::collections::HashMap::new(),
+        next_connection: 0,
         addr,
         control_chan: control_signal_receiver,
         control_sender: control_signal_sender,
         new_msg_chan: msg_sink,
+        events: EventBus::default(),
+        metrics: Metrics::default(),
+        transport: Transport::default(),
     };
     Ok((ctx, handle))
 }
 
 pub struct Context {
     peers: std::collections::HashMap<std::net::SocketAddr, peer::Handle>,
+    streams: std::collections::HashMap<std::net::SocketAddr, (u64, AsyncArc<Async<net::TcpStream>>)>,   // with the id of the connection
+    next_connection: u64,
     addr: std::net::SocketAddr,
     control_chan: smol::channel::Receiver<ControlSignal>,
     control_sender: smol::channel::Sender<ControlSignal>,
     new_msg_chan: smol::channel::Sender<(Vec<u8>, peer::Handle)>,
+    events: EventBus,
+    metrics: Metrics,
+    transport: Transport,
 }
 
 impl Context {
-    /// Start a new server context.
-    pub fn start(self) -> std::io::Result<()> {
+    /// Count peers, their traffic and our broadcasts in `metrics`
+    pub fn set_metrics(&mut self, metrics: Metrics) {
+        self.metrics = metrics;
+    }
+
+    /// Publish peers connecting and disconnecting on an event bus
+    pub fn set_events(&mut self, events: EventBus) {
+        self.events = events;
+    }
+
+    /// Carry the messages to and from peers over `transport`, such as simulated links.
+    /// Messages go straight to and from the sockets until a transport is set.
+    pub fn set_transport(&mut self, transport: Transport) {
+        self.transport = transport;
+    }
+
+    /// Start a new server context, returning its thread, which ends once the server is shut
+    /// down, and the address it listens at, which has a free port if it asked for port 0
+    pub fn start(mut self) -> std::io::Result<(thread::JoinHandle<()>, std::net::SocketAddr)> {
         // initialize the server socket
         let listener = Async::<net::TcpListener>::bind(self.addr)?;
-        info!("P2P server listening at {}", self.addr);
+        self.addr = listener.get_ref().local_addr()?;
+        let addr = self.addr;
+        info!("P2P server listening at {}", addr);
         let control_chan = self.control_sender.clone();
         let ex = Executor::new();
         let ex = Arc::new(ex);
         let ex_clone = ex.clone();
-        ex.spawn(async move {
-            self.dispatch_control(ex_clone).await.unwrap();
-        })
-            .detach();
-        ex.spawn(async move {
+        // dropping this task, on shutdown, stops accepting peers
+        let listener_task = ex.spawn(async move {
             Self::listener_loop(listener, control_chan).await.unwrap();
-        })
-            .detach();
-        thread::spawn(move || smol::block_on(ex.run(futures::future::pending::<()>())));
-        return Ok(());
+        });
+        let handle = thread::Builder::new()
+            .name("p2p-server".to_string())
+            .spawn(move || {
+                // the peers' tasks are dropped, closing their connections, along with the executor
+                smol::block_on(ex.run(self.dispatch_control(ex_clone, listener_task))).unwrap();
+            })?;
+        Ok((handle, addr))
     }
 
     /// the loop that endlessly accept incoming peers
@@ -72,14 +105,28 @@ impl Context {
                 .send(ControlSignal::GetNewPeer(stream))
                 .await
                 .unwrap();
-            info!("Incoming peer from {}", addr);
+            info!(peer:% = addr; "Incoming peer");
         }
     }
 
-    async fn dispatch_control(mut self, ex: Arc<Executor<'_>>) -> std::io::Result<()> {
+    async fn dispatch_control(mut self, ex: Arc<Executor<'_>>, listener_task: Task<()>) -> std::io::Result<()> {
         // read the next control signal
         while let Ok(ctrl) = self.control_chan.recv().await {
             match ctrl {
+                ControlSignal::Shutdown(done) => {
+                    drop(listener_task);
+                    for (addr, (_, stream)) in self.streams.drain() {
+                        // wakes the peer's reader, which then ends
+                        let _ = stream.get_ref().shutdown(net::Shutdown::Both);
+                        if self.peers.remove(&addr).is_some() {
+                            self.events.publish(Event::PeerDisconnected { addr: addr.to_string() });
+                        }
+                    }
+                    self.metrics.set("bitcoin_peers", &[], 0.0);
+                    info!("P2P server shut down");
+                    let _ = done.send(());
+                    return Ok(());
+                }
                 ControlSignal::ConnectNewPeer(addr, result_chan) => {
                     trace!("Processing ConnectNewPeer command");
                     let handle = self.connect(&addr, ex.clone()).await;
@@ -87,6 +134,7 @@ impl Context {
                 }
                 ControlSignal::BroadcastMessage(msg) => {
                     trace!("Processing BroadcastMessage command");
+                    self.metrics.inc("bitcoin_messages_broadcast_total", &[("type", msg.kind())]);
                     for (_, hd) in self.peers.iter_mut() {
                         hd.write(msg.clone());
                     }
@@ -95,17 +143,48 @@ impl Context {
                     trace!("Processing GetNewPeer command");
                     self.accept(stream, ex.clone()).await?;
                 }
-                ControlSignal::DroppedPeer(addr) => {
+                ControlSignal::DroppedPeer(addr, connection) => {
                     trace!("Processing DroppedPeer({})", addr);
-                    self.peers.remove(&addr);
-                    info!("Peer {} disconnected", addr);
+                    // both the reader and the writer report a connection ending, and a newer
+                    // connection to the same address may have replaced it since
+                    if self.streams.get(&addr).is_some_and(|(current, _)| *current == connection) {
+                        self.remove_peer(addr);
+                    }
+                }
+                ControlSignal::Disconnect(addr, result_chan) => {
+                    trace!("Processing Disconnect({})", addr);
+                    let connected = match self.streams.get(&addr) {
+                        Some((_, stream)) => {
+                            // wakes the peer's reader, which then ends
+                            let _ = stream.get_ref().shutdown(net::Shutdown::Both);
+                            self.remove_peer(addr);
+                            true
+                        }
+                        None => false,
+                    };
+                    let _ = result_chan.send(connected);
+                }
+                ControlSignal::GetPeers(result_chan) => {
+                    let _ = result_chan.send(self.peers.keys().copied().collect());
                 }
                 ControlSignal::SendToPeer((_receiver, _msg)) => {
                     unimplemented!()
                 }
             }
         }
-        return Ok(());
+        Ok(())
+    }
+
+    fn remove_peer(&mut self, addr: std::net::SocketAddr) {
+        self.streams.remove(&addr);
+        if self.peers.remove(&addr).is_some() {
+            self.events.publish(Event::PeerDisconnected { addr: addr.to_string() });
+        }
+        let peer = addr.to_string();
+        self.metrics.remove("bitcoin_peer_bytes_received_total", &[("peer", &peer)]);
+        self.metrics.remove("bitcoin_peer_bytes_sent_total", &[("peer", &peer)]);
+        self.metrics.set("bitcoin_peers", &[], self.peers.len() as f64);
+        info!(peer:% = addr; "Peer disconnected");
     }
 
     /// Connect to a peer, and register this peer
@@ -114,8 +193,8 @@ impl Context {
         addr: &std::net::SocketAddr,
         ex: Arc<Executor<'_>>,
     ) -> std::io::Result<peer::Handle> {
-        debug!("Establishing connection to peer {}", addr);
-        let stream = Async::<std::net::TcpStream>::connect(addr.clone()).await?;
+        debug!(peer:% = addr; "Establishing connection to peer");
+        let stream = Async::<std::net::TcpStream>::connect(*addr).await?;
 
         // register the new peer
         self.register(stream, peer::Direction::Outgoing, ex).await
@@ -136,30 +215,31 @@ impl Context {
         _direction: peer::Direction,
         ex: Arc<Executor<'_>>,
     ) -> std::io::Result<peer::Handle> {
-        let (mut write_queue, handle) = peer::new(&stream)?;
+        let (write_queue, handle) = peer::new(&stream)?;
 
         let stream = AsyncArc::new(stream);
         let new_msg_chan = self.new_msg_chan.clone();
         let handle_copy = handle.clone();
         let control_chan = self.control_sender.clone();
         let addr = stream.get_ref().peer_addr()?;
+        let connection = self.next_connection;
+        self.next_connection += 1;
 
         // start the reactor for this peer
         // first, start a task that keeps reading from this guy
         let mut reader = BufReader::new(stream.clone());
+        let metrics = self.metrics.clone();
+        let peer = addr.to_string();
+        let reader_control_chan = self.control_sender.clone();
+        let mut incoming = self.transport.incoming(&ex, addr, new_msg_chan, handle_copy, self.metrics.clone());
         ex.spawn(async move {
             // the buffer to store the frame header, which contains the length of the frame
             let mut size_buffer: [u8; 4] = [0; 4];
             // the buffer to store the message content
             let mut msg_buffer: Vec<u8> = vec![];
-            loop {
-                // first, read exactly 4 bytes to get the frame header
-                let msg_size = match reader.read_exact(&mut size_buffer).await {
-                    Ok(_) => u32::from_be_bytes(size_buffer),
-                    Err(_) => {
-                        break;
-                    }
-                };
+            // first, read exactly 4 bytes to get the frame header
+            while reader.read_exact(&mut size_buffer).await.is_ok() {
+                let msg_size = u32::from_be_bytes(size_buffer);
                 // then, read exactly msg_size bytes to get the whole message
                 if msg_buffer.len() < msg_size as usize {
                     msg_buffer.resize(msg_size as usize, 0);
@@ -169,28 +249,30 @@ impl Context {
                     .await
                 {
                     Ok(_) => {
+                        metrics.add("bitcoin_peer_bytes_received_total", &[("peer", &peer)], 4 + msg_size as u64);
                         let new_payload: Vec<u8> = msg_buffer[0..msg_size as usize].to_vec();
-                        new_msg_chan
-                            .send((new_payload, handle_copy.clone()))
-                            .await
-                            .unwrap();
+                        if !incoming.send(new_payload).await {
+                            break;  // the workers have stopped
+                        }
                     }
                     Err(_) => {
                         break;
                     }
                 }
             }
-            // the peer is disconnected
+            // the peer is disconnected; nobody is listening if the server was shut down
+            let _ = reader_control_chan.send(ControlSignal::DroppedPeer(addr, connection)).await;
         })
             .detach();
 
-        // second, start a task that keeps writing to this guy
+        // second, start a task that keeps writing to this guy, once the transport delivers each message
+        let mut write_queue = self.transport.outgoing(&ex, addr, write_queue, self.metrics.clone());
         let mut writer = BufWriter::new(stream.clone());
+        let metrics = self.metrics.clone();
+        let peer = addr.to_string();
         ex.spawn(async move {
-            loop {
-                // first, get a message to write from the queue
-                let new_msg = write_queue.next().await.unwrap();
-
+            // first, get a message to write from the queue
+            while let Some(new_msg) = write_queue.next().await {
                 // second, encode the length of the message
                 let size_buffer = (new_msg.len() as u32).to_be_bytes();
 
@@ -213,17 +295,18 @@ impl Context {
                         break;
                     }
                 }
+                metrics.add("bitcoin_peer_bytes_sent_total", &[("peer", &peer)], (size_buffer.len() + new_msg.len()) as u64);
             }
-            // the peer is disconnected
-            control_chan
-                .send(ControlSignal::DroppedPeer(addr))
-                .await
-                .unwrap();
+            // the peer is disconnected; nobody is listening if the server was shut down
+            let _ = control_chan.send(ControlSignal::DroppedPeer(addr, connection)).await;
         })
             .detach();
 
         // insert the peer handle so that we can broadcast to this guy later
         self.peers.insert(addr, handle.clone());
+        self.streams.insert(addr, (connection, stream));
+        self.events.publish(Event::PeerConnected { addr: addr.to_string() });
+        self.metrics.set("bitcoin_peers", &[], self.peers.len() as f64);
         Ok(handle)
     }
 }
@@ -232,11 +315,11 @@ impl Context {
 pub struct Handle {
     control_chan: smol::channel::Sender<ControlSignal>,
 }
-#[cfg(any(test,test_utilities))]
+#[cfg(any(test, feature = "test-utilities"))]
 pub struct TestReceiver{
     control_chan: smol::channel::Receiver<ControlSignal>,
 }
-#[cfg(any(test,test_utilities))]
+#[cfg(any(test, feature = "test-utilities"))]
 impl TestReceiver {
     pub fn recv(&self) -> Option<message::Message> {
         let sig = smol::block_on(self.control_chan.recv()).unwrap();
@@ -251,23 +334,55 @@ impl TestReceiver {
 impl Handle {
     pub fn connect(&self, addr: std::net::SocketAddr) -> std::io::Result<peer::Handle> {
         let (sender, receiver) = oneshot::channel();
+        let shut_down = || std::io::Error::new(std::io::ErrorKind::NotConnected, "the P2P server is shut down");
         smol::block_on(
             self.control_chan
                 .send(ControlSignal::ConnectNewPeer(addr, sender)),
         )
-            .unwrap();
-        smol::block_on(receiver).unwrap()
+            .map_err(|_| shut_down())?;
+        smol::block_on(receiver).map_err(|_| shut_down())?
+    }
+
+    /// Close the connection to a peer, by the address it is known by in `peers`.
+    /// Returns false if there was no such peer.
+    pub fn disconnect(&self, addr: std::net::SocketAddr) -> bool {
+        let (sender, receiver) = oneshot::channel();
+        if smol::block_on(self.control_chan.send(ControlSignal::Disconnect(addr, sender))).is_err() {
+            return false;
+        }
+        smol::block_on(receiver).unwrap_or(false)
+    }
+
+    /// The addresses of the connected peers: the address we connected to for the peers
+    /// we connected to, and the address they connect from for the others
+    pub fn peers(&self) -> Vec<std::net::SocketAddr> {
+        let (sender, receiver) = oneshot::channel();
+        if smol::block_on(self.control_chan.send(ControlSignal::GetPeers(sender))).is_err() {
+            return Vec::new();
+        }
+        smol::block_on(receiver).unwrap_or_default()
     }
 
     pub fn broadcast(&self, msg: message::Message) {
-        smol::block_on(self.control_chan.send(ControlSignal::BroadcastMessage(msg))).unwrap();
+        if smol::block_on(self.control_chan.send(ControlSignal::BroadcastMessage(msg))).is_err() {
+            trace!("Not broadcasting, the P2P server is shut down");
+        }
+    }
+
+    /// Stop accepting peers and disconnect from every peer. Once the last message
+    /// received is handled, the network workers exit.
+    pub fn shutdown(&self) {
+        let (sender, receiver) = oneshot::channel();
+        if smol::block_on(self.control_chan.send(ControlSignal::Shutdown(sender))).is_ok() {
+            let _ = smol::block_on(receiver);
+        }
     }
 
     pub fn send(&self, receiver: Address, msg: message::Message) {
         smol::block_on(self.control_chan.send(ControlSignal::SendToPeer((receiver, msg)))).unwrap();
     }
 
-    #[cfg(any(test,test_utilities))]
+    #[cfg(any(test, feature = "test-utilities"))]
     pub fn new_for_test() -> (Handle, TestReceiver) {
         let (s,r) = smol::channel::unbounded();
         let h = Handle {control_chan: s};
@@ -283,6 +398,9 @@ enum ControlSignal {
     ),
     BroadcastMessage(message::Message),
     GetNewPeer(Async<net::TcpStream>),
-    DroppedPeer(std::net::SocketAddr),
+    DroppedPeer(std::net::SocketAddr, u64),     // with the id of the connection that ended
+    Disconnect(std::net::SocketAddr, oneshot::Sender<bool>),
+    GetPeers(oneshot::Sender<Vec<std::net::SocketAddr>>),
     SendToPeer((Address,message::Message)),
+    Shutdown(oneshot::Sender<()>),
 }
@@ -0,0 +1,152 @@
+use crate::metrics::Metrics;
+use super::conditions::{Conditions, Shaper};
+use super::peer;
+
+use futures::channel::mpsc;
+use futures::stream::{self, BoxStream, StreamExt};
+use smol::Executor;
+use std::cmp;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+// the messages on their way over a simulated link, one way, before the sender waits
+const LINK_BUFFER: usize = 1000;
+
+// how often a message waiting to arrive checks the clock, which a simulation may move at will
+const CLOCK_POLL: Duration = Duration::from_millis(10);
+
+/// How messages travel between the P2P server and the sockets of its peers
+#[derive(Debug, Clone, Default)]
+pub enum Transport {
+    /// Straight to and from the sockets, like a real network
+    #[default]
+    Direct,
+    /// Over simulated links, which delay, throttle and drop messages as the conditions say
+    Simulated(Conditions),
+}
+
+/// Where the reader of a peer's socket puts the messages it reads
+pub enum Incoming {
+    Direct {
+        workers: smol::channel::Sender<(Vec<u8>, peer::Handle)>,
+        handle: peer::Handle,
+    },
+    Simulated(Link),
+}
+
+/// The messages going one way over a simulated link, queued until they arrive
+pub struct Link {
+    shaper: Shaper,
+    queue: smol::channel::Sender<(u64, Vec<u8>)>,
+    direction: &'static str,
+    metrics: Metrics,
+}
+
+impl Transport {
+    /// The way from the reader of `peer`'s socket to the workers, who get each message
+    /// along with the peer's handle once the link delivers it
+    pub fn incoming(
+        &self,
+        ex: &Executor<'_>,
+        peer: SocketAddr,
+        workers: smol::channel::Sender<(Vec<u8>, peer::Handle)>,
+        handle: peer::Handle,
+        metrics: Metrics,
+    ) -> Incoming {
+        let conditions = match self {
+            Transport::Direct => return Incoming::Direct { workers, handle },
+            Transport::Simulated(conditions) => conditions,
+        };
+        let (link, arrivals) = Link::open(conditions, peer, "received", metrics);
+        let conditions = conditions.clone();
+        ex.spawn(async move {
+            while let Ok((arrival, payload)) = arrivals.recv().await {
+                wait_until(&conditions, arrival).await;
+                if workers.send((payload, handle.clone())).await.is_err() {
+                    // the workers have stopped, so the reader stops at its next message
+                    arrivals.close();
+                    break;
+                }
+            }
+        })
+            .detach();
+        Incoming::Simulated(link)
+    }
+
+    /// The messages written to `peer`'s handle, as the link delivers them to its socket.
+    /// The stream ends once every handle to the peer is dropped.
+    pub fn outgoing(
+        &self,
+        ex: &Executor<'_>,
+        peer: SocketAddr,
+        mut write_queue: mpsc::UnboundedReceiver<Vec<u8>>,
+        metrics: Metrics,
+    ) -> BoxStream<'static, Vec<u8>> {
+        let conditions = match self {
+            Transport::Direct => return write_queue.boxed(),
+            Transport::Simulated(conditions) => conditions,
+        };
+        let (mut link, arrivals) = Link::open(conditions, peer, "sent", metrics);
+        ex.spawn(async move {
+            while let Some(msg) = write_queue.next().await {
+                if !link.send(msg).await {
+                    break;  // the writer has stopped
+                }
+            }
+        })
+            .detach();
+        let conditions = conditions.clone();
+        stream::unfold((arrivals, conditions), |(arrivals, conditions)| async move {
+            let (arrival, msg) = arrivals.recv().await.ok()?;
+            wait_until(&conditions, arrival).await;
+            Some((msg, (arrivals, conditions)))
+        })
+            .boxed()
+    }
+}
+
+impl Incoming {
+    /// Pass on a message read from the peer. Returns false once the workers have stopped.
+    pub async fn send(&mut self, payload: Vec<u8>) -> bool {
+        match self {
+            Incoming::Direct { workers, handle } => workers.send((payload, handle.clone())).await.is_ok(),
+            Incoming::Simulated(link) => link.send(payload).await,
+        }
+    }
+}
+
+impl Link {
+    fn open(
+        conditions: &Conditions,
+        peer: SocketAddr,
+        direction: &'static str,
+        metrics: Metrics,
+    ) -> (Self, smol::channel::Receiver<(u64, Vec<u8>)>) {
+        let (queue, arrivals) = smol::channel::bounded(LINK_BUFFER);
+        let link = Self { shaper: conditions.shaper(peer), queue, direction, metrics };
+        (link, arrivals)
+    }
+
+    /// Put a message on the link, unless the link loses it, waiting while the link is full.
+    /// Returns false once the other end has stopped taking messages.
+    pub async fn send(&mut self, msg: Vec<u8>) -> bool {
+        match self.shaper.schedule(4 + msg.len()) {
+            Some(arrival) => self.queue.send((arrival, msg)).await.is_ok(),
+            None => {
+                self.metrics.inc("bitcoin_link_messages_lost_total", &[("direction", self.direction)]);
+                true
+            }
+        }
+    }
+}
+
+// wait until the clock of the conditions reaches `arrival`
+async fn wait_until(conditions: &Conditions, arrival: u64) {
+    loop {
+        let now = conditions.now();
+        if now >= arrival {
+            break;
+        }
+        smol::Timer::after(cmp::min(Duration::from_millis(arrival - now), CLOCK_POLL)).await;
+    }
+}
@@ -3,22 +3,24 @@ use super::peer;
 use super::server::Handle as ServerHandle;
 use crate::types::{
     hash::{H256, Hashable},
-    mempool::Mempool,
-    transaction,
+    mempool::{Mempool, Rejection},
     block::{Block},
 };
 use crate::blockchain::Blockchain;
+use crate::metrics::Metrics;
+use crate::miner::Handle as MinerHandle;
+use crate::simulation::clock::{Clock, SystemClock};
 use std::{
     sync::{Arc, Mutex},
     thread,
 };
 use std::collections::HashMap;
-use log::{debug, warn, error};
+use log::debug;
 
 
-#[cfg(any(test,test_utilities))]
+#[cfg(test)]
 use super::peer::TestReceiver as PeerTestReceiver;
-#[cfg(any(test,test_utilities))]
+#[cfg(test)]
 use super::server::TestReceiver as ServerTestReceiver;
 #[derive(Clone)]
 pub struct Worker {
@@ -26,7 +28,11 @@ pub struct Worker {
     num_worker: usize,
     server: ServerHandle,
     blockchain: Arc<Mutex<Blockchain>>,
-    mempool: Arc<Mutex<Mempool>>
+    mempool: Arc<Mutex<Mempool>>,
+    miner: MinerHandle,
+    orphans: Arc<Mutex<HashMap<H256, Vec<Block>>>>,    // blocks waiting for their parent, by the parent's hash
+    metrics: Metrics,
+    clock: Arc<dyn Clock>,     // times how long blocks take to arrive
 }
 
 
@@ -36,49 +42,65 @@ impl Worker {
         msg_src: smol::channel::Receiver<(Vec<u8>, peer::Handle)>,
         server: &ServerHandle,
         blockchain: &Arc<Mutex<Blockchain>>,
-        mempool: &Arc<Mutex<Mempool>>
+        mempool: &Arc<Mutex<Mempool>>,
+        miner: &MinerHandle
     ) -> Self {
         Self {
             msg_chan: msg_src,
             num_worker,
             server: server.clone(),
             blockchain: Arc::clone(blockchain),
-            mempool: Arc::clone(mempool)
+            mempool: Arc::clone(mempool),
+            miner: miner.clone(),
+            orphans: Arc::new(Mutex::new(HashMap::new())),
+            metrics: Metrics::default(),
+            clock: Arc::new(SystemClock),
         }
     }
 
-    pub fn start(self) {
+    /// Count the messages, blocks and transactions received in `metrics`
+    pub fn set_metrics(&mut self, metrics: Metrics) {
+        self.metrics = metrics;
+    }
+
+    /// Time block propagation by `clock`, the one blocks are stamped by
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Start the worker threads, which exit once the server is shut down and the messages it received are handled
+    pub fn start(self) -> Vec<thread::JoinHandle<()>> {
         let num_worker = self.num_worker;
-        for i in 0..num_worker {
-            let cloned = self.clone();
-            thread::spawn(move || {
-                cloned.worker_loop();
-                warn!("Worker thread {} exited", i);
-            });
-        }
+        (0..num_worker)
+            .map(|i| {
+                let cloned = self.clone();
+                thread::Builder::new()
+                    .name(format!("p2p-worker-{}", i))
+                    .spawn(move || {
+                        cloned.worker_loop();
+                        debug!("Worker thread {} exited", i);
+                    })
+                    .unwrap()
+            })
+            .collect()
     }
 
     fn worker_loop(&self) {
-        let mut orphan_buffer: HashMap<H256, Vec<Block>> = HashMap::new();
-        loop {
-            let result = smol::block_on(self.msg_chan.recv());
-            if let Err(e) = result {
-                error!("network worker terminated {}", e);
-                break;
-            }
-            let msg = result.unwrap();
-            let (msg, mut peer) = msg;
+        // the channel is closed, after the messages in it, once the server is shut down
+        while let Ok((msg, mut peer)) = smol::block_on(self.msg_chan.recv()) {
             let msg: Message = bincode::deserialize(&msg).unwrap();
+            self.metrics.inc("bitcoin_messages_received_total", &[("type", msg.kind())]);
+            let _span = crate::span!("handle_message", peer = peer.addr(), kind = msg.kind());
             match msg {
                 // PING
                 Message::Ping(nonce) => {
-                    debug!("Ping: {}", nonce);
+                    debug!(nonce:%; "Ping");
                     peer.write(Message::Pong(nonce.to_string()));
                 }
 
                 // PONG
                 Message::Pong(nonce) => {
-                    debug!("Pong: {}", nonce);
+                    debug!(nonce:%; "Pong");
                 }
 
                 // NEW BLOCK HASHES
@@ -90,10 +112,10 @@ impl Worker {
                         // Get the hash and check
                         let block_hash = blockchain.get_block(hash);
                         match block_hash {
-                            Ok(_) => {()}
+                            Ok(_) => {}
                             Err(_) => {
                                 // Add the hash to the unknown vector
-                                unknown.push(hash.clone());
+                                unknown.push(*hash);
                             }
                         }
                     }
@@ -113,11 +135,8 @@ impl Worker {
                     
                     for hash in hashes.iter() {
                         let result = blockchain.get_block(hash);
-                        match result {
-                            Ok(block) => {
-                                known.push(block.clone());
-                            }
-                            Err(_) => {()}
+                        if let Ok(block) = result {
+                            known.push(block.clone());
                         }
                     }
 
@@ -130,17 +149,21 @@ impl Worker {
 
                 // BLOCKS
                 Message::Blocks(blocks) => {
+                    self.metrics.add("bitcoin_blocks_received_total", &[], blocks.len() as u64);
                     let mut blockchain = self.blockchain.lock().unwrap();
-                    
+                    // shared by the worker threads, since a block and its parent may come to different ones
+                    let mut orphan_buffer = self.orphans.lock().unwrap();
+
                     let mut new_block_hashes = Vec::new();
                     let mut blocks = blocks.clone();
 
                     let mut i = 0;
                     while i < blocks.len() {
                         let block = &blocks[i].clone();                 
+                        i += 1;    // next block
 
                         // Skip if block hash exceeds difficulty
-                        if block.hash() > block.get_difficulty() {
+                        if !blockchain.satisfies_pow(block) {
                             continue;
                         }
 
@@ -150,15 +173,20 @@ impl Worker {
                         }
 
                         // Attempt to insert this block into the blockchain
-                        match blockchain.insert(&block) {
+                        match blockchain.insert(block) {
                             // Block was successfully inserted into blockchain
                             Ok(_) => {
                                 new_block_hashes.push(block.hash());
+                                // how long the block took to get here since it was mined, on average over the count
+                                let now = self.clock.now_millis();
+                                let delay = now.saturating_sub(block.header.timestamp) as u64;
+                                self.metrics.inc("bitcoin_new_blocks_received_total", &[]);
+                                self.metrics.add("bitcoin_block_propagation_milliseconds_total", &[], delay);
                                 
                                 // Remove the block's transactions from mempool
                                 let mut mempool = self.mempool.lock().unwrap();
                                 for txn in block.content.transactions.iter() {
-                                    mempool.map.remove(&txn.hash());
+                                    mempool.remove(&txn.hash());
                                 }
                                 drop(mempool);
                                 
@@ -166,7 +194,7 @@ impl Worker {
                                 if let Some(orphans) = orphan_buffer.get(&block.hash()) {
                                     // This block is the parent to some orphans, so take them out 
                                     // of orphan_buffer and put them in line to be added to blockchain
-                                    blocks.extend_from_slice(&orphans);
+                                    blocks.extend_from_slice(orphans);
                                     orphan_buffer.remove(&block.hash());
                                 }
                             }
@@ -175,20 +203,20 @@ impl Worker {
                             Err(true) => {
                                 // Add block into the array of orphans corresponding to its parent
                                 orphan_buffer.entry(block.get_parent())
-                                             .or_insert_with(Vec::new).push(block.clone());
+                                             .or_default().push(block.clone());
 
-                                // Request missing blocks
-                                peer.write(Message::GetBlocks(vec![block.hash()]));
+                                // Request the missing parent, which may be an orphan in turn
+                                peer.write(Message::GetBlocks(vec![block.get_parent()]));
                             }
                             
                             // Block did not pass transaction checks
                             Err(false) => {}
                         }
-
-                        i += 1;    // next block
                     }
 
                     if !new_block_hashes.is_empty() {
+                        // the tip may have moved, so let the miner rebuild its block
+                        self.miner.update();
                         self.server.broadcast(Message::NewBlockHashes(new_block_hashes));
                     }
                 } 
@@ -198,9 +226,9 @@ impl Worker {
                     let mempool = self.mempool.lock().unwrap();
                     let mut unknown = Vec::new();
                     for hash in hashes.iter() {
-                        if !mempool.map.contains_key(hash) {
+                        if !mempool.contains(hash) {
                             // hash not in mempool, so add it to vec of unknowns
-                            unknown.push(hash.clone());   
+                            unknown.push(*hash);   
                         }
                     }
                     drop(mempool);
@@ -215,8 +243,7 @@ impl Worker {
                     let mempool = self.mempool.lock().unwrap();
                     let mut transactions = Vec::new();
                     for hash in hashes.iter() {
-                        if mempool.map.contains_key(hash) {
-                            let txn = mempool.map.get(hash).unwrap();
+                        if let Some(txn) = mempool.get(hash) {
                             transactions.push(txn.clone());
                         }
                     }
@@ -229,21 +256,24 @@ impl Worker {
 
                 // TRANSACTIONS
                 Message::Transactions(transactions) => {
+                    self.metrics.add("bitcoin_transactions_received_total", &[], transactions.len() as u64);
+                    // check the transactions against the tip, the same way the API does
+                    let blockchain = self.blockchain.lock().unwrap();
+                    let state = blockchain.get_state(&blockchain.tip()).unwrap();
                     let mut mempool = self.mempool.lock().unwrap();
                     let mut new_hashes = Vec::new();
-                    for txn in transactions.iter() {
-                        if !mempool.map.contains_key(&txn.hash()) {
-                            // check current transaction
-                            if transaction::verify(&txn.transaction, &txn.public_key, &txn.signature) {
-                                // passed check; insert transaction into mempool
-                                mempool.map.insert(txn.hash(), txn.clone());
-                                new_hashes.push(txn.hash());
-                            }                            
+                    for txn in transactions {
+                        match mempool.admit(txn, state) {
+                            Ok(hash) => new_hashes.push(hash),
+                            Err(Rejection::Duplicate) => {}
+                            Err(rejection) => debug!(reason = rejection.reason(); "Rejected transaction: {}", rejection),
                         }
                     }
                     drop(mempool);
+                    drop(blockchain);
 
                     if !new_hashes.is_empty() {
+                        self.miner.update();
                         self.server.broadcast(Message::NewTransactionHashes(new_hashes));
                     }
                 }
@@ -252,11 +282,11 @@ impl Worker {
     }
 }
 
-#[cfg(any(test,test_utilities))]
+#[cfg(test)]
 struct TestMsgSender {
     s: smol::channel::Sender<(Vec<u8>, peer::Handle)>
 }
-#[cfg(any(test,test_utilities))]
+#[cfg(test)]
 impl TestMsgSender {
     fn new() -> (TestMsgSender, smol::channel::Receiver<(Vec<u8>, peer::Handle)>) {
         let (s,r) = smol::channel::unbounded();
@@ -271,14 +301,16 @@ impl TestMsgSender {
     }
 }
 
-#[cfg(any(test,test_utilities))]
+#[cfg(test)]
 /// returns two structs used by tests, and an ordered vector of hashes of all blocks in the blockchain
 fn generate_test_worker_and_start() -> (TestMsgSender, ServerTestReceiver, Vec<H256>) {
     let (server, server_receiver) = ServerHandle::new_for_test();
     let (test_msg_sender, msg_chan) = TestMsgSender::new();
     let blockchain = Blockchain::new();
     let blockchain = Arc::new(Mutex::new(blockchain));
-    let worker = Worker::new(1, msg_chan, &server, &blockchain);
+    let mempool = Arc::new(Mutex::new(Mempool::new()));
+    let miner = MinerHandle::new_for_test();
+    let worker = Worker::new(1, msg_chan, &server, &blockchain, &mempool, &miner);
     worker.start(); 
 
     let current_chain = blockchain.lock().unwrap();
@@ -314,8 +346,8 @@ mod test {
     #[timeout(60000)]
     fn reply_get_blocks() {
         let (test_msg_sender, _server_receiver, v) = generate_test_worker_and_start();
-        let h = v.last().unwrap().clone();
-        let mut peer_receiver = test_msg_sender.send(Message::GetBlocks(vec![h.clone()]));
+        let h = *v.last().unwrap();
+        let mut peer_receiver = test_msg_sender.send(Message::GetBlocks(vec![h]));
         let reply = peer_receiver.recv();
         if let Message::Blocks(v) = reply {
             assert_eq!(1, v.len());
@@ -0,0 +1,430 @@
+use crate::api::{self, handlers::Context as ApiContext, Listener as ApiListener, Server as ApiServer};
+use crate::blockchain::{archive::Archive, spec::ChainSpec, Blockchain};
+use crate::config::Config;
+use crate::events::EventBus;
+use crate::generator::{self, scenario::Scenario};
+use crate::metrics::Metrics;
+use crate::miner;
+use crate::network::{self, conditions::Conditions, transport::Transport};
+use crate::shutdown::Shutdown;
+use crate::simulation::{clock::Clock, Simulation};
+use crate::types::mempool::Mempool;
+use crate::wallet::Wallet;
+use log::{error, info};
+use smol::channel;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time;
+
+// the stream of the simulation's randomness that decides which messages simulated links lose
+const NETWORK_RNG_STREAM: u64 = 3;
+
+// how many blocks an import goes through between progress reports
+const IMPORT_PROGRESS_INTERVAL: usize = 1000;
+
+/// Builds and starts a node from a config, the way the `bitcoin` binary does, so tools
+/// and tests can run nodes in-process. The chain spec, the chain archive to import, the
+/// generator scenario and the wallet are read from the files the config names, unless
+/// they are given here.
+pub struct NodeBuilder {
+    config: Config,
+    spec: Option<ChainSpec>,
+    archive: Option<Archive>,
+    scenario: Option<Scenario>,
+    wallet_passphrase: Option<String>,
+    clock: Option<Arc<dyn Clock>>,
+    shutdown: Shutdown,
+}
+
+/// A running node. Its subsystems can be used directly through their handles, and
+/// `stop` shuts them all down in order.
+pub struct Node {
+    pub config: Config,
+    pub blockchain: Arc<Mutex<Blockchain>>,
+    pub mempool: Arc<Mutex<Mempool>>,
+    pub miner: miner::Handle,
+    pub generator: generator::Handle,
+    pub network: network::server::Handle,
+    pub network_conditions: Option<Conditions>,    // of the links to peers, if they are simulated
+    pub wallet: Option<Arc<Mutex<Wallet>>>,
+    pub events: EventBus,
+    pub metrics: Metrics,
+    pub shutdown: Shutdown,     // requested by the API's /node/shutdown
+    p2p_addr: SocketAddr,
+    api: api::Handle,
+    generator_threads: Vec<thread::JoinHandle<()>>,
+    miner_threads: Vec<thread::JoinHandle<()>>,
+    server_thread: thread::JoinHandle<()>,
+    worker_threads: Vec<thread::JoinHandle<()>>,
+}
+
+impl NodeBuilder {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            spec: None,
+            archive: None,
+            scenario: None,
+            wallet_passphrase: None,
+            clock: None,
+            shutdown: Shutdown::new(),
+        }
+    }
+
+    /// Follow this chain spec rather than the one named by `consensus.chain_spec`
+    pub fn chain_spec(mut self, spec: ChainSpec) -> Self {
+        self.spec = Some(spec);
+        self
+    }
+
+    /// Replay the blocks of this archive at start rather than those of `consensus.import`
+    pub fn import(mut self, archive: Archive) -> Self {
+        self.archive = Some(archive);
+        self
+    }
+
+    /// Generate transactions by this scenario rather than the one named by `generator.scenario`
+    pub fn scenario(mut self, scenario: Scenario) -> Self {
+        self.scenario = Some(scenario);
+        self
+    }
+
+    /// The passphrase of the keystore named by `wallet.keystore`, needed if one is set
+    pub fn wallet_passphrase(mut self, passphrase: &str) -> Self {
+        self.wallet_passphrase = Some(passphrase.to_string());
+        self
+    }
+
+    /// Stamp blocks, deliver messages over simulated links and time block propagation by
+    /// this clock rather than the wall clock
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Share a shutdown request with the node, such as one made by signals
+    pub fn shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Start every subsystem, and connect to the peers of `network.connect` in the background
+    pub fn start(self) -> Result<Node, String> {
+        let config = self.config;
+        config.validate()?;
+
+        // parse simulation settings
+        let simulation = match config.mining.seed {
+            Some(seed) => Simulation::seeded(seed),
+            None => Simulation::default(),
+        };
+        let simulation = simulation.with_mining_mode(config.mining.mode);
+        let simulation = match self.clock {
+            Some(clock) => simulation.with_clock(clock),
+            None => simulation,
+        };
+
+        let scenario = match (self.scenario, &config.generator.scenario) {
+            (Some(scenario), _) => {
+                scenario.validate().map_err(|e| format!("error in scenario: {}", e))?;
+                scenario
+            }
+            (None, Some(path)) => load_scenario(path)?,
+            (None, None) => Scenario::default(),
+        };
+
+        // the chain to follow, which every node of the network has to agree on
+        let spec = match (self.spec, &config.consensus.chain_spec) {
+            (Some(spec), _) => spec,
+            (None, Some(path)) => ChainSpec::load(path)?,
+            (None, None) => ChainSpec::default(),
+        };
+        info!(chain = spec.name, genesis:% = spec.genesis_hash(); "Following chain");
+
+        // the blockchain, mempool and network server publish what happens to them here,
+        // and the components count what they do in the metrics served by the API
+        let events = EventBus::new();
+        let metrics = Metrics::new();
+
+        let mut blockchain = Blockchain::from_spec(&spec);
+        blockchain.set_check_pow(simulation.checks_pow());
+        blockchain.set_events(events.clone());
+        blockchain.set_metrics(metrics.clone());
+        let archive = match (self.archive, &config.consensus.import) {
+            (Some(archive), _) => Some(archive),
+            (None, Some(path)) => Some(Archive::read(path)?),
+            (None, None) => None,
+        };
+        if let Some(archive) = archive {
+            import(&mut blockchain, &archive)?;
+        }
+        let blockchain = Arc::new(Mutex::new(blockchain));
+        let mut mempool = Mempool::new();
+        mempool.set_events(events.clone());
+        mempool.set_metrics(metrics.clone());
+        mempool.set_capacity(config.mempool.max_transactions);
+        let mempool = Arc::new(Mutex::new(mempool));
+
+        // unlock the wallet
+        let wallet = match &config.wallet.keystore {
+            Some(path) => {
+                let passphrase = self
+                    .wallet_passphrase
+                    .ok_or_else(|| format!("wallet {} needs a passphrase", path.display()))?;
+                let wallet = Wallet::open(path, &passphrase)
+                    .map_err(|e| format!("error opening wallet {}: {}", path.display(), e))?;
+                Some(Arc::new(Mutex::new(wallet)))
+            }
+            None => None,
+        };
+
+        // set up API access control, and take the API's port before starting anything that
+        // would have to be stopped if it were taken
+        let auth = config.api.auth()?;
+        let api_listener = ApiListener::bind(config.api.addr)
+            .map_err(|e| format!("error starting the API server at {}: {}", config.api.addr, e))?;
+
+        // create channels between server and worker
+        let (msg_tx, msg_rx) = channel::bounded(config.network.message_buffer);
+
+        // start the p2p server
+        let (mut server_ctx, server) = network::server::new(config.network.p2p_addr, msg_tx)
+            .map_err(|e| format!("error creating the P2P server: {}", e))?;
+        server_ctx.set_events(events.clone());
+        server_ctx.set_metrics(metrics.clone());
+        let network_conditions = if config.network.simulate_links {
+            let conditions = Conditions::new(simulation.rng(NETWORK_RNG_STREAM), simulation.clock());
+            server_ctx.set_transport(Transport::Simulated(conditions.clone()));
+            Some(conditions)
+        } else {
+            None
+        };
+        let (server_thread, p2p_addr) = server_ctx
+            .start()
+            .map_err(|e| format!("error starting the P2P server at {}: {}", config.network.p2p_addr, e))?;
+
+        // create the miner first, so the network worker can tell it about new blocks and transactions
+        let (miner_ctx, miner, finished_block_chan) = miner::new(&blockchain, &mempool, config.mining.threads, &simulation);
+        // blocks over the chain's limit would be rejected
+        let block_size_limit = spec.consensus.max_block_transactions.map_or(config.mining.block_size_limit, |limit| {
+            std::cmp::min(limit, config.mining.block_size_limit)
+        });
+        miner.set_block_size_limit(block_size_limit);
+        let miner_worker_ctx = miner::worker::Worker::new(&server, finished_block_chan);
+
+        // start the worker
+        let mut worker_ctx = network::worker::Worker::new(
+            config.network.workers,
+            msg_rx,
+            &server,
+            &blockchain,
+            &mempool,
+            &miner,
+        );
+        worker_ctx.set_metrics(metrics.clone());
+        worker_ctx.set_clock(simulation.clock());
+        let worker_threads = worker_ctx.start();
+
+        // start the miner
+        let miner_threads = vec![miner_ctx.start(), miner_worker_ctx.start()];
+
+        // start the transaction generator
+        let (mut generator_ctx, txn_generator, finished_txn_chan) = generator::new(&blockchain, &mempool, &simulation, scenario);
+        generator_ctx.set_metrics(metrics.clone());
+        let generator_worker_ctx = generator::generator::TransactionGenerator::new(&server, finished_txn_chan, &miner);
+        let generator_threads = vec![generator_ctx.start(), generator_worker_ctx.start()];
+
+        // connect to known peers
+        if !config.network.connect.is_empty() {
+            let known_peers = config.network.connect.clone();
+            let server = server.clone();
+            let shutdown = self.shutdown.clone();
+            thread::spawn(move || {
+                for addr in known_peers {
+                    while !shutdown.is_requested() {
+                        match server.connect(addr) {
+                            Ok(_) => {
+                                info!(peer:% = addr; "Connected to outgoing peer");
+                                break;
+                            }
+                            Err(e) => {
+                                error!(peer:% = addr; "Error connecting to peer, retrying in one second: {}", e);
+                                thread::sleep(time::Duration::from_millis(1000));
+                            }
+                        }
+                    }
+                }
+            });
+        }
+
+        // start the API server
+        let api_ctx = ApiContext {
+            miner: miner.clone(),
+            txn_generator: txn_generator.clone(),
+            network: server.clone(),
+            network_conditions: network_conditions.clone(),
+            blockchain: Arc::clone(&blockchain),
+            mempool: Arc::clone(&mempool),
+            wallet: wallet.clone(),
+            events: events.clone(),
+            metrics: metrics.clone(),
+            shutdown: self.shutdown.clone(),
+        };
+        let api = ApiServer::start(api_listener, api_ctx, auth);
+
+        Ok(Node {
+            config,
+            blockchain,
+            mempool,
+            miner,
+            generator: txn_generator,
+            network: server,
+            network_conditions,
+            wallet,
+            events,
+            metrics,
+            shutdown: self.shutdown,
+            p2p_addr,
+            api,
+            generator_threads,
+            miner_threads,
+            server_thread,
+            worker_threads,
+        })
+    }
+}
+
+impl Node {
+    /// The address the P2P server listens at, with the port it was given if it asked for port 0
+    pub fn p2p_addr(&self) -> SocketAddr {
+        self.p2p_addr
+    }
+
+    /// The address the API server listens at, with the port it was given if it asked for port 0
+    pub fn api_addr(&self) -> SocketAddr {
+        self.api.addr()
+    }
+
+    /// Block until shutdown is requested, then stop the node
+    pub fn run(self) {
+        let reason = self.shutdown.wait();
+        info!(reason; "Shutting down");
+        self.stop();
+    }
+
+    /// Stop every subsystem, waiting for their threads, and save the wallet
+    pub fn stop(self) {
+        // stop taking requests first, so nothing new is started while the rest stops
+        self.shutdown.request("stop");
+        self.api.stop();
+        self.events.close();
+
+        // stop making transactions and blocks, and send out the ones already made
+        self.generator.exit();
+        for thread in self.generator_threads {
+            let _ = thread.join();
+        }
+        self.miner.exit();
+        for thread in self.miner_threads {
+            let _ = thread.join();
+        }
+
+        // disconnect from peers, then let the workers handle what was already received
+        self.network.shutdown();
+        let _ = self.server_thread.join();
+        for thread in self.worker_threads {
+            let _ = thread.join();
+        }
+
+        if let Some(wallet) = self.wallet {
+            if let Err(e) = wallet.lock().unwrap().save() {
+                error!("Error saving wallet: {}", e);
+            }
+        }
+        info!("Node shut down");
+    }
+}
+
+// replay an archive's blocks, before the node has peers or mines
+fn import(blockchain: &mut Blockchain, archive: &Archive) -> Result<(), String> {
+    info!(blocks = archive.blocks.len(); "Importing blocks");
+    let status = archive
+        .import(blockchain, |progress| {
+            if progress.done % IMPORT_PROGRESS_INTERVAL == 0 {
+                info!(done = progress.done, total = progress.total; "Importing blocks");
+            }
+        })
+        .map_err(|e| format!("error importing blocks: {}", e))?;
+    info!(inserted = status.inserted, known = status.done - status.inserted, height = blockchain.tip_height(); "Imported blocks");
+    Ok(())
+}
+
+fn load_scenario(path: &Path) -> Result<Scenario, String> {
+    let scenario = fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|json| serde_json::from_str::<Scenario>(&json).map_err(|e| e.to_string()))
+        .and_then(|scenario| scenario.validate().map(|_| scenario))
+        .map_err(|e| format!("error loading scenario {}: {}", path.display(), e))?;
+    Ok(scenario)
+}
+
+// DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. BEFORE TEST
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::simulation::MiningMode;
+    use std::time::Duration;
+
+    #[test]
+    fn start_and_stop_nodes() {
+        let mut config = Config::default();
+        config.network.p2p_addr = ([127, 0, 0, 1], 0).into();
+        config.api.addr = ([127, 0, 0, 1], 0).into();
+        config.mining.mode = MiningMode::Instant;
+        let first = NodeBuilder::new(config.clone()).start().unwrap();
+        assert_ne!(first.p2p_addr().port(), 0);
+        assert_ne!(first.api_addr().port(), 0);
+
+        config.network.connect = vec![first.p2p_addr()];
+        let second = NodeBuilder::new(config).start().unwrap();
+        first.miner.start(1000);
+        // the second node hears about the first one's blocks
+        let deadline = time::Instant::now() + Duration::from_secs(10);
+        while second.blockchain.lock().unwrap().tip_height() < 3 {
+            assert!(time::Instant::now() < deadline, "blocks were not relayed");
+            thread::sleep(Duration::from_millis(10));
+        }
+        second.stop();
+        first.stop();
+    }
+
+    #[test]
+    fn reject_invalid_scenario() {
+        let mut config = Config::default();
+        config.network.p2p_addr = ([127, 0, 0, 1], 0).into();
+        config.api.addr = ([127, 0, 0, 1], 0).into();
+        let scenario = Scenario { num_accounts: 1, ..Default::default() };
+        let result = NodeBuilder::new(config).scenario(scenario).start();
+        assert!(result.err().unwrap().contains("at least 2 accounts"));
+    }
+
+    #[test]
+    fn fail_to_start_on_a_taken_api_port() {
+        let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let free = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut config = Config::default();
+        config.network.p2p_addr = free.local_addr().unwrap();
+        config.api.addr = taken.local_addr().unwrap();
+        drop(free);
+        let result = NodeBuilder::new(config.clone()).start();
+        assert!(result.err().unwrap().contains("error starting the API server"));
+        // nothing was started, so the P2P port is still free
+        assert!(std::net::TcpListener::bind(config.network.p2p_addr).is_ok());
+    }
+}
+
+// DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. AFTER TEST
@@ -0,0 +1,88 @@
+use async_signal::{Signal, Signals};
+use futures::stream::StreamExt;
+use log::{info, warn};
+use std::io;
+use std::process;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+/// A request to shut the node down, made by a signal or the API and waited on by
+/// whoever started the node's services, which then stops them in order.
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown {
+    // why shutdown was requested, once it was
+    reason: Arc<(Mutex<Option<String>>, Condvar)>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask for shutdown. Only the first request counts.
+    pub fn request(&self, reason: &str) {
+        let (lock, requested) = &*self.reason;
+        let mut current = lock.lock().unwrap();
+        if current.is_none() {
+            info!(reason; "Shutdown requested");
+            *current = Some(reason.to_string());
+            requested.notify_all();
+        }
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.reason.0.lock().unwrap().is_some()
+    }
+
+    /// Block until shutdown is requested, and return why
+    pub fn wait(&self) -> String {
+        let (lock, requested) = &*self.reason;
+        let current = requested.wait_while(lock.lock().unwrap(), |reason| reason.is_none()).unwrap();
+        current.clone().unwrap()
+    }
+
+    /// Request shutdown on SIGINT or SIGTERM. A second signal, while the services are
+    /// still stopping, exits at once.
+    pub fn on_signals(&self) -> io::Result<()> {
+        let mut signals = Signals::new([Signal::Int, Signal::Term])?;
+        let shutdown = self.clone();
+        thread::Builder::new()
+            .name("signals".to_string())
+            .spawn(move || {
+                while let Some(Ok(signal)) = smol::block_on(signals.next()) {
+                    if shutdown.is_requested() {
+                        warn!("Exiting without stopping the node's services");
+                        process::exit(130);
+                    }
+                    shutdown.request(match signal {
+                        Signal::Int => "SIGINT",
+                        _ => "SIGTERM",
+                    });
+                }
+            })?;
+        Ok(())
+    }
+}
+
+// DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. BEFORE TEST
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn wait_for_request() {
+        let shutdown = Shutdown::new();
+        assert!(!shutdown.is_requested());
+        let requester = shutdown.clone();
+        let thread = thread::spawn(move || {
+            requester.request("api");
+            requester.request("SIGINT");
+        });
+        assert_eq!(shutdown.wait(), "api");
+        assert!(shutdown.is_requested());
+        thread.join().unwrap();
+    }
+}
+
+// DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. AFTER TEST
@@ -0,0 +1,68 @@
+use std::{
+    cmp,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// The longest a thread waiting for a time on a clock sleeps before reading it again,
+/// since a simulation may move its clock at any time
+pub const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A source of block timestamps, in milliseconds since the UNIX epoch
+pub trait Clock: Send + Sync {
+    fn now_millis(&self) -> u128;
+}
+
+/// The wall clock
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> u128 {
+        match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(time) => time.as_millis(),
+            Err(_) => panic!("SystemTime before UNIX EPOCH!"),
+        }
+    }
+}
+
+/// A clock that only moves when told to. Every reading also advances it by `step`
+/// milliseconds, so a sequence of readings is reproducible without anyone driving it.
+#[derive(Debug)]
+pub struct ManualClock {
+    now: AtomicU64,
+    step: u64,
+}
+
+impl ManualClock {
+    pub fn new(start: u64, step: u64) -> Self {
+        Self {
+            now: AtomicU64::new(start),
+            step,
+        }
+    }
+
+    pub fn set(&self, millis: u64) {
+        self.now.store(millis, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, millis: u64) {
+        self.now.fetch_add(millis, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_millis(&self) -> u128 {
+        self.now.fetch_add(self.step, Ordering::SeqCst) as u128
+    }
+}
+
+/// How long to sleep before reading `clock` again, while waiting for it to reach `deadline`
+/// milliseconds. None once it has.
+pub fn until(clock: &dyn Clock, deadline: f64) -> Option<Duration> {
+    let left = deadline - clock.now_millis() as f64;
+    if left <= 0.0 {
+        return None;
+    }
+    Some(cmp::min(Duration::from_secs_f64(left / 1000.0), POLL_INTERVAL))
+}
@@ -0,0 +1,223 @@
+pub mod clock;
+
+use clock::{Clock, SystemClock};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{fmt, str::FromStr, sync::Arc, time::Duration};
+
+/// How the miner produces blocks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MiningMode {
+    /// Search for a nonce that satisfies the difficulty, like a real node
+    ProofOfWork,
+    /// Seal every block right away with a random nonce, skipping the hashing
+    Instant,
+    /// Seal blocks without hashing after an exponentially distributed delay, so that
+    /// blocks arrive as a Poisson process with the given mean interval in milliseconds
+    Poisson { mean_interval_ms: u64 },
+}
+
+// Settings that make the miner and transaction generator reproducible. The default
+// is a normal node: random seeds, the wall clock, and proof of work.
+#[derive(Clone)]
+pub struct Simulation {
+    seed: Option<u64>,
+    clock: Arc<dyn Clock>,
+    mining_mode: MiningMode,
+}
+
+impl Default for Simulation {
+    fn default() -> Self {
+        Self {
+            seed: None,
+            clock: Arc::new(SystemClock),
+            mining_mode: MiningMode::ProofOfWork,
+        }
+    }
+}
+
+impl Simulation {
+    /// A simulation whose random choices all derive from `seed`
+    pub fn seeded(seed: u64) -> Self {
+        Self {
+            seed: Some(seed),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn with_mining_mode(mut self, mining_mode: MiningMode) -> Self {
+        self.mining_mode = mining_mode;
+        self
+    }
+
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    pub fn clock(&self) -> Arc<dyn Clock> {
+        Arc::clone(&self.clock)
+    }
+
+    pub fn mining_mode(&self) -> MiningMode {
+        self.mining_mode
+    }
+
+    /// Whether blocks have to satisfy their difficulty; only real proof of work produces such blocks
+    pub fn checks_pow(&self) -> bool {
+        self.mining_mode == MiningMode::ProofOfWork
+    }
+
+    /// A random number generator for one component. Each component uses its own
+    /// `stream`, so the draws of one do not shift those of another.
+    pub fn rng(&self, stream: u64) -> StdRng {
+        match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed ^ stream.wrapping_mul(0x9e37_79b9_7f4a_7c15)),
+            None => StdRng::from_entropy(),
+        }
+    }
+}
+
+impl MiningMode {
+    /// How long to wait before sealing the next block without proof of work
+    pub fn next_delay<R: Rng>(&self, rng: &mut R) -> Duration {
+        match *self {
+            MiningMode::ProofOfWork | MiningMode::Instant => Duration::from_millis(0),
+            MiningMode::Poisson { mean_interval_ms } => {
+                // inverse transform sampling of the exponential distribution
+                let u: f64 = rng.gen();
+                Duration::from_secs_f64(-(1.0 - u).ln() * mean_interval_ms as f64 / 1000.0)
+            }
+        }
+    }
+}
+
+impl fmt::Display for MiningMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MiningMode::ProofOfWork => write!(f, "pow"),
+            MiningMode::Instant => write!(f, "instant"),
+            MiningMode::Poisson { mean_interval_ms } => write!(f, "poisson:{}", mean_interval_ms),
+        }
+    }
+}
+
+impl FromStr for MiningMode {
+    type Err = String;
+
+    /// Parse "pow", "instant" or "poisson:<mean interval in ms>"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pow" => Ok(MiningMode::ProofOfWork),
+            "instant" => Ok(MiningMode::Instant),
+            _ => match s.strip_prefix("poisson:") {
+                Some(mean) => mean
+                    .parse::<u64>()
+                    .map(|mean_interval_ms| MiningMode::Poisson { mean_interval_ms })
+                    .map_err(|e| format!("invalid poisson mean interval: {}", e)),
+                None => Err(format!("unknown mining mode {}", s)),
+            },
+        }
+    }
+}
+
+// DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. BEFORE TEST
+
+#[cfg(test)]
+mod test {
+    use super::{clock::ManualClock, MiningMode, Simulation};
+    use crate::blockchain::Blockchain;
+    use crate::miner;
+    use crate::types::{block::Block, hash::Hashable, mempool::Mempool};
+    use ntest::timeout;
+    use std::sync::{Arc, Mutex};
+
+    /// Mine `count` blocks on a fresh chain with an instant miner seeded by `seed`
+    fn mine_blocks(seed: u64, count: usize) -> Vec<Block> {
+        let simulation = Simulation::seeded(seed)
+            .with_clock(Arc::new(ManualClock::new(0, 1000)))
+            .with_mining_mode(MiningMode::Instant);
+        let mut blockchain = Blockchain::new();
+        blockchain.set_check_pow(simulation.checks_pow());
+        let blockchain = Arc::new(Mutex::new(blockchain));
+        let mempool = Arc::new(Mutex::new(Mempool::new()));
+        let (miner_ctx, miner_handle, finished_block_chan) = miner::new(&blockchain, &mempool, 1, &simulation);
+        miner_ctx.start();
+        miner_handle.start(0);
+        let blocks = (0..count).map(|_| finished_block_chan.recv().unwrap()).collect();
+        miner_handle.exit();
+        blocks
+    }
+
+    #[test]
+    #[timeout(60000)]
+    fn same_seed_same_blocks() {
+        let first: Vec<_> = mine_blocks(7, 3).iter().map(|b| b.hash()).collect();
+        let second: Vec<_> = mine_blocks(7, 3).iter().map(|b| b.hash()).collect();
+        let other: Vec<_> = mine_blocks(8, 3).iter().map(|b| b.hash()).collect();
+        assert_eq!(first, second);
+        assert_ne!(first, other);
+    }
+
+    #[test]
+    #[timeout(60000)]
+    fn seeded_fork_and_reorg() {
+        let chain_a = mine_blocks(1, 2);
+        let chain_b = mine_blocks(2, 3);
+
+        let mut blockchain = Blockchain::new();
+        blockchain.set_check_pow(false);
+        let genesis_hash = blockchain.tip();
+
+        //      genesis
+        //        / \
+        //      a0   b0
+        //       |   |
+        //      a1   b1
+        //           |
+        //           b2
+        for block in chain_a.iter() {
+            assert!(blockchain.insert(block).is_ok());
+        }
+        for block in chain_b[..2].iter() {
+            assert!(blockchain.insert(block).is_ok());
+        }
+        assert_eq!(blockchain.tip(), chain_a[1].hash());
+
+        assert!(blockchain.insert(&chain_b[2]).is_ok());
+        let expected: Vec<_> = std::iter::once(genesis_hash)
+            .chain(chain_b.iter().map(|b| b.hash()))
+            .collect();
+        assert_eq!(blockchain.all_blocks_in_longest_chain(), expected);
+    }
+
+    #[test]
+    fn poisson_delays_follow_seed() {
+        let mode = MiningMode::Poisson { mean_interval_ms: 100 };
+        let mut rng = Simulation::seeded(3).rng(1);
+        let mut same_rng = Simulation::seeded(3).rng(1);
+        let delays: Vec<_> = (0..1000).map(|_| mode.next_delay(&mut rng)).collect();
+        let same_delays: Vec<_> = (0..1000).map(|_| mode.next_delay(&mut same_rng)).collect();
+        assert_eq!(delays, same_delays);
+
+        let mean_ms = delays.iter().map(|d| d.as_secs_f64() * 1000.0).sum::<f64>() / 1000.0;
+        assert!(mean_ms > 80.0 && mean_ms < 120.0);
+    }
+
+    #[test]
+    fn parse_mining_mode() {
+        assert_eq!("pow".parse::<MiningMode>().unwrap(), MiningMode::ProofOfWork);
+        assert_eq!("instant".parse::<MiningMode>().unwrap(), MiningMode::Instant);
+        assert_eq!(
+            "poisson:250".parse::<MiningMode>().unwrap(),
+            MiningMode::Poisson { mean_interval_ms: 250 }
+        );
+        assert!("poisson:soon".parse::<MiningMode>().is_err());
+        assert!("fast".parse::<MiningMode>().is_err());
+    }
+}
+
+// DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. AFTER TEST
@@ -19,6 +19,12 @@ impl std::convert::From<[u8; 20]> for Address {
     }
 }
 
+impl std::convert::AsRef<[u8]> for Address {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
 impl std::fmt::Display for Address {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         let start = if let Some(precision) = f.precision() {
@@ -66,6 +72,17 @@ impl Address {
         self.0.iter().map(|byte| format!("{:02x}", byte)).collect()
     }
 }
+
+impl std::str::FromStr for Address {
+    type Err = hex::FromHexError;
+
+    /// Parse an address from its 40 character hex string
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut buffer: [u8; 20] = [0; 20];
+        hex::decode_to_slice(s, &mut buffer)?;
+        Ok(Address(buffer))
+    }
+}
 // DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. BEFORE TEST
 
 #[cfg(test)]
@@ -82,6 +99,14 @@ mod test {
         // "0a0b0c0d0e0f0e0d0a0b0c0d0e0f0e0d0a0b0c0d0e0f0e0d0a0b0c0d0e0f0e0d"
         // take the last 20 bytes, we get "1851a0eae0060a132cf0f64a0ffaea248de6cba0"
     }
+
+    #[test]
+    fn parse_hex_string() {
+        let addr: Address = hex!("1851a0eae0060a132cf0f64a0ffaea248de6cba0").into();
+        assert_eq!(addr.to_hex_string().parse::<Address>().unwrap(), addr);
+        assert!("1851a0eae0".parse::<Address>().is_err());
+        assert!("zz51a0eae0060a132cf0f64a0ffaea248de6cba0".parse::<Address>().is_err());
+    }
 }
 
 // DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. AFTER TEST
\ No newline at end of file
@@ -1,8 +1,10 @@
 use crate::types::{
     hash::{H256, Hashable},
     transaction::SignedTransaction,
-    merkle::MerkleTree,
 };
+#[cfg(any(test, feature = "test-utilities"))]
+use crate::types::merkle::MerkleTree;
+#[cfg(any(test, feature = "test-utilities"))]
 use rand::Rng;
 use bincode;
 use serde::{Serialize, Deserialize};
@@ -59,12 +61,12 @@ impl Block {
 //------------------------------------------------------------------------------------
 
 // Generate a random Block to help test the Blockchain implementation
-#[cfg(any(test, test_utilities))]
+#[cfg(any(test, feature = "test-utilities"))]
 pub fn generate_random_block(parent: &H256) -> Block {
     let mut rng = rand::thread_rng();  // create a random number generator
     let nonce: u32 = rng.gen();        // make nonce a random integer
 
-    let difficulty = H256::default();       // use default difficulty
+    let difficulty: H256 = [255u8; 32].into();      // use the easiest difficulty
     let timestamp = rng.gen::<u128>();      // use current time
 
     let transactions: Vec<SignedTransaction> = Vec::new();  // empty transactions vector
@@ -75,10 +77,10 @@ pub fn generate_random_block(parent: &H256) -> Block {
     
     let header = Header {
         parent: *parent,
-        nonce: nonce,
-        difficulty: difficulty,
-        timestamp: timestamp,
-        merkle_root: merkle_root
+        nonce,
+        difficulty,
+        timestamp,
+        merkle_root
     };
 
     Block{ header, content }
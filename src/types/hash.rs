@@ -1,6 +1,6 @@
 use serde::{Serialize, Deserialize};
 use std::convert::TryInto;
-#[cfg(any(test, test_utilities))]
+#[cfg(any(test, feature = "test-utilities"))]
 use rand::Rng;
 
 /// An object that can be meaningfully hashed.
@@ -47,6 +47,17 @@ impl std::fmt::Debug for H256 {
     }
 }
 
+impl std::str::FromStr for H256 {
+    type Err = hex::FromHexError;
+
+    /// Parse a hash from its 64 character hex string
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut buffer: [u8; 32] = [0; 32];
+        hex::decode_to_slice(s, &mut buffer)?;
+        Ok(H256(buffer))
+    }
+}
+
 impl std::convert::AsRef<[u8]> for H256 {
     fn as_ref(&self) -> &[u8] {
         &self.0
@@ -109,7 +120,7 @@ impl PartialOrd for H256 {
     }
 }
 
-#[cfg(any(test, test_utilities))]
+#[cfg(any(test, feature = "test-utilities"))]
 pub fn generate_random_hash() -> H256 {
     let mut rng = rand::thread_rng();
     let random_bytes: Vec<u8> = (0..32).map(|_| rng.gen()).collect();
@@ -1,18 +1,267 @@
 use super::{
+    address::Address,
     hash::{Hashable, H256},
-    transaction::SignedTransaction,
+    state::State,
+    transaction::{self, SignedTransaction},
 };
+use crate::events::{Event, EventBus};
+use crate::metrics::Metrics;
+use serde::Serialize;
 use std::collections::HashMap;
+use std::fmt;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
 
 #[derive(Debug, Default, Clone)]
 pub struct Mempool {
-    pub map: HashMap<H256, SignedTransaction>
+    map: HashMap<H256, SignedTransaction>,
+    version: Arc<AtomicU64>,    // bumped on every insertion or removal
+    capacity: Option<usize>,    // the most transactions `admit` lets in
+    events: EventBus,
+    metrics: Metrics
+}
+
+/// Why a transaction was not admitted to the mempool
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum Rejection {
+    Duplicate,
+    BadSignature,
+    UnknownSender { sender: String },
+    StaleNonce { account_nonce: u128, state_nonce: u128 },  // the nonce is already used, or zero
+    Full { capacity: usize },
 }
 
 impl Mempool {
     pub fn new() -> Self {
         Self{
-            map: HashMap::new()
+            map: HashMap::new(),
+            version: Arc::new(AtomicU64::new(0)),
+            capacity: None,
+            events: EventBus::default(),
+            metrics: Metrics::default()
+        }
+    }
+
+    /// Limit the transactions `admit` lets in, or lift the limit with None.
+    /// Transactions inserted directly, like the generator's, still count towards it.
+    pub fn set_capacity(&mut self, capacity: Option<usize>) {
+        self.capacity = capacity;
+    }
+
+    /// Publish transactions entering and leaving the mempool on an event bus
+    pub fn set_events(&mut self, events: EventBus) {
+        self.events = events;
+    }
+
+    /// Count rejected transactions in `metrics`
+    pub fn set_metrics(&mut self, metrics: Metrics) {
+        self.metrics = metrics;
+    }
+
+    /// Insert a transaction, returning false if it was already in the mempool
+    pub fn insert(&mut self, txn: SignedTransaction) -> bool {
+        let hash = txn.hash();
+        if self.map.contains_key(&hash) {
+            return false;
+        }
+        self.map.insert(hash, txn);
+        self.version.fetch_add(1, Ordering::Release);
+        self.events.publish(Event::TransactionAdded { hash: hash.to_string() });
+        true
+    }
+
+    /// Check a transaction against the tip `state` and insert it if it may be included in a
+    /// block, now or once the sender's earlier transactions are. Transactions whose nonce
+    /// is ahead of the chain, or that the sender can't afford yet, are admitted.
+    pub fn admit(&mut self, txn: SignedTransaction, state: &State) -> Result<H256, Rejection> {
+        let result = self.check_and_insert(txn, state);
+        if let Err(rejection) = &result {
+            self.metrics.inc("bitcoin_transaction_rejections_total", &[("reason", rejection.reason())]);
+        }
+        result
+    }
+
+    fn check_and_insert(&mut self, txn: SignedTransaction, state: &State) -> Result<H256, Rejection> {
+        let hash = txn.hash();
+        if self.contains(&hash) {
+            return Err(Rejection::Duplicate);
+        }
+        if !transaction::verify(&txn.transaction, &txn.public_key, &txn.signature) {
+            return Err(Rejection::BadSignature);
+        }
+        let sender = Address::from_public_key_bytes(&txn.public_key);
+        let state_nonce = match state.map.get(&sender) {
+            Some((nonce, _)) => *nonce,
+            None => return Err(Rejection::UnknownSender { sender: sender.to_hex_string() }),
+        };
+        if txn.transaction.account_nonce <= state_nonce {
+            return Err(Rejection::StaleNonce { account_nonce: txn.transaction.account_nonce, state_nonce });
+        }
+        if let Some(capacity) = self.capacity.filter(|capacity| self.len() >= *capacity) {
+            return Err(Rejection::Full { capacity });
+        }
+        self.insert(txn);
+        Ok(hash)
+    }
+
+    /// Remove a transaction by hash, returning it if it was in the mempool
+    pub fn remove(&mut self, hash: &H256) -> Option<SignedTransaction> {
+        let removed = self.map.remove(hash);
+        if removed.is_some() {
+            self.version.fetch_add(1, Ordering::Release);
+            self.events.publish(Event::TransactionRemoved { hash: hash.to_string() });
+        }
+        removed
+    }
+
+    pub fn contains(&self, hash: &H256) -> bool {
+        self.map.contains_key(hash)
+    }
+
+    pub fn get(&self, hash: &H256) -> Option<&SignedTransaction> {
+        self.map.get(hash)
+    }
+
+    /// Iterate over all transactions in the mempool, in no particular order
+    pub fn transactions(&self) -> impl Iterator<Item = &SignedTransaction> {
+        self.map.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// The nonce and balance of an account once the transactions it has waiting in the
+    /// mempool are applied on top of its confirmed `(nonce, balance)`. Only transactions
+    /// that continue the nonce sequence without a gap count.
+    pub fn pending_account(&self, sender: &Address, confirmed: (u128, u128)) -> (u128, u128) {
+        let mut values = HashMap::new();
+        for txn in self.map.values() {
+            if Address::from_public_key_bytes(&txn.public_key) == *sender {
+                // of several transactions with the same nonce, count the most expensive
+                let value = values.entry(txn.transaction.account_nonce).or_insert(0);
+                *value = std::cmp::max(*value, txn.transaction.value);
+            }
+        }
+        let (mut nonce, mut balance) = confirmed;
+        while let Some(value) = values.get(&(nonce + 1)) {
+            if *value > balance {
+                break;
+            }
+            nonce += 1;
+            balance -= value;
+        }
+        (nonce, balance)
+    }
+
+    /// Get a counter that is bumped every time the mempool changes, so other threads
+    /// can notice new transactions without locking the mempool
+    pub fn version(&self) -> Arc<AtomicU64> {
+        Arc::clone(&self.version)
+    }
+}
+
+impl Rejection {
+    /// The name of the rejection, as in its `reason` field
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Rejection::Duplicate => "duplicate",
+            Rejection::BadSignature => "bad_signature",
+            Rejection::UnknownSender { .. } => "unknown_sender",
+            Rejection::StaleNonce { .. } => "stale_nonce",
+            Rejection::Full { .. } => "full",
         }
     }
 }
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Rejection::Duplicate => write!(f, "transaction is already in the mempool"),
+            Rejection::BadSignature => write!(f, "bad signature"),
+            Rejection::UnknownSender { sender } => write!(f, "sender {} has no account", sender),
+            Rejection::StaleNonce { account_nonce, state_nonce } => {
+                write!(f, "nonce {} is not above the account nonce {}", account_nonce, state_nonce)
+            }
+            Rejection::Full { capacity } => write!(f, "the mempool is full, with {} transactions", capacity),
+        }
+    }
+}
+
+// DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. BEFORE TEST
+
+#[cfg(test)]
+mod test {
+    use super::{Mempool, Rejection};
+    use crate::blockchain::Blockchain;
+    use crate::types::{
+        address::Address,
+        transaction::{self, SignedTransaction, Transaction},
+    };
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    fn signed_transaction(seed: u8, account_nonce: u128, value: u128) -> SignedTransaction {
+        let key = Ed25519KeyPair::from_seed_unchecked(&[seed; 32]).unwrap();
+        let receiver = Address::from_public_key_bytes(&[1u8; 32]);
+        let transaction = Transaction { account_nonce, receiver, value };
+        let signature = transaction::sign(&transaction, &key).as_ref().to_vec();
+        SignedTransaction {
+            transaction,
+            signature,
+            public_key: key.public_key().as_ref().to_vec(),
+        }
+    }
+
+    #[test]
+    fn admit_checks_transactions() {
+        let blockchain = Blockchain::new();
+        let state = blockchain.get_state(&blockchain.tip()).unwrap();
+        let mut mempool = Mempool::new();
+
+        let valid = signed_transaction(0, 1, 10);
+        assert!(mempool.admit(valid.clone(), state).is_ok());
+        assert_eq!(mempool.admit(valid, state), Err(Rejection::Duplicate));
+        // ahead of the chain and more than the sender has, but may become valid
+        assert!(mempool.admit(signed_transaction(0, 2, 1_000_000), state).is_ok());
+
+        let mut bad_signature = signed_transaction(0, 1, 20);
+        bad_signature.signature[0] ^= 0xff;
+        assert_eq!(mempool.admit(bad_signature, state), Err(Rejection::BadSignature));
+        assert_eq!(
+            mempool.admit(signed_transaction(0, 0, 10), state),
+            Err(Rejection::StaleNonce { account_nonce: 0, state_nonce: 0 })
+        );
+        assert!(matches!(
+            mempool.admit(signed_transaction(7, 1, 10), state),
+            Err(Rejection::UnknownSender { .. })
+        ));
+        assert_eq!(mempool.len(), 2);
+
+        mempool.set_capacity(Some(2));
+        assert_eq!(mempool.admit(signed_transaction(0, 3, 10), state), Err(Rejection::Full { capacity: 2 }));
+        mempool.set_capacity(None);
+        assert!(mempool.admit(signed_transaction(0, 3, 10), state).is_ok());
+    }
+
+    #[test]
+    fn pending_account_follows_nonces() {
+        let sender = Address::from_public_key_bytes(
+            Ed25519KeyPair::from_seed_unchecked(&[0; 32]).unwrap().public_key().as_ref()
+        );
+        let mut mempool = Mempool::new();
+        mempool.insert(signed_transaction(0, 1, 10));
+        mempool.insert(signed_transaction(0, 2, 20));
+        mempool.insert(signed_transaction(0, 4, 40));   // after a gap, so it doesn't count
+        assert_eq!(mempool.pending_account(&sender, (0, 100)), (2, 70));
+        assert_eq!(mempool.pending_account(&sender, (0, 15)), (1, 5));
+    }
+}
+
+// DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. AFTER TEST
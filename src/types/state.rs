@@ -6,6 +6,12 @@ pub struct State {
     pub map: HashMap<Address, (u128, u128)>      // <account address, (account nonce, balance)>
 }
 
+impl Default for State {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl State {
     pub fn new() -> Self {
         Self {
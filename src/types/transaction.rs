@@ -1,6 +1,7 @@
 use super::hash::{Hashable, H256};
 use serde::{Serialize,Deserialize};
 use ring::signature::{Ed25519KeyPair, Signature};
+#[cfg(any(test, feature = "test-utilities"))]
 use rand::Rng;
 use ring::signature;
 
@@ -37,9 +38,7 @@ pub fn sign(t: &Transaction, key: &Ed25519KeyPair) -> Signature {
     let transaction_bytes: Vec<u8> = bincode::serialize(t).unwrap();
 
     // Sign the serialized transaction with the private key
-    let signature = key.sign(&transaction_bytes);
-
-    signature
+    key.sign(&transaction_bytes)
 }
 
 /// Verify digital signature of a transaction, using public key instead of secret key
@@ -52,13 +51,10 @@ pub fn verify(t: &Transaction, public_key: &[u8], signature: &[u8]) -> bool {
     let public_key = signature::UnparsedPublicKey::new(&signature::ED25519, public_key);
 
     // Verify the signature using the public key
-    match public_key.verify(&transaction_bytes, signature) {
-        Ok(_) => true,   // Signature is valid
-        Err(_) => false, // Signature is invalid
-    }    
+    public_key.verify(&transaction_bytes, signature).is_ok()
 }
 
-#[cfg(any(test, test_utilities))]
+#[cfg(any(test, feature = "test-utilities"))]
 pub fn generate_random_transaction() -> Transaction { 
     // Create a random number generator
     fn generate_random_bytes() -> [u8; 20] {
@@ -0,0 +1,98 @@
+use clap::ArgMatches;
+use std::{
+    env,
+    io,
+    path::Path,
+};
+use crate::types::address::Address;
+use super::Wallet;
+
+// the environment variable holding the keystore passphrase; we prompt for it otherwise
+pub const PASSPHRASE_VAR: &str = "WALLET_PASSPHRASE";
+
+// the environment variable holding the seed for `wallet import`; we prompt for it otherwise
+pub const SEED_VAR: &str = "WALLET_SEED";
+
+/// Read the keystore passphrase from the environment, or from the terminal without echoing it
+pub fn read_passphrase() -> io::Result<String> {
+    if let Ok(passphrase) = env::var(PASSPHRASE_VAR) {
+        return Ok(passphrase);
+    }
+    rpassword::prompt_password("Keystore passphrase: ")
+}
+
+// the passphrase of a new keystore, typed twice if it comes from the terminal
+fn read_new_passphrase() -> io::Result<String> {
+    if let Ok(passphrase) = env::var(PASSPHRASE_VAR) {
+        return Ok(passphrase);
+    }
+    let passphrase = rpassword::prompt_password("New keystore passphrase: ")?;
+    if rpassword::prompt_password("Repeat the passphrase: ")? != passphrase {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "the passphrases do not match"));
+    }
+    Ok(passphrase)
+}
+
+// the hex seed of a key to import, read like the passphrase so it stays off the command line
+fn read_seed() -> Result<[u8; 32], String> {
+    let hex_seed = match env::var(SEED_VAR) {
+        Ok(hex_seed) => hex_seed,
+        Err(_) => rpassword::prompt_password("Seed (hex): ").map_err(|e| format!("error reading seed: {}", e))?,
+    };
+    let mut seed = [0u8; 32];
+    hex::decode_to_slice(hex_seed.trim(), &mut seed).map_err(|e| format!("error parsing seed: {}", e))?;
+    Ok(seed)
+}
+
+/// Run the `wallet` subcommand
+pub fn run(matches: &ArgMatches) -> Result<(), String> {
+    let path = Path::new(matches.value_of("keystore").unwrap());
+    if let ("create", Some(_)) = matches.subcommand() {
+        let passphrase = read_new_passphrase().map_err(|e| format!("error reading passphrase: {}", e))?;
+        Wallet::create(path, &passphrase).map_err(|e| e.to_string())?;
+        println!("Created {}", path.display());
+        return Ok(());
+    }
+    let passphrase = read_passphrase().map_err(|e| format!("error reading passphrase: {}", e))?;
+    let mut wallet = Wallet::open(path, &passphrase).map_err(|e| e.to_string())?;
+
+    match matches.subcommand() {
+        ("new", Some(m)) => {
+            let address = wallet.new_key(m.value_of("name").unwrap()).map_err(|e| e.to_string())?;
+            println!("{}", address);
+        }
+        ("import", Some(m)) => {
+            let seed = read_seed()?;
+            let address = wallet.add_seed(m.value_of("name").unwrap(), &seed).map_err(|e| e.to_string())?;
+            println!("{}", address);
+        }
+        ("list", Some(_)) => {
+            for (name, address) in wallet.addresses() {
+                println!("{}\t{}", address, name);
+            }
+        }
+        ("sign", Some(m)) => {
+            let from = parse_arg::<Address>(m, "from")?;
+            let to = parse_arg::<Address>(m, "to")?;
+            let value = parse_arg::<u128>(m, "value")?;
+            let nonce = parse_arg::<u128>(m, "nonce")?;
+            let signed_transaction = wallet.sign(&from, to, value, nonce).map_err(|e| e.to_string())?;
+            // hex-encoded bincode, ready to submit to a node
+            println!("{}", hex::encode(bincode::serialize(&signed_transaction).unwrap()));
+        }
+        _ => return Err(matches.usage().to_string()),
+    }
+    Ok(())
+}
+
+fn parse_arg<T>(matches: &ArgMatches, name: &str) -> Result<T, String>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    matches
+        .value_of(name)
+        .unwrap()
+        .parse::<T>()
+        .map_err(|e| format!("error parsing {}: {}", name, e))
+}
@@ -0,0 +1,140 @@
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN},
+    pbkdf2,
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::{Serialize, Deserialize};
+use std::{fs, num::NonZeroU32, path::Path};
+use crate::types::address::Address;
+use super::WalletError;
+
+const VERSION: u32 = 1;
+const PBKDF2_ITERATIONS: u32 = 100_000;
+const SALT_LEN: usize = 16;
+// encrypted with every keystore, so a wrong passphrase is caught even before it holds any keys
+const CHECK_PLAINTEXT: &[u8] = b"bitcoin keystore";
+
+// The keystore file. Each key is an Ed25519 PKCS#8 document sealed with ChaCha20-Poly1305,
+// under a key derived from the passphrase with PBKDF2. The address of each key is stored in
+// the clear, and authenticated along with the ciphertext.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Keystore {
+    version: u32,
+    kdf: Kdf,
+    check: Sealed,
+    keys: Vec<StoredKey>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Kdf {
+    iterations: u32,
+    salt: String,   // hex
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Sealed {
+    nonce: String,  // hex
+    ciphertext: String,     // hex, with the authentication tag appended
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct StoredKey {
+    name: String,
+    address: String,    // hex
+    sealed: Sealed,
+}
+
+// The key that seals and opens the entries of one keystore
+pub struct Cipher {
+    key: LessSafeKey,
+    rng: SystemRandom,
+}
+
+impl Keystore {
+    /// A new, empty keystore protected by `passphrase`
+    pub fn create(passphrase: &str) -> Result<(Self, Cipher), WalletError> {
+        let rng = SystemRandom::new();
+        let mut salt = [0u8; SALT_LEN];
+        rng.fill(&mut salt).map_err(|_| WalletError::Crypto)?;
+        let kdf = Kdf { iterations: PBKDF2_ITERATIONS, salt: hex::encode(salt) };
+        let cipher = Cipher::derive(&kdf, passphrase)?;
+        let check = cipher.seal(CHECK_PLAINTEXT, &[])?;
+        Ok((Self { version: VERSION, kdf, check, keys: Vec::new() }, cipher))
+    }
+
+    /// Read a keystore from a file and unlock it with `passphrase`
+    pub fn load(path: &Path, passphrase: &str) -> Result<(Self, Cipher), WalletError> {
+        let json = fs::read_to_string(path)?;
+        let keystore: Keystore = serde_json::from_str(&json)
+            .map_err(|e| WalletError::Format(e.to_string()))?;
+        if keystore.version != VERSION {
+            return Err(WalletError::Format(format!("unsupported keystore version {}", keystore.version)));
+        }
+        let cipher = Cipher::derive(&keystore.kdf, passphrase)?;
+        if cipher.open(&keystore.check, &[])? != CHECK_PLAINTEXT {
+            return Err(WalletError::WrongPassphrase);
+        }
+        Ok((keystore, cipher))
+    }
+
+    /// Write the keystore to a file, replacing it atomically
+    pub fn save(&self, path: &Path) -> Result<(), WalletError> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| WalletError::Format(e.to_string()))?;
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, json)?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Add a PKCS#8 document to the keystore
+    pub fn add(&mut self, cipher: &Cipher, name: &str, address: Address, pkcs8: &[u8]) -> Result<(), WalletError> {
+        let sealed = cipher.seal(pkcs8, address.as_ref())?;
+        self.keys.push(StoredKey { name: name.to_string(), address: address.to_hex_string(), sealed });
+        Ok(())
+    }
+
+    /// Decrypt every key in the keystore, as (name, address, PKCS#8 document)
+    pub fn keys(&self, cipher: &Cipher) -> Result<Vec<(String, Address, Vec<u8>)>, WalletError> {
+        self.keys
+            .iter()
+            .map(|key| {
+                let address = key.address.parse::<Address>().map_err(|e| WalletError::Format(e.to_string()))?;
+                let pkcs8 = cipher.open(&key.sealed, address.as_ref())?;
+                Ok((key.name.clone(), address, pkcs8))
+            })
+            .collect()
+    }
+}
+
+impl Cipher {
+    fn derive(kdf: &Kdf, passphrase: &str) -> Result<Self, WalletError> {
+        let salt = hex::decode(&kdf.salt).map_err(|e| WalletError::Format(e.to_string()))?;
+        let iterations = NonZeroU32::new(kdf.iterations)
+            .ok_or_else(|| WalletError::Format("kdf iterations must be positive".to_string()))?;
+        let mut key = [0u8; 32];
+        pbkdf2::derive(pbkdf2::PBKDF2_HMAC_SHA256, iterations, &salt, passphrase.as_bytes(), &mut key);
+        let key = UnboundKey::new(&CHACHA20_POLY1305, &key).map_err(|_| WalletError::Crypto)?;
+        Ok(Self { key: LessSafeKey::new(key), rng: SystemRandom::new() })
+    }
+
+    fn seal(&self, plaintext: &[u8], aad: &[u8]) -> Result<Sealed, WalletError> {
+        let mut nonce = [0u8; NONCE_LEN];
+        self.rng.fill(&mut nonce).map_err(|_| WalletError::Crypto)?;
+        let mut in_out = plaintext.to_vec();
+        self.key
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(aad), &mut in_out)
+            .map_err(|_| WalletError::Crypto)?;
+        Ok(Sealed { nonce: hex::encode(nonce), ciphertext: hex::encode(in_out) })
+    }
+
+    fn open(&self, sealed: &Sealed, aad: &[u8]) -> Result<Vec<u8>, WalletError> {
+        let nonce = hex::decode(&sealed.nonce).map_err(|e| WalletError::Format(e.to_string()))?;
+        let nonce = Nonce::try_assume_unique_for_key(&nonce)
+            .map_err(|_| WalletError::Format("bad nonce length".to_string()))?;
+        let mut in_out = hex::decode(&sealed.ciphertext).map_err(|e| WalletError::Format(e.to_string()))?;
+        let plaintext = self.key
+            .open_in_place(nonce, Aad::from(aad), &mut in_out)
+            .map_err(|_| WalletError::WrongPassphrase)?;
+        Ok(plaintext.to_vec())
+    }
+}
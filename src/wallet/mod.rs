@@ -0,0 +1,302 @@
+pub mod cli;
+pub mod keystore;
+
+use ring::{
+    rand::SystemRandom,
+    signature::{Ed25519KeyPair, KeyPair},
+};
+use serde::Serialize;
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+};
+use crate::blockchain::Blockchain;
+use crate::types::{
+    address::Address,
+    mempool::Mempool,
+    state::State,
+    transaction::{self, SignedTransaction, Transaction},
+};
+use keystore::{Cipher, Keystore};
+
+// the DER that surrounds the seed and public key in an Ed25519 PKCS#8 v2 document
+const PKCS8_SEED_PREFIX: &[u8] = &[0x30, 0x53, 0x02, 0x01, 0x01, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04, 0x20];
+const PKCS8_PUBLIC_KEY_PREFIX: &[u8] = &[0xa1, 0x23, 0x03, 0x21, 0x00];
+
+// A Wallet holds the user's keys, unlocked from an encrypted keystore file,
+// and signs transactions with them
+pub struct Wallet {
+    path: PathBuf,
+    keystore: Keystore,
+    cipher: Cipher,
+    keys: Vec<WalletKey>,
+}
+
+struct WalletKey {
+    name: String,
+    address: Address,
+    key_pair: Ed25519KeyPair,
+}
+
+/// A wallet key and what it holds on chain
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct AccountInfo {
+    pub name: String,
+    pub address: String,
+    pub nonce: u128,
+    pub balance: u128,
+    pub pending_nonce: u128,    // the nonce of the last transaction waiting in the mempool
+    pub pending_balance: u128,  // the balance once the waiting transactions are confirmed
+}
+
+#[derive(Debug)]
+pub enum WalletError {
+    Io(io::Error),
+    Format(String),     // the keystore file is malformed
+    Crypto,             // the system failed to generate randomness or keys
+    WrongPassphrase,
+    AlreadyExists(PathBuf),
+    NotFound(PathBuf),          // no keystore to open; it has to be created first
+    UnknownAddress(Address),    // the address has no key in this wallet
+    UnknownAccount(Address),    // the address has no account on chain
+    InsufficientBalance { balance: u128, value: u128 },
+}
+
+impl Wallet {
+    /// Create a new, empty keystore file protected by `passphrase`
+    pub fn create(path: &Path, passphrase: &str) -> Result<Self, WalletError> {
+        if path.exists() {
+            return Err(WalletError::AlreadyExists(path.to_path_buf()));
+        }
+        let (keystore, cipher) = Keystore::create(passphrase)?;
+        keystore.save(path)?;
+        Ok(Self { path: path.to_path_buf(), keystore, cipher, keys: Vec::new() })
+    }
+
+    /// Unlock an existing keystore file
+    pub fn open(path: &Path, passphrase: &str) -> Result<Self, WalletError> {
+        if !path.exists() {
+            return Err(WalletError::NotFound(path.to_path_buf()));
+        }
+        let (keystore, cipher) = Keystore::load(path, passphrase)?;
+        let keys = keystore
+            .keys(&cipher)?
+            .into_iter()
+            .map(|(name, address, pkcs8)| {
+                let key_pair = Ed25519KeyPair::from_pkcs8(&pkcs8)
+                    .map_err(|e| WalletError::Format(format!("bad key {}: {}", name, e)))?;
+                Ok(WalletKey { name, address, key_pair })
+            })
+            .collect::<Result<Vec<_>, WalletError>>()?;
+        Ok(Self { path: path.to_path_buf(), keystore, cipher, keys })
+    }
+
+    /// Generate a new key, save it to the keystore and return its address
+    pub fn new_key(&mut self, name: &str) -> Result<Address, WalletError> {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).map_err(|_| WalletError::Crypto)?;
+        self.add_pkcs8(name, pkcs8.as_ref())
+    }
+
+    /// Import an Ed25519 PKCS#8 document, save it to the keystore and return its address
+    pub fn add_pkcs8(&mut self, name: &str, pkcs8: &[u8]) -> Result<Address, WalletError> {
+        let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8)
+            .map_err(|e| WalletError::Format(format!("bad key: {}", e)))?;
+        let address = Address::from_public_key_bytes(key_pair.public_key().as_ref());
+        self.keystore.add(&self.cipher, name, address, pkcs8)?;
+        self.keystore.save(&self.path)?;
+        self.keys.push(WalletKey { name: name.to_string(), address, key_pair });
+        Ok(address)
+    }
+
+    /// Import a raw Ed25519 seed, such as the ones the genesis accounts are made from
+    pub fn add_seed(&mut self, name: &str, seed: &[u8; 32]) -> Result<Address, WalletError> {
+        let key_pair = Ed25519KeyPair::from_seed_unchecked(seed).map_err(|_| WalletError::Crypto)?;
+        // wrap the seed and public key in the PKCS#8 v2 document ring reads
+        let mut pkcs8 = PKCS8_SEED_PREFIX.to_vec();
+        pkcs8.extend_from_slice(seed);
+        pkcs8.extend_from_slice(PKCS8_PUBLIC_KEY_PREFIX);
+        pkcs8.extend_from_slice(key_pair.public_key().as_ref());
+        self.add_pkcs8(name, &pkcs8)
+    }
+
+    /// Write the keystore to its file. Every change is saved as it is made, so this is only needed to be sure.
+    pub fn save(&self) -> Result<(), WalletError> {
+        self.keystore.save(&self.path)
+    }
+
+    /// The addresses of the wallet's keys, with their names, in the order they were added
+    pub fn addresses(&self) -> Vec<(String, Address)> {
+        self.keys.iter().map(|key| (key.name.clone(), key.address)).collect()
+    }
+
+    /// What each of the wallet's keys holds, at the tip of the chain and with the
+    /// transactions waiting in the mempool
+    pub fn accounts(&self, state: &State, mempool: &Mempool) -> Vec<AccountInfo> {
+        self.keys
+            .iter()
+            .map(|key| {
+                let (nonce, balance) = state.map.get(&key.address).copied().unwrap_or((0, 0));
+                let (pending_nonce, pending_balance) = mempool.pending_account(&key.address, (nonce, balance));
+                AccountInfo {
+                    name: key.name.clone(),
+                    address: key.address.to_hex_string(),
+                    nonce,
+                    balance,
+                    pending_nonce,
+                    pending_balance,
+                }
+            })
+            .collect()
+    }
+
+    /// Sign a transaction from one of the wallet's keys with the given nonce
+    pub fn sign(&self, sender: &Address, receiver: Address, value: u128, account_nonce: u128) -> Result<SignedTransaction, WalletError> {
+        let key = self.keys
+            .iter()
+            .find(|key| key.address == *sender)
+            .ok_or(WalletError::UnknownAddress(*sender))?;
+        let transaction = Transaction { account_nonce, receiver, value };
+        let signature = transaction::sign(&transaction, &key.key_pair).as_ref().to_vec();
+        Ok(SignedTransaction {
+            transaction,
+            signature,
+            public_key: key.key_pair.public_key().as_ref().to_vec()
+        })
+    }
+
+    /// Build and sign a payment from one of the wallet's keys at the tip of the chain.
+    /// It follows the sender's transactions already waiting in the mempool, so several
+    /// payments can be made before a block confirms them.
+    pub fn build_transaction(
+        &self,
+        sender: &Address,
+        receiver: Address,
+        value: u128,
+        blockchain: &Blockchain,
+        mempool: &Mempool,
+    ) -> Result<SignedTransaction, WalletError> {
+        let state = blockchain.get_state(&blockchain.tip()).expect("tip has a state");
+        let confirmed = *state.map.get(sender).ok_or(WalletError::UnknownAccount(*sender))?;
+        let (nonce, balance) = mempool.pending_account(sender, confirmed);
+        if value > balance {
+            return Err(WalletError::InsufficientBalance { balance, value });
+        }
+        self.sign(sender, receiver, value, nonce + 1)
+    }
+}
+
+impl fmt::Display for WalletError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WalletError::Io(e) => write!(f, "keystore i/o error: {}", e),
+            WalletError::Format(e) => write!(f, "malformed keystore: {}", e),
+            WalletError::Crypto => write!(f, "cryptographic failure"),
+            WalletError::WrongPassphrase => write!(f, "wrong passphrase"),
+            WalletError::AlreadyExists(path) => write!(f, "keystore {} already exists", path.display()),
+            WalletError::NotFound(path) => {
+                write!(f, "keystore {} does not exist; create it with `bitcoin wallet create`", path.display())
+            }
+            WalletError::UnknownAddress(address) => write!(f, "no key for address {} in the wallet", address),
+            WalletError::UnknownAccount(address) => write!(f, "address {} has no account on chain", address),
+            WalletError::InsufficientBalance { balance, value } => {
+                write!(f, "insufficient balance: {} available, {} needed", balance, value)
+            }
+        }
+    }
+}
+
+impl std::error::Error for WalletError {}
+
+impl From<io::Error> for WalletError {
+    fn from(e: io::Error) -> Self {
+        WalletError::Io(e)
+    }
+}
+
+// DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. BEFORE TEST
+
+#[cfg(test)]
+mod test {
+    use super::{Wallet, WalletError};
+    use crate::blockchain::Blockchain;
+    use crate::types::{
+        address::Address,
+        mempool::Mempool,
+        transaction,
+    };
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+    use std::path::PathBuf;
+
+    // a keystore path that is removed when the test ends
+    struct TempPath(PathBuf);
+
+    impl TempPath {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("bitcoin-wallet-{}-{}.json", name, std::process::id()));
+            let _ = std::fs::remove_file(&path);
+            TempPath(path)
+        }
+    }
+
+    impl Drop for TempPath {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn keystore_round_trip() {
+        let path = TempPath::new("round-trip");
+        let mut wallet = Wallet::create(&path.0, "correct horse").unwrap();
+        let first = wallet.new_key("first").unwrap();
+        let second = wallet.new_key("second").unwrap();
+        assert!(matches!(Wallet::create(&path.0, "correct horse"), Err(WalletError::AlreadyExists(_))));
+        assert!(matches!(Wallet::open(&path.0.with_extension("missing"), "correct horse"), Err(WalletError::NotFound(_))));
+
+        let wallet = Wallet::open(&path.0, "correct horse").unwrap();
+        assert_eq!(wallet.addresses(), vec![("first".to_string(), first), ("second".to_string(), second)]);
+        assert!(matches!(Wallet::open(&path.0, "battery staple"), Err(WalletError::WrongPassphrase)));
+    }
+
+    #[test]
+    fn build_transactions_in_sequence() {
+        let path = TempPath::new("build");
+        let mut wallet = Wallet::create(&path.0, "passphrase").unwrap();
+        // the key funded at genesis
+        let sender = wallet.add_seed("genesis", &[0; 32]).unwrap();
+        let genesis_key = Ed25519KeyPair::from_seed_unchecked(&[0; 32]).unwrap();
+        assert_eq!(sender, Address::from_public_key_bytes(genesis_key.public_key().as_ref()));
+        let receiver = wallet.new_key("receiver").unwrap();
+
+        // the keystore doesn't hold the seed in the clear, but it can be unlocked again
+        let json = std::fs::read_to_string(&path.0).unwrap();
+        assert!(json.contains(&sender.to_hex_string()));
+        assert!(!json.contains(&hex::encode([0u8; 32])));
+        assert_eq!(Wallet::open(&path.0, "passphrase").unwrap().addresses().len(), 2);
+
+        let blockchain = Blockchain::new();
+        let mut mempool = Mempool::new();
+        let first = wallet.build_transaction(&sender, receiver, 6000, &blockchain, &mempool).unwrap();
+        assert_eq!(first.transaction.account_nonce, 1);
+        assert!(transaction::verify(&first.transaction, &first.public_key, &first.signature));
+        mempool.insert(first);
+
+        let second = wallet.build_transaction(&sender, receiver, 4000, &blockchain, &mempool).unwrap();
+        assert_eq!(second.transaction.account_nonce, 2);
+        assert!(matches!(
+            wallet.build_transaction(&sender, receiver, 4001, &blockchain, &mempool),
+            Err(WalletError::InsufficientBalance { balance: 4000, value: 4001 })
+        ));
+        assert!(matches!(
+            wallet.build_transaction(&receiver, sender, 1, &blockchain, &mempool),
+            Err(WalletError::UnknownAccount(_))
+        ));
+        let stranger = Address::from_public_key_bytes(&[9; 32]);
+        assert!(matches!(
+            wallet.sign(&stranger, sender, 1, 1),
+            Err(WalletError::UnknownAddress(_))
+        ));
+    }
+}
+
+// DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. AFTER TEST